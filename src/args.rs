@@ -1,81 +1,251 @@
 extern crate getopts;
 
+use crate::cmdline::CmdlineFormat;
+use crate::status::OutputFormat;
 use getopts::Options;
 use log::LevelFilter;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
 
-#[allow(dead_code)]
-#[derive(Debug)]
+const DEFAULT_SHUTDOWN_TIMEOUT: u64 = 60;
+
+const COMMANDS: &str = r#"
+Commands:
+    start <name>           start a virtual machine
+    stop <name>            shutdown a virtual machine
+    hibernate <name>       save the state of a virtual machine to disk, and stop it
+    status <name>          show the status of a virtual machine
+    list                   show the status of all virtual machines
+    validate <name>        check a config file, without starting anything
+    show-cmdline <name>    print the command lines that start would run
+    console <name>         open the display of a running virtual machine
+    help                   print this usage message
+
+With --config, <name> may be left out, it then defaults to the name of the config file."#;
+
+#[derive(Debug, PartialEq)]
 pub enum EzkvmCommand {
     Help,
     Start { name: String },
-    Stop { name: String },
+    Stop { name: String, timeout: Duration },
     Hibernate { name: String },
+    Status { name: String, format: OutputFormat },
+    List { format: OutputFormat },
+    Validate { name: String },
+    ShowCmdline { name: String, format: CmdlineFormat },
+    Console { name: String },
 }
 
 pub struct EzkvmArguments {
     pub program: String,
     pub command: EzkvmCommand,
-    pub opts: Options,
     pub log_level: LevelFilter,
+    pub config: Option<PathBuf>,
 }
 
 impl EzkvmArguments {
-    pub fn new(args: Vec<String>) -> Self {
-        let mut command = EzkvmCommand::Help;
-        let program = args[0].to_string();
-
+    fn options() -> Options {
         let mut opts = Options::new();
-        opts.optopt("", "help", "print usage message", "");
-        opts.optopt("", "start", "start a virtual machine by name", "");
-        opts.optopt("", "shutdown", "shutdown a virtual machine by name", "");
-        opts.optopt("", "hibernate", "hibernate a virtual machine by name", "");
-
-        let matches = match opts.parse(&args[1..]) {
-            Ok(m) => m,
-            Err(f) => {
-                panic!("{}", f.to_string())
-            }
-        };
+        opts.optflag("h", "help", "print this usage message");
+        opts.optflagmulti(
+            "v",
+            "verbose",
+            "log more details, repeat for even more (-vv, -vvv)",
+        );
+        opts.optflag("q", "quiet", "do not log anything, only report errors");
+        opts.optopt(
+            "c",
+            "config",
+            "use this config file, instead of searching for <name>.yaml",
+            "PATH",
+        );
+        opts.optopt(
+            "f",
+            "format",
+            "output format for status and list: text, yaml or json (default: text), for show-cmdline: shell or lines (default: shell)",
+            "FORMAT",
+        );
+        opts.optopt(
+            "t",
+            "timeout",
+            "seconds to wait for the guest to shutdown before forcing qemu to quit (default: 60)",
+            "SECONDS",
+        );
+        opts.optflag(
+            "n",
+            "dry-run",
+            "with start, print the command lines instead of starting",
+        );
+        opts
+    }
 
-        if matches.opt_present("start") {
-            match matches.opt_str("start") {
-                None => {}
-                Some(name) => command = EzkvmCommand::Start { name },
-            }
-        }
+    /// parses the command line, an error describes what is wrong with it
+    pub fn new(args: Vec<String>) -> Result<Self, String> {
+        let program = args
+            .first()
+            .map(|program| program.to_string())
+            .unwrap_or("ezkvm".to_string());
+        let matches = Self::options()
+            .parse(args.iter().skip(1))
+            .map_err(|error| error.to_string())?;
 
-        if matches.opt_present("shutdown") {
-            match matches.opt_str("shutdown") {
-                None => {}
-                Some(name) => command = EzkvmCommand::Stop { name },
-            }
-        }
+        let log_level = match (matches.opt_present("quiet"), matches.opt_count("verbose")) {
+            (true, _) => LevelFilter::Off,
+            (false, 0) => LevelFilter::Warn,
+            (false, 1) => LevelFilter::Info,
+            (false, 2) => LevelFilter::Debug,
+            (false, _) => LevelFilter::Trace,
+        };
 
-        if matches.opt_present("hibernate") {
-            match matches.opt_str("hibernate") {
-                None => {}
-                Some(name) => command = EzkvmCommand::Hibernate { name },
-            }
-        }
+        let config = matches.opt_str("config").map(PathBuf::from);
+        let format = matches.opt_str("format");
+        let timeout = match matches.opt_str("timeout") {
+            None => DEFAULT_SHUTDOWN_TIMEOUT,
+            Some(timeout) => timeout
+                .parse()
+                .map_err(|_| format!("invalid timeout '{}'", timeout))?,
+        };
 
-        if matches.opt_present("help") {
-            match matches.opt_str("help") {
-                None => {}
-                Some(_name) => command = EzkvmCommand::Help,
-            }
+        let mut free = matches.free.iter();
+        let command = free.next().map(String::as_str);
+        let name = free.next().cloned().or(config.as_deref().and_then(name_of));
+        if let Some(extra) = free.next() {
+            return Err(format!("unexpected argument '{}'", extra));
         }
+        let name = || {
+            name.clone()
+                .ok_or("missing the name of the virtual machine".to_string())
+        };
 
-        let log_level = LevelFilter::Off;
-        EzkvmArguments {
+        let command = match command {
+            _ if matches.opt_present("help") => EzkvmCommand::Help,
+            None | Some("help") => EzkvmCommand::Help,
+            Some("start") if matches.opt_present("dry-run") => EzkvmCommand::ShowCmdline {
+                name: name()?,
+                format: parse_format(format)?,
+            },
+            Some("start") => EzkvmCommand::Start { name: name()? },
+            Some("stop") => EzkvmCommand::Stop {
+                name: name()?,
+                timeout: Duration::from_secs(timeout),
+            },
+            Some("hibernate") => EzkvmCommand::Hibernate { name: name()? },
+            Some("status") => EzkvmCommand::Status {
+                name: name()?,
+                format: parse_format(format)?,
+            },
+            Some("list") => EzkvmCommand::List {
+                format: parse_format(format)?,
+            },
+            Some("validate") => EzkvmCommand::Validate { name: name()? },
+            Some("show-cmdline") => EzkvmCommand::ShowCmdline {
+                name: name()?,
+                format: parse_format(format)?,
+            },
+            Some("console") => EzkvmCommand::Console { name: name()? },
+            Some(other) => return Err(format!("unknown command '{}'", other)),
+        };
+
+        Ok(EzkvmArguments {
             program,
             command,
-            opts,
             log_level,
-        }
+            config,
+        })
+    }
+
+    pub fn usage(program: &str) -> String {
+        let brief = format!("Usage: {} [options] <command> [<name>]", program);
+        format!("{}{}\n", Self::options().usage(&brief), COMMANDS)
     }
 
     pub fn print_usage(&self) {
-        let brief = format!("Usage: {} [options]", self.program);
-        print!("{}", self.opts.usage(&brief));
+        print!("{}", Self::usage(&self.program));
+    }
+}
+
+fn name_of(path: &Path) -> Option<String> {
+    path.file_stem()
+        .and_then(|stem| stem.to_str())
+        .map(str::to_string)
+}
+
+fn parse_format<T: FromStr<Err = String> + Default>(format: Option<String>) -> Result<T, String> {
+    match format {
+        None => Ok(T::default()),
+        Some(format) => format.parse(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &str) -> Result<EzkvmArguments, String> {
+        EzkvmArguments::new(args.split_whitespace().map(str::to_string).collect())
+    }
+
+    #[test]
+    fn test_commands() {
+        assert_eq!(
+            parse("ezkvm start wakiza").unwrap().command,
+            EzkvmCommand::Start {
+                name: "wakiza".to_string()
+            }
+        );
+        assert_eq!(
+            parse("ezkvm -t 30 stop wakiza").unwrap().command,
+            EzkvmCommand::Stop {
+                name: "wakiza".to_string(),
+                timeout: Duration::from_secs(30)
+            }
+        );
+        assert_eq!(
+            parse("ezkvm list --format json").unwrap().command,
+            EzkvmCommand::List {
+                format: OutputFormat::Json
+            }
+        );
+        assert_eq!(
+            parse("ezkvm start wakiza --dry-run -f lines")
+                .unwrap()
+                .command,
+            EzkvmCommand::ShowCmdline {
+                name: "wakiza".to_string(),
+                format: CmdlineFormat::Lines
+            }
+        );
+        assert_eq!(parse("ezkvm").unwrap().command, EzkvmCommand::Help);
+        assert_eq!(
+            parse("ezkvm --help stop").unwrap().command,
+            EzkvmCommand::Help
+        );
+    }
+
+    #[test]
+    fn test_config_and_verbosity() {
+        let args = parse("ezkvm -vv --config /tmp/vms/wakiza.yaml validate").unwrap();
+        assert_eq!(
+            args.command,
+            EzkvmCommand::Validate {
+                name: "wakiza".to_string()
+            }
+        );
+        assert_eq!(args.config, Some(PathBuf::from("/tmp/vms/wakiza.yaml")));
+        assert_eq!(args.log_level, LevelFilter::Debug);
+
+        assert_eq!(parse("ezkvm list").unwrap().log_level, LevelFilter::Warn);
+        assert_eq!(parse("ezkvm -q list").unwrap().log_level, LevelFilter::Off);
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(parse("ezkvm start").is_err());
+        assert!(parse("ezkvm reboot wakiza").is_err());
+        assert!(parse("ezkvm start wakiza mygeeto").is_err());
+        assert!(parse("ezkvm --bogus list").is_err());
+        assert!(parse("ezkvm status wakiza --format xml").is_err());
+        assert!(parse("ezkvm stop wakiza --timeout soon").is_err());
     }
 }
@@ -0,0 +1,93 @@
+use std::str::FromStr;
+
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub enum CmdlineFormat {
+    /// one command per line, quoted so it can be pasted into a shell
+    #[default]
+    Shell,
+    /// one argument per line, commands separated by an empty line
+    Lines,
+}
+
+impl FromStr for CmdlineFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "shell" => Ok(CmdlineFormat::Shell),
+            "lines" => Ok(CmdlineFormat::Lines),
+            other => Err(format!("unknown command line format '{}'", other)),
+        }
+    }
+}
+
+/// quotes an argument for a posix shell, arguments without special characters are kept as is
+fn shell_quote(arg: &str) -> String {
+    let is_plain = |c: char| c.is_ascii_alphanumeric() || "_-+=:,./@%".contains(c);
+    if !arg.is_empty() && arg.chars().all(is_plain) {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
+/// formats a list of commands, each given as its program followed by the arguments
+pub fn format_commands(commands: &[Vec<String>], format: CmdlineFormat) -> String {
+    match format {
+        CmdlineFormat::Shell => commands
+            .iter()
+            .map(|command| {
+                let quoted: Vec<String> = command.iter().map(|arg| shell_quote(arg)).collect();
+                quoted.join(" ") + "\n"
+            })
+            .collect(),
+        CmdlineFormat::Lines => commands
+            .iter()
+            .map(|command| command.join("\n") + "\n")
+            .collect::<Vec<String>>()
+            .join("\n"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn commands() -> Vec<Vec<String>> {
+        vec![
+            vec![
+                "qemu-system-x86_64".to_string(),
+                "-drive".to_string(),
+                "file=/vms/my disk,,1.img,if=none".to_string(),
+                "-nodefaults".to_string(),
+            ],
+            vec![
+                "remote-viewer".to_string(),
+                "spice://127.0.0.1:5900".to_string(),
+            ],
+        ]
+    }
+
+    #[test]
+    fn test_shell_format() {
+        assert_eq!(
+            format_commands(&commands(), CmdlineFormat::Shell),
+            "qemu-system-x86_64 -drive 'file=/vms/my disk,,1.img,if=none' -nodefaults\n\
+             remote-viewer spice://127.0.0.1:5900\n"
+        );
+        assert_eq!(shell_quote("it's"), "'it'\\''s'");
+        assert_eq!(shell_quote(""), "''");
+    }
+
+    #[test]
+    fn test_lines_format() {
+        assert_eq!(
+            format_commands(&commands(), CmdlineFormat::Lines),
+            "qemu-system-x86_64\n-drive\nfile=/vms/my disk,,1.img,if=none\n-nodefaults\n\
+             \n\
+             remote-viewer\nspice://127.0.0.1:5900\n"
+        );
+        assert_eq!("lines".parse(), Ok(CmdlineFormat::Lines));
+        assert!("json".parse::<CmdlineFormat>().is_err());
+    }
+}
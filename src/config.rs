@@ -1,34 +1,48 @@
+mod config_error;
 mod display;
 mod general;
 mod gpu;
 mod host;
 mod network;
+mod pci_allocator;
+mod resources;
 mod spice;
 mod storage;
+mod strict;
 mod system;
 mod types;
+mod validation;
 
 use crate::config::display::Display;
 use crate::config::gpu::Gpu;
 use derive_getters::Getters;
-use log::debug;
+use log::{debug, warn};
 use serde::{Deserialize, Deserializer};
-use std::any::{Any, TypeId};
+use std::any::TypeId;
 use std::ops::Deref;
+use std::path::PathBuf;
 
 use crate::config::network::NetworkItem;
+use crate::config::pci_allocator::PciAllocator;
 use crate::config::storage::StorageItem;
 #[mockall_double::double]
 use crate::osal::Osal;
 use crate::osal::OsalError;
+use crate::resource::data_manager::DataManager;
+use crate::resource::lock::Lock;
+use crate::resource::resource::Resource;
+pub use config_error::ConfigError;
 pub use display::Gtk;
 pub use general::General;
 pub use host::Host;
+pub use resources::ResourceRequest;
 pub use spice::Spice;
-pub use system::System;
+pub use system::{installed_qemu_version, System};
 pub use types::Pci;
 pub use types::QemuDevice;
 pub use types::Usb;
+pub use types::{PciAddress, PciSlot, QemuArgument};
+pub use validation::{within, Finding, Validation};
 
 #[macro_export]
 macro_rules! optional_value_getter {
@@ -38,7 +52,11 @@ macro_rules! optional_value_getter {
             match &self.$id {
                 None => "".to_string(),
                 Some(option) => {
-                    format!(",{}={}", $lit, option.clone())
+                    format!(
+                        ",{}={}",
+                        $lit,
+                        $crate::config::QemuArgument::escape(&option.to_string())
+                    )
                 }
             }
         }
@@ -51,7 +69,7 @@ macro_rules! required_value_getter {
         paste! {
             #[allow(dead_code)]
             pub fn $id(&self) -> String {
-                format!(",{}={}", $lit, self.$id)
+                format!(",{}={}", $lit, $crate::config::QemuArgument::escape(&self.$id.to_string()))
             }
             #[allow(dead_code)]
             pub fn [<$id _default>]() -> $ty {
@@ -61,8 +79,18 @@ macro_rules! required_value_getter {
     };
 }
 
+/// where vm config files are searched for, in order of precedence
+const CONFIG_LOCATIONS: [&str; 4] = [".", "$XDG_CONFIG_HOME/ezkvm", "~/.ezkvm", "/etc/ezkvm"];
+/// where vm config files are listed from; the current directory is left out, it is only
+/// searched when a vm is looked up by name, since any other yaml file may be there as well
+const LIST_LOCATIONS: [&str; 3] = ["$XDG_CONFIG_HOME/ezkvm", "~/.ezkvm", "/etc/ezkvm"];
+
 #[derive(Deserialize, Debug, Default, Getters)]
+#[serde(deny_unknown_fields)]
 pub struct Config {
+    /// unknown keys are errors, unless this is set to false
+    #[serde(default = "Config::strict_default")]
+    strict: bool,
     #[serde(default, deserialize_with = "default_when_missing")]
     general: General,
     #[serde(default, deserialize_with = "default_when_missing")]
@@ -81,9 +109,17 @@ pub struct Config {
     network: Vec<NetworkItem>,
     #[serde(default, deserialize_with = "default_when_missing")]
     extras: Vec<String>,
+    #[serde(default)]
+    resources: Vec<ResourceRequest>,
+    #[serde(skip)]
+    claimed_resources: Vec<Resource>,
 }
 
 impl Config {
+    fn strict_default() -> bool {
+        true
+    }
+
     pub fn with_general(self, general: General) -> Config {
         Config { general, ..self }
     }
@@ -120,23 +156,242 @@ impl Config {
         Config { extras, ..self }
     }
 
-    pub fn read<S>(name: S) -> Option<Self>
+    pub fn with_resources(self, resources: Vec<ResourceRequest>) -> Config {
+        Config { resources, ..self }
+    }
+
+    /// the config file for a vm; a name that occurs in more than one location is an error,
+    /// as it is not obvious which of them would be started
+    pub fn find<S>(name: S) -> Result<PathBuf, OsalError>
+    where
+        S: AsRef<str>,
+    {
+        let name = name.as_ref();
+        let candidates = Osal::find_files(format!("{}.yaml", name), CONFIG_LOCATIONS.to_vec());
+
+        match candidates.as_slice() {
+            [] => Err(OsalError::OpenError(Some(format!(
+                "{}.yaml, it is not in any of {}",
+                name,
+                CONFIG_LOCATIONS.join(", ")
+            )))),
+            [candidate] => Ok(candidate.clone()),
+            _ => Err(OsalError::OpenError(Some(format!(
+                "{}.yaml, there is more than one: {}",
+                name,
+                candidates
+                    .iter()
+                    .map(|candidate| candidate.display().to_string())
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            )))),
+        }
+    }
+
+    pub fn read<S>(name: S) -> Result<Self, OsalError>
     where
         S: AsRef<str>,
     {
-        let name = format!("{}.yaml", name.as_ref());
-        let candidates = Osal::find_files(name, vec![".", "~/.ezkvm", "/etc/ezkvm"]);
+        Self::read_file(Self::find(name)?)
+    }
+
+    pub fn read_file(path: PathBuf) -> Result<Self, OsalError> {
+        debug!("Config::read_file({})", path.display());
+        let content = Osal::read_file(path.clone())?;
+        match strict::is_strict(&content) {
+            true => ConfigError::parse(path, &content),
+            false => ConfigError::parse_lenient(path, &content),
+        }
+        .map_err(OsalError::Config)
+    }
+
+    /// all vm config files in the search path, except the current directory, as (name, path)
+    /// pairs; when a name occurs in more than one location, only the first one is returned
+    pub fn list() -> Vec<(String, PathBuf)> {
+        let mut result: Vec<(String, PathBuf)> = vec![];
 
-        if let Some(candidate) = candidates.get(0) {
-            if let Ok(config) = Osal::read_yaml_file(candidate.clone()) {
-                return Some(config);
+        for path in Osal::find_files("*.yaml", LIST_LOCATIONS.to_vec()) {
+            if let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) {
+                if !result.iter().any(|(known, _)| known == name) {
+                    result.push((name.to_string(), path.clone()));
+                }
             }
         }
 
-        None
+        result
     }
-    pub(crate) fn allocate_resources(&self) -> Result<Vec<String>, OsalError> {
-        Ok(vec![])
+
+    /// claim a device for every resource request, returns the ids to record in the lock
+    pub(crate) fn allocate_resources(&mut self) -> Result<Vec<String>, OsalError> {
+        let history_file = self.general.resource_history_file();
+        let history: Vec<String> = Osal::read_yaml_file(history_file.clone()).unwrap_or_default();
+
+        let data_manager = DataManager::instance();
+        let mut data_manager = data_manager.lock().unwrap();
+        let ids = self.claim_resources(&mut data_manager, &history)?;
+
+        if !ids.is_empty() {
+            let content = serde_yaml::to_string(&ids).unwrap_or_default();
+            if let Err(error) = Osal::write_file(history_file, content) {
+                warn!("allocate_resources(): unable to save history: {:?}", error);
+            }
+        }
+        Ok(ids)
+    }
+
+    /// like allocate_resources, but against a copy of the current claims, so nothing is claimed;
+    /// the claims of the vm itself are left out, so a running vm previews what it would get
+    pub(crate) fn preview_resources(&mut self, name: &str) -> Result<Vec<String>, OsalError> {
+        let history_file = self.general.resource_history_file();
+        let history: Vec<String> = Osal::read_yaml_file(history_file).unwrap_or_default();
+
+        let mut data_manager = DataManager::instance().lock().unwrap().clone();
+        data_manager.release(name);
+        self.claim_resources(&mut data_manager, &history)
+    }
+
+    fn claim_resources(
+        &mut self,
+        data_manager: &mut DataManager,
+        history: &[String],
+    ) -> Result<Vec<String>, OsalError> {
+        let mut claimed_resources = vec![];
+        for request in &self.resources {
+            let preferred = match request.sticky() {
+                true => history,
+                false => &[],
+            };
+            claimed_resources.push(data_manager.claim_resource(
+                request.pool().as_ref(),
+                request.tags().as_ref(),
+                preferred,
+            )?);
+        }
+
+        self.claimed_resources = claimed_resources;
+        Ok(self
+            .claimed_resources
+            .iter()
+            .map(|resource| resource.get_id())
+            .collect())
+    }
+
+    /// look up the firmware files on the host, like the ovmf firmware of the distro
+    pub(crate) fn find_firmware(&mut self) {
+        self.system.find_firmware();
+    }
+
+    /// let the helper processes of the vm use the runtime directory of the vm
+    pub(crate) fn use_runtime_dir(&mut self) {
+        let runtime_dir = self.general.runtime_dir();
+        self.system.use_runtime_dir(&runtime_dir);
+    }
+
+    /// give every pci device an address, devices with a pinned address keep theirs; devices
+    /// first adapt to the chipset, since the buses and controllers differ between chipsets
+    pub(crate) fn allocate_pci_addresses(&mut self) -> Result<(), OsalError> {
+        let chipset = self.system.chipset().as_ref();
+        self.display.use_chipset(chipset);
+        self.gpu.use_chipset(chipset);
+        if let Some(spice) = &mut self.spice {
+            spice.use_chipset(chipset);
+        }
+        for storage in &mut self.storage {
+            storage.use_chipset(chipset);
+        }
+        for network in &mut self.network {
+            network.use_chipset(chipset);
+        }
+        let mut allocator = PciAllocator::new(chipset.get_pci_buses());
+
+        let mut slots = vec![];
+        slots.extend(self.display.get_pci_slots());
+        slots.extend(self.gpu.get_pci_slots());
+        if let Some(spice) = &mut self.spice {
+            slots.extend(spice.get_pci_slots());
+        }
+        if let Some(host) = &mut self.host {
+            slots.extend(host.get_pci_slots());
+        }
+        for storage in &mut self.storage {
+            slots.extend(storage.get_pci_slots());
+        }
+        for network in &mut self.network {
+            slots.extend(network.get_pci_slots());
+        }
+        for resource in &mut self.claimed_resources {
+            slots.extend(resource.get_pci_slots());
+        }
+
+        allocator.assign(slots)
+    }
+
+    /// the qemu command line, program first
+    pub fn get_qemu_command(&self) -> Vec<String> {
+        let mut result = vec!["qemu-system-x86_64".to_string()];
+        result.extend(QemuArgument::flatten(&self.get_qemu_args(0)));
+        result
+    }
+
+    /// whether the display is a T, like LookingGlass
+    pub fn has_display<T: 'static>(&self) -> bool {
+        let display: &dyn Display = self.display().deref();
+        (*display).type_id() == TypeId::of::<T>()
+    }
+
+    /// whether the gpu is a T, like PassthroughGpu
+    pub fn has_gpu<T: 'static>(&self) -> bool {
+        let gpu: &dyn Gpu = self.gpu().deref();
+        (*gpu).type_id() == TypeId::of::<T>()
+    }
+
+    /// all problems in this config, including mac addresses that other vms use as well
+    pub fn validation(&self, name: &str) -> Validation {
+        let mut findings = self.validate(self);
+
+        for (other, path) in Config::list() {
+            if other == name {
+                continue;
+            }
+            // a broken config of another vm is reported when that vm is validated
+            let Ok(config) = Config::read_file(path) else {
+                continue;
+            };
+            for (index, mac) in self.get_macs() {
+                if config
+                    .get_macs()
+                    .iter()
+                    .any(|(_, other_mac)| *other_mac == mac)
+                {
+                    findings.push(Finding::error(
+                        &format!("network[{}].mac", index),
+                        format!("{} is also used by vm {}", mac, other),
+                    ));
+                }
+            }
+        }
+        if let Some(resource) = self.system.tpm().get_host_resource() {
+            if let Some(holder) = Lock::find_holder(&resource, name) {
+                findings.push(Finding::error(
+                    "system.tpm",
+                    format!("the tpm of the host is in use by vm {}", holder.name()),
+                ));
+            }
+        }
+
+        Validation::new(findings)
+    }
+
+    /// the explicitly configured mac addresses, by network index
+    fn get_macs(&self) -> Vec<(usize, String)> {
+        self.network
+            .iter()
+            .enumerate()
+            .filter_map(|(index, network)| {
+                let mac = network.footer().configured_mac()?;
+                Some((index, mac.to_uppercase()))
+            })
+            .collect()
     }
 
     fn has_gtk_display_configured(&self) -> bool {
@@ -144,19 +399,17 @@ impl Config {
     }
 
     fn get_gtk_display(&self) -> Option<Gtk> {
-        let display = self.display();
+        let display: &dyn Display = self.display().deref();
 
-        // make sure display contains an &Box<Gtk> instance
-        if (*display).type_id() == TypeId::of::<Box<dyn Display>>()
-            && (*display.deref()).type_id() == TypeId::of::<Gtk>()
-        {
-            // since we now know display is an &Box<Gtk>, we can do a cast to it
-            let t = unsafe { &*(display as *const dyn Any as *const Box<Gtk>) };
+        // make sure display contains a Gtk instance
+        if (*display).type_id() == TypeId::of::<Gtk>() {
+            // since we now know display is a Gtk, we can do a cast to it
+            let t = unsafe { &*(display as *const dyn Display as *const Gtk) };
 
             // and return a clone wrapped in an Option<>
-            Some(t.deref().clone())
+            Some(t.clone())
         } else {
-            // display is not an &Box<Gtk>, so return None
+            // display is not a Gtk, so return None
             None
         }
     }
@@ -182,10 +435,13 @@ impl Config {
 }
 
 impl QemuDevice for Config {
-    fn get_qemu_args(&self, _index: usize) -> Vec<String> {
+    fn get_qemu_args(&self, _index: usize) -> Vec<QemuArgument> {
         let mut result = vec![];
         result.extend(self.general.get_qemu_args(0));
         result.extend(self.system.get_qemu_args(0));
+        if self.system.chipset().has_serial_console() {
+            result.extend(self.general.get_serial_console_args());
+        }
         result.extend(self.display.get_qemu_args(0));
         result.extend(self.gpu.get_qemu_args(0));
 
@@ -211,17 +467,134 @@ impl QemuDevice for Config {
             result.extend(network.get_qemu_args(i));
         }
 
+        for (i, resource) in self.claimed_resources.iter().enumerate() {
+            result.extend(resource.get_qemu_args(i));
+        }
+
+        result
+    }
+
+    fn get_helper_commands(&self, config: &Config) -> Vec<Vec<String>> {
+        let mut result = vec![];
+        result.extend(self.system.get_helper_commands(config));
+        result.extend(self.display.get_helper_commands(config));
+        result
+    }
+
+    fn validate(&self, config: &Config) -> Vec<Finding> {
+        let mut result = vec![];
+        result.extend(within("system", self.system.validate(config)));
+        result.extend(within("display", self.display.validate(config)));
+        result.extend(within("gpu", self.gpu.validate(config)));
+        if let Some(spice) = self.spice() {
+            result.extend(within("spice", spice.validate(config)));
+        }
+        if let Some(host) = self.host() {
+            result.extend(within("host", host.validate(config)));
+        }
+
+        let mut boot_indexes: Vec<(u8, usize)> = vec![];
+        for (i, disk) in self.storage.iter().enumerate() {
+            let path = format!("storage[{}]", i);
+            result.extend(within(&path, disk.validate(config)));
+
+            let ide_buses = self.system.chipset().get_ide_buses();
+            match disk.payload().get_ide_bus(i) {
+                Some(_) if ide_buses == 0 => {
+                    result.push(Finding::error(&path, "the chipset has no ide buses"))
+                }
+                Some(bus) if bus >= ide_buses => result.push(Finding::error(
+                    &path,
+                    format!(
+                        "ide.{} does not exist, the chipset only has ide.0 to ide.{}; \
+                         list the ide-cd drives first",
+                        bus,
+                        ide_buses - 1
+                    ),
+                )),
+                _ => {}
+            }
+
+            if let Some(boot_index) = *disk.footer().boot_index() {
+                match boot_indexes.iter().find(|(known, _)| *known == boot_index) {
+                    Some((_, first)) => result.push(Finding::error(
+                        &format!("{}.boot_index", path),
+                        format!(
+                            "boot index {} is also used by storage[{}]",
+                            boot_index, first
+                        ),
+                    )),
+                    None => boot_indexes.push((boot_index, i)),
+                }
+            }
+        }
+
+        // claimed devices are passed through with vfio-pci
+        if !self.system.chipset().has_pci_bus() {
+            for i in 0..self.resources.len() {
+                result.push(Finding::error(
+                    &format!("resources[{}]", i),
+                    "the chipset has no pci bus to pass a claimed device through",
+                ));
+            }
+        }
+
+        let macs = self.get_macs();
+        for (i, mac) in &macs {
+            if let Some((first, _)) = macs
+                .iter()
+                .find(|(other, other_mac)| other < i && other_mac == mac)
+            {
+                result.push(Finding::error(
+                    &format!("network[{}].mac", i),
+                    format!("{} is also used by network[{}]", mac, first),
+                ));
+            }
+        }
+
         result
     }
 
-    fn pre_start(&self, config: &Config) {
-        self.system.pre_start(config);
+    fn pre_start(&self, config: &Config) -> Result<(), OsalError> {
+        self.system.pre_start(config)
     }
 
     fn post_start(&self, config: &Config) {
         self.system.post_start(config);
         self.display.post_start(config);
     }
+
+    fn pre_stop(&self, config: &Config) {
+        self.system.pre_stop(config);
+        self.display.pre_stop(config);
+        for network in &self.network {
+            network.pre_stop(config);
+        }
+    }
+
+    fn post_stop(&self, config: &Config) {
+        self.system.post_stop(config);
+        self.display.post_stop(config);
+        for network in &self.network {
+            network.post_stop(config);
+        }
+    }
+
+    fn pre_hibernate(&self, config: &Config) {
+        self.system.pre_hibernate(config);
+        self.display.pre_hibernate(config);
+        for network in &self.network {
+            network.pre_hibernate(config);
+        }
+    }
+
+    fn post_hibernate(&self, config: &Config) {
+        self.system.post_hibernate(config);
+        self.display.post_hibernate(config);
+        for network in &self.network {
+            network.post_hibernate(config);
+        }
+    }
 }
 
 pub fn default_when_missing<'de, D, T>(deserializer: D) -> Result<T, D::Error>
@@ -233,44 +606,101 @@ where
     Ok(option.unwrap_or_default())
 }
 
+/// helper function to compare argument lists independent of order
+#[allow(unused)]
+pub fn assert_argument_lists_are_equal(
+    mut actual: Vec<QemuArgument>,
+    mut expected: Vec<QemuArgument>,
+) {
+    assert_eq!(actual.len(), expected.len());
+    actual.sort();
+    expected.sort();
+    let mut count = 0;
+    while count < actual.len() {
+        assert_eq!(actual[count], expected[count]);
+        count += 1;
+    }
+}
+
+/// helper function to compare argument options independent of order
+#[allow(unused)]
+pub fn assert_arguments_are_equal(actual: &QemuArgument, expected: &QemuArgument) {
+    // the flag itself must match
+    assert_eq!(actual.get_flag(), expected.get_flag());
+
+    // if there are options, then split them
+    match (actual.get_value(), expected.get_value()) {
+        (Some(actual), Some(expected)) => {
+            let mut actual_split: Vec<&str> = actual.split(',').collect();
+            let mut expected_split: Vec<&str> = expected.split(',').collect();
+
+            // for the first option in the arguments, order may still be relevant, so compare those first, and the rest after sorting
+            assert_eq!(actual_split.first(), expected_split.first());
+            actual_split.sort();
+            expected_split.sort();
+            assert_eq!(actual_split, expected_split);
+        }
+        (actual, expected) => assert_eq!(actual, expected),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::config::display::NoDisplay;
-    use crate::config::gpu::NoGpu;
+    use crate::resource::lock::Lock;
+    use crate::resource::resource_pool::ResourcePool;
     use serial_test::serial;
-    use std::path::PathBuf;
+    use std::collections::HashMap;
 
     #[test]
+    #[serial]
     fn test_empty_config() {
+        let get_machine_types = Osal::get_machine_types_context();
+        get_machine_types
+            .expect()
+            .returning(|_| vec!["pc-q35-8.1".to_string()]);
         let config: Config = serde_yaml::from_str(
             r#"
               "#,
         )
         .unwrap();
 
-        let tmp = config.get_qemu_args(0);
-        let actual: Vec<&str> = tmp.iter().map(std::ops::Deref::deref).collect();
-        let expected: Vec<&str> = vec![
-            "-accel kvm",
-            "-nodefaults",
-            "-monitor unix:/var/ezkvm/anonymous.monitor,server,nowait",
-            "-chardev socket,id=qmp,path=/var/ezkvm/anonymous.qmp,server=on,wait=off",
-            "-mon chardev=qmp,mode=control",
-            "-chardev socket,id=qmp-event,path=/var/run/qmeventd.sock,reconnect=5",
-            "-mon chardev=qmp-event,mode=control",
-            "-machine hpet=off,type=pc-q35-8.1",
-            "-rtc driftfix=slew,base=localtime",
-            "-global kvm-pit.lost_tick_policy=discard",
-            "-readconfig /usr/share/ezkvm/pve-q35-4.0.cfg",
-            "-device qemu-xhci,p2=15,p3=15,id=xhci,bus=pci.1,addr=0x1b",
-            "-iscsi initiator-name=iqn.1993-08.org.debian:01:39407ad058b",
-            "-device pvscsi,id=scsihw0,bus=pci.0,addr=0x5",
-            "-boot menu=on,strict=on,reboot-timeout=1000,splash=/usr/share/ezkvm/bootsplash.jpg",
-            "-smbios type=1,uuid=",
-            "-m 16384",
-            "-smp 4,sockets=1,cores=4,maxcpus=4",
-            "-cpu qemu64,+aes,+pni,+popcnt,+sse4.1,+sse4.2,+ssse3,enforce",
+        let actual = config.get_qemu_args(0);
+        let expected: Vec<QemuArgument> = vec![
+            QemuArgument::new("-accel", "kvm"),
+            QemuArgument::flag("-nodefaults"),
+            QemuArgument::new("-monitor", "unix:/var/ezkvm/anonymous.monitor,server,nowait"),
+            QemuArgument::new("-chardev", "socket,id=qmp,path=/var/ezkvm/anonymous.qmp,server=on,wait=off"),
+            QemuArgument::new("-mon", "chardev=qmp,mode=control"),
+            QemuArgument::new("-chardev", "socket,id=qmp-event,path=/var/run/qmeventd.sock,reconnect=5"),
+            QemuArgument::new("-mon", "chardev=qmp-event,mode=control"),
+            QemuArgument::new("-machine", "hpet=off,type=pc-q35-8.1"),
+            QemuArgument::new("-rtc", "driftfix=slew,base=localtime"),
+            QemuArgument::new("-global", "kvm-pit.lost_tick_policy=discard"),
+            QemuArgument::new("-device", "ich9-usb-ehci1,id=ehci,multifunction=on,bus=pcie.0,addr=0x1d.0x7"),
+            QemuArgument::new("-device", "ich9-usb-uhci1,id=uhci-1,multifunction=on,bus=pcie.0,addr=0x1d.0x0,masterbus=ehci.0,firstport=0"),
+            QemuArgument::new("-device", "ich9-usb-uhci2,id=uhci-2,multifunction=on,bus=pcie.0,addr=0x1d.0x1,masterbus=ehci.0,firstport=2"),
+            QemuArgument::new("-device", "ich9-usb-uhci3,id=uhci-3,multifunction=on,bus=pcie.0,addr=0x1d.0x2,masterbus=ehci.0,firstport=4"),
+            QemuArgument::new("-device", "ich9-usb-ehci2,id=ehci-2,multifunction=on,bus=pcie.0,addr=0x1a.0x7"),
+            QemuArgument::new("-device", "ich9-usb-uhci4,id=uhci-4,multifunction=on,bus=pcie.0,addr=0x1a.0x0,masterbus=ehci-2.0,firstport=0"),
+            QemuArgument::new("-device", "ich9-usb-uhci5,id=uhci-5,multifunction=on,bus=pcie.0,addr=0x1a.0x1,masterbus=ehci-2.0,firstport=2"),
+            QemuArgument::new("-device", "ich9-usb-uhci6,id=uhci-6,multifunction=on,bus=pcie.0,addr=0x1a.0x2,masterbus=ehci-2.0,firstport=4"),
+            QemuArgument::new("-device", "pcie-root-port,id=ich9-pcie-port-1,multifunction=on,bus=pcie.0,addr=0x1c.0x0,port=1,chassis=1,x-speed=16,x-width=32"),
+            QemuArgument::new("-device", "pcie-root-port,id=ich9-pcie-port-2,multifunction=on,bus=pcie.0,addr=0x1c.0x1,port=2,chassis=2,x-speed=16,x-width=32"),
+            QemuArgument::new("-device", "pcie-root-port,id=ich9-pcie-port-3,multifunction=on,bus=pcie.0,addr=0x1c.0x2,port=3,chassis=3,x-speed=16,x-width=32"),
+            QemuArgument::new("-device", "pcie-root-port,id=ich9-pcie-port-4,multifunction=on,bus=pcie.0,addr=0x1c.0x3,port=4,chassis=4,x-speed=16,x-width=32"),
+            QemuArgument::new("-device", "i82801b11-bridge,id=pcidmi,bus=pcie.0,addr=0x1e"),
+            QemuArgument::new("-device", "pci-bridge,id=pci.0,chassis_nr=1,bus=pcidmi,addr=0x1"),
+            QemuArgument::new("-device", "pci-bridge,id=pci.1,chassis_nr=2,bus=pcidmi,addr=0x2"),
+            QemuArgument::new("-device", "pci-bridge,id=pci.2,chassis_nr=3,bus=pcidmi,addr=0x3"),
+            QemuArgument::new("-device", "qemu-xhci,p2=15,p3=15,id=xhci,bus=pci.1,addr=0x1b"),
+            QemuArgument::new("-iscsi", "initiator-name=iqn.1993-08.org.debian:01:39407ad058b"),
+            QemuArgument::new("-device", "pvscsi,id=scsihw0,bus=pci.0,addr=0x5"),
+            QemuArgument::new("-boot", "menu=on,strict=on,reboot-timeout=1000,splash=/usr/share/ezkvm/bootsplash.jpg"),
+            QemuArgument::new("-smbios", "type=1,uuid="),
+            QemuArgument::new("-m", "16384"),
+            QemuArgument::new("-smp", "4,sockets=1,cores=4,maxcpus=4"),
+            QemuArgument::new("-cpu", "qemu64,+aes,+pni,+popcnt,+sse4.1,+sse4.2,+ssse3,enforce"),
         ];
 
         assert_argument_lists_are_equal(actual, expected);
@@ -281,10 +711,11 @@ mod tests {
             name: windows_gaming_config
 
         system:
+            chipset: { type: "q35", version: "8.1" }
             bios: { type: "ovmf", uuid: "04d064c3-66a1-4aa7-9589-f8b3ecf91cd7", file: "/dev/vm1/vm-108-efidisk" }
             cpu: { model: "qemu64", sockets: 1, cores: 8, flags: "+aes,+pni,+popcnt,+sse4.1,+sse4.2,+ssse3,enforce" }
             memory: { max: 16384, balloon: false }
-            tpm: { type: "swtpm", version: 2.0, disk: "/dev/vm1/vm-108-tpmstate", socket: "/var/ezkvm/windows_gaming_config-tpm.socket" }
+            tpm: { type: "swtpm", disk: "/dev/vm1/vm-108-tpmstate", socket: "/var/ezkvm/windows_gaming_config-tpm.socket" }
 
         spice:
             port: 5903
@@ -316,56 +747,72 @@ mod tests {
 
     #[test]
     fn test_windows_gaming_config() {
-        let config: Config = serde_yaml::from_str(WINDOWS_GAMING_CONFIG).unwrap();
-
-        let tmp = config.get_qemu_args(0);
-        let actual: Vec<&str> = tmp.iter().map(std::ops::Deref::deref).collect();
-        let expected: Vec<&str> = vec![
-            "-accel kvm",
-            "-nodefaults",
-            "-monitor unix:/var/ezkvm/windows_gaming_config.monitor,server,nowait",
-            "-chardev socket,id=qmp,path=/var/ezkvm/windows_gaming_config.qmp,server=on,wait=off",
-            "-mon chardev=qmp,mode=control",
-            "-chardev socket,id=qmp-event,path=/var/run/qmeventd.sock,reconnect=5",
-            "-mon chardev=qmp-event,mode=control",
-            "-machine hpet=off,type=pc-q35-8.1",
-            "-rtc driftfix=slew,base=localtime",
-            "-global kvm-pit.lost_tick_policy=discard",
-            "-readconfig /usr/share/ezkvm/pve-q35-4.0.cfg",
-            "-device qemu-xhci,p2=15,p3=15,id=xhci,bus=pci.1,addr=0x1b",
-            "-iscsi initiator-name=iqn.1993-08.org.debian:01:39407ad058b",
-            "-device pvscsi,id=scsihw0,bus=pci.0,addr=0x5",
-            "-boot menu=on,strict=on,reboot-timeout=1000",
-            "-smbios type=1,uuid=04d064c3-66a1-4aa7-9589-f8b3ecf91cd7",
-            "-drive if=pflash,unit=0,format=raw,readonly=on,file=/usr/share/ezkvm/OVMF_CODE_4M.secboot.fd",
-            "-drive if=pflash,unit=1,id=drive-efidisk0,format=raw,file=/dev/vm1/vm-108-efidisk,size=540672",
-            "-m 16384",
-            "-smp 8,sockets=1,cores=8,maxcpus=8",
-            "-cpu qemu64,+aes,+pni,+popcnt,+sse4.1,+sse4.2,+ssse3,enforce",
-            "-chardev socket,id=chrtpm0,path=/var/ezkvm/windows_gaming_config-tpm.socket",
-            "-tpmdev emulator,id=tpm0,chardev=chrtpm0",
-            "-device tpm-tis,tpmdev=tpm0",
-            "-vga none", "-nographic",
-            "-device virtio-mouse",
-            "-device virtio-keyboard",
-            "-device ivshmem-plain,memdev=ivshmem0,bus=pcie.0",
-            "-object memory-backend-file,id=ivshmem0,share=on,mem-path=/dev/kvmfr0,size=128M",
-            "-device vfio-pci,host=0000:03:00.0,id=hostpci0.0,bus=ich9-pcie-port-1,addr=0x0.0,multifunction=on",
-            "-device vfio-pci,host=0000:03:00.1,id=hostpci0.1,bus=ich9-pcie-port-1,addr=0x0.1",
-            "-spice port=5903,addr=0.0.0.0,disable-ticketing=on",
-            "-device virtio-serial-pci",
-            "-chardev spicevmc,id=vdagent,name=vdagent",
-            "-device virtserialport,chardev=vdagent,name=com.redhat.spice.0",
-            "-audiodev spice,id=spice-backend0",
-            "-device ich9-intel-hda,id=audiodev0,bus=pci.2,addr=0xc",
-            "-device hda-duplex,id=audiodev0-codec0,bus=audiodev0.0,cad=0,audiodev=spice-backend0",
-            "-device usb-host,bus=xhci.0,port=1,hostbus=1,hostport=2.2,id=usb0",
-            "-drive file=/dev/vm1/vm-108-boot,if=none,aio=io_uring,id=drive-scsi0,discard=on,format=raw,cache=none,detect-zeroes=unmap",
-            "-device scsi-hd,scsi-id=0,drive=drive-scsi0,id=scsi0,bus=scsihw0.0,rotation_rate=1,bootindex=0",
-            "-drive file=/dev/vm1/vm-108-tmp,if=none,aio=io_uring,id=drive-scsi1,discard=on,format=raw,cache=none,detect-zeroes=unmap",
-            "-device scsi-hd,scsi-id=1,drive=drive-scsi1,id=scsi1,bus=scsihw0.0,rotation_rate=1",
-            "-netdev type=bridge,br=vmbr0,id=netdev0",
-            "-device virtio-net-pci,id=net0,bus=pci.1,addr=0x0,netdev=netdev0,mac=BC:24:11:3A:21:B7"
+        let mut config: Config = serde_yaml::from_str(WINDOWS_GAMING_CONFIG).unwrap();
+        config.allocate_pci_addresses().unwrap();
+
+        let actual = config.get_qemu_args(0);
+        let expected: Vec<QemuArgument> = vec![
+            QemuArgument::new("-accel", "kvm"),
+            QemuArgument::flag("-nodefaults"),
+            QemuArgument::new("-monitor", "unix:/var/ezkvm/windows_gaming_config.monitor,server,nowait"),
+            QemuArgument::new("-chardev", "socket,id=qmp,path=/var/ezkvm/windows_gaming_config.qmp,server=on,wait=off"),
+            QemuArgument::new("-mon", "chardev=qmp,mode=control"),
+            QemuArgument::new("-chardev", "socket,id=qmp-event,path=/var/run/qmeventd.sock,reconnect=5"),
+            QemuArgument::new("-mon", "chardev=qmp-event,mode=control"),
+            QemuArgument::new("-machine", "hpet=off,type=pc-q35-8.1"),
+            QemuArgument::new("-rtc", "driftfix=slew,base=localtime"),
+            QemuArgument::new("-global", "kvm-pit.lost_tick_policy=discard"),
+            QemuArgument::new("-device", "ich9-usb-ehci1,id=ehci,multifunction=on,bus=pcie.0,addr=0x1d.0x7"),
+            QemuArgument::new("-device", "ich9-usb-uhci1,id=uhci-1,multifunction=on,bus=pcie.0,addr=0x1d.0x0,masterbus=ehci.0,firstport=0"),
+            QemuArgument::new("-device", "ich9-usb-uhci2,id=uhci-2,multifunction=on,bus=pcie.0,addr=0x1d.0x1,masterbus=ehci.0,firstport=2"),
+            QemuArgument::new("-device", "ich9-usb-uhci3,id=uhci-3,multifunction=on,bus=pcie.0,addr=0x1d.0x2,masterbus=ehci.0,firstport=4"),
+            QemuArgument::new("-device", "ich9-usb-ehci2,id=ehci-2,multifunction=on,bus=pcie.0,addr=0x1a.0x7"),
+            QemuArgument::new("-device", "ich9-usb-uhci4,id=uhci-4,multifunction=on,bus=pcie.0,addr=0x1a.0x0,masterbus=ehci-2.0,firstport=0"),
+            QemuArgument::new("-device", "ich9-usb-uhci5,id=uhci-5,multifunction=on,bus=pcie.0,addr=0x1a.0x1,masterbus=ehci-2.0,firstport=2"),
+            QemuArgument::new("-device", "ich9-usb-uhci6,id=uhci-6,multifunction=on,bus=pcie.0,addr=0x1a.0x2,masterbus=ehci-2.0,firstport=4"),
+            QemuArgument::new("-device", "pcie-root-port,id=ich9-pcie-port-1,multifunction=on,bus=pcie.0,addr=0x1c.0x0,port=1,chassis=1,x-speed=16,x-width=32"),
+            QemuArgument::new("-device", "pcie-root-port,id=ich9-pcie-port-2,multifunction=on,bus=pcie.0,addr=0x1c.0x1,port=2,chassis=2,x-speed=16,x-width=32"),
+            QemuArgument::new("-device", "pcie-root-port,id=ich9-pcie-port-3,multifunction=on,bus=pcie.0,addr=0x1c.0x2,port=3,chassis=3,x-speed=16,x-width=32"),
+            QemuArgument::new("-device", "pcie-root-port,id=ich9-pcie-port-4,multifunction=on,bus=pcie.0,addr=0x1c.0x3,port=4,chassis=4,x-speed=16,x-width=32"),
+            QemuArgument::new("-device", "i82801b11-bridge,id=pcidmi,bus=pcie.0,addr=0x1e"),
+            QemuArgument::new("-device", "pci-bridge,id=pci.0,chassis_nr=1,bus=pcidmi,addr=0x1"),
+            QemuArgument::new("-device", "pci-bridge,id=pci.1,chassis_nr=2,bus=pcidmi,addr=0x2"),
+            QemuArgument::new("-device", "pci-bridge,id=pci.2,chassis_nr=3,bus=pcidmi,addr=0x3"),
+            QemuArgument::new("-device", "qemu-xhci,p2=15,p3=15,id=xhci,bus=pci.1,addr=0x1b"),
+            QemuArgument::new("-iscsi", "initiator-name=iqn.1993-08.org.debian:01:39407ad058b"),
+            QemuArgument::new("-device", "pvscsi,id=scsihw0,bus=pci.0,addr=0x5"),
+            QemuArgument::new("-boot", "menu=on,strict=on,reboot-timeout=1000"),
+            QemuArgument::new("-smbios", "type=1,uuid=04d064c3-66a1-4aa7-9589-f8b3ecf91cd7"),
+            QemuArgument::new("-drive", "if=pflash,unit=0,format=raw,readonly=on,file=/usr/share/ezkvm/OVMF_CODE_4M.secboot.fd"),
+            QemuArgument::new("-drive", "if=pflash,unit=1,id=drive-efidisk0,format=raw,file=/dev/vm1/vm-108-efidisk,size=540672"),
+            QemuArgument::new("-m", "16384"),
+            QemuArgument::new("-smp", "8,sockets=1,cores=8,maxcpus=8"),
+            QemuArgument::new("-cpu", "qemu64,+aes,+pni,+popcnt,+sse4.1,+sse4.2,+ssse3,enforce"),
+            QemuArgument::new("-chardev", "socket,id=chrtpm0,path=/var/ezkvm/windows_gaming_config-tpm.socket"),
+            QemuArgument::new("-tpmdev", "emulator,id=tpm0,chardev=chrtpm0"),
+            QemuArgument::new("-device", "tpm-tis,tpmdev=tpm0"),
+            QemuArgument::new("-vga", "none"),
+            QemuArgument::flag("-nographic"),
+            QemuArgument::new("-device", "virtio-mouse"),
+            QemuArgument::new("-device", "virtio-keyboard"),
+            QemuArgument::new("-device", "ivshmem-plain,memdev=ivshmem0,bus=pcie.0"),
+            QemuArgument::new("-object", "memory-backend-file,id=ivshmem0,share=on,mem-path=/dev/kvmfr0,size=128M"),
+            QemuArgument::new("-device", "vfio-pci,host=0000:03:00.0,id=hostpci0.0,bus=ich9-pcie-port-1,addr=0x0.0,multifunction=on"),
+            QemuArgument::new("-device", "vfio-pci,host=0000:03:00.1,id=hostpci0.1,bus=ich9-pcie-port-1,addr=0x0.1"),
+            QemuArgument::new("-spice", "port=5903,addr=0.0.0.0,disable-ticketing=on"),
+            QemuArgument::new("-device", "virtio-serial-pci"),
+            QemuArgument::new("-chardev", "spicevmc,id=vdagent,name=vdagent"),
+            QemuArgument::new("-device", "virtserialport,chardev=vdagent,name=com.redhat.spice.0"),
+            QemuArgument::new("-audiodev", "spice,id=spice-backend0"),
+            QemuArgument::new("-device", "ich9-intel-hda,id=audiodev0,bus=pci.2,addr=0xc"),
+            QemuArgument::new("-device", "hda-duplex,id=audiodev0-codec0,bus=audiodev0.0,cad=0,audiodev=spice-backend0"),
+            QemuArgument::new("-device", "usb-host,bus=xhci.0,port=1,hostbus=1,hostport=2.2,id=usb0"),
+            QemuArgument::new("-drive", "file=/dev/vm1/vm-108-boot,if=none,aio=io_uring,id=drive-scsi0,discard=on,format=raw,cache=none,detect-zeroes=unmap"),
+            QemuArgument::new("-device", "scsi-hd,scsi-id=0,drive=drive-scsi0,id=scsi0,bus=scsihw0.0,rotation_rate=1,bootindex=0"),
+            QemuArgument::new("-drive", "file=/dev/vm1/vm-108-tmp,if=none,aio=io_uring,id=drive-scsi1,discard=on,format=raw,cache=none,detect-zeroes=unmap"),
+            QemuArgument::new("-device", "scsi-hd,scsi-id=1,drive=drive-scsi1,id=scsi1,bus=scsihw0.0,rotation_rate=1"),
+            QemuArgument::new("-netdev", "type=bridge,br=vmbr0,id=netdev0"),
+            QemuArgument::new("-device", "virtio-net-pci,id=net0,bus=pci.1,addr=0x0,netdev=netdev0,mac=BC:24:11:3A:21:B7")
         ];
 
         assert_argument_lists_are_equal(actual, expected);
@@ -380,11 +827,11 @@ mod tests {
           bios: { type: "ovmf", uuid: "181f1a56-e0e2-42d1-a916-bc16dd415a59", file: "/dev/vm1/vm-111-efidisk" }
           cpu: { model: "qemu64", sockets: 1, cores: 8, flags: "+aes,+pni,+popcnt,+sse4.1,+sse4.2,+ssse3,enforce" }
           memory: { max: 16384, balloon: false }
-          tpm: { type: "swtpm", version: 2.0, disk: "/dev/vm1/vm-111-tpmstate", socket: "/var/ezkvm/windows_desktop_config-tpm.socket" }
+          tpm: { type: "swtpm", disk: "/dev/vm1/vm-111-tpmstate", socket: "/var/ezkvm/windows_desktop_config-tpm.socket" }
 
         spice:
           path: /var/ezkvm/windows_desktop_config-spice.socket
-          gl: true
+          gl: on
           render_node: /dev/dri/renderD128
 
         gpu:
@@ -402,47 +849,62 @@ mod tests {
 
     #[test]
     fn test_windows_desktop_config() {
-        let config: Config = serde_yaml::from_str(WINDOWS_DESKTOP_CONFIG).unwrap();
-
-        let tmp = config.get_qemu_args(0);
-        let actual: Vec<&str> = tmp.iter().map(std::ops::Deref::deref).collect();
-        let expected: Vec<&str> = vec![
-            "-accel kvm",
-            "-nodefaults",
-            "-monitor unix:/var/ezkvm/windows_desktop_config.monitor,server,nowait",
-            "-chardev socket,id=qmp,path=/var/ezkvm/windows_desktop_config.qmp,server=on,wait=off", 
-            "-mon chardev=qmp,mode=control", 
-            "-chardev socket,id=qmp-event,path=/var/run/qmeventd.sock,reconnect=5", 
-            "-mon chardev=qmp-event,mode=control", 
-            "-machine hpet=off,type=pc-q35-8.1", 
-            "-rtc driftfix=slew,base=localtime", 
-            "-global kvm-pit.lost_tick_policy=discard", 
-            "-readconfig /usr/share/ezkvm/pve-q35-4.0.cfg", 
-            "-device qemu-xhci,p2=15,p3=15,id=xhci,bus=pci.1,addr=0x1b", 
-            "-iscsi initiator-name=iqn.1993-08.org.debian:01:39407ad058b", 
-            "-device pvscsi,id=scsihw0,bus=pci.0,addr=0x5", 
-            "-boot menu=on,strict=on,reboot-timeout=1000",
-            "-smbios type=1,uuid=181f1a56-e0e2-42d1-a916-bc16dd415a59", 
-            "-drive if=pflash,unit=0,format=raw,readonly=on,file=/usr/share/ezkvm/OVMF_CODE_4M.secboot.fd",
-            "-drive if=pflash,unit=1,id=drive-efidisk0,format=raw,file=/dev/vm1/vm-111-efidisk,size=540672", 
-            "-m 16384", 
-            "-smp 8,sockets=1,cores=8,maxcpus=8", 
-            "-cpu qemu64,+aes,+pni,+popcnt,+sse4.1,+sse4.2,+ssse3,enforce", 
-            "-chardev socket,id=chrtpm0,path=/var/ezkvm/windows_desktop_config-tpm.socket", 
-            "-tpmdev emulator,id=tpm0,chardev=chrtpm0", 
-            "-device tpm-tis,tpmdev=tpm0", 
-            "-device virtio-vga-gl,id=vga,bus=pcie.0,addr=0x2", 
-            "-spice unix=on,addr=/var/ezkvm/windows_desktop_config-spice.socket,disable-ticketing=on", 
-            "-device virtio-serial-pci", 
-            "-chardev spicevmc,id=vdagent,name=vdagent", 
-            "-device virtserialport,chardev=vdagent,name=com.redhat.spice.0", 
-            "-audiodev spice,id=spice-backend0", 
-            "-device ich9-intel-hda,id=audiodev0,bus=pci.2,addr=0xc", 
-            "-device hda-duplex,id=audiodev0-codec0,bus=audiodev0.0,cad=0,audiodev=spice-backend0", 
-            "-drive file=/dev/vm1/vm-111-boot,if=none,aio=io_uring,id=drive-scsi0,discard=on,format=raw,cache=none,detect-zeroes=unmap", 
-            "-device scsi-hd,scsi-id=0,drive=drive-scsi0,id=scsi0,bus=scsihw0.0,rotation_rate=1,bootindex=0", 
-            "-netdev type=bridge,br=vmbr0,id=netdev0", 
-            "-device virtio-net-pci,id=net0,bus=pci.1,addr=0x0,netdev=netdev0,mac=BC:24:11:3A:21:7B"
+        let mut config: Config = serde_yaml::from_str(WINDOWS_DESKTOP_CONFIG).unwrap();
+        config.allocate_pci_addresses().unwrap();
+
+        let actual = config.get_qemu_args(0);
+        let expected: Vec<QemuArgument> = vec![
+            QemuArgument::new("-accel", "kvm"),
+            QemuArgument::flag("-nodefaults"),
+            QemuArgument::new("-monitor", "unix:/var/ezkvm/windows_desktop_config.monitor,server,nowait"),
+            QemuArgument::new("-chardev", "socket,id=qmp,path=/var/ezkvm/windows_desktop_config.qmp,server=on,wait=off"),
+            QemuArgument::new("-mon", "chardev=qmp,mode=control"),
+            QemuArgument::new("-chardev", "socket,id=qmp-event,path=/var/run/qmeventd.sock,reconnect=5"),
+            QemuArgument::new("-mon", "chardev=qmp-event,mode=control"),
+            QemuArgument::new("-machine", "hpet=off,type=pc-q35-8.1"),
+            QemuArgument::new("-rtc", "driftfix=slew,base=localtime"),
+            QemuArgument::new("-global", "kvm-pit.lost_tick_policy=discard"),
+            QemuArgument::new("-device", "ich9-usb-ehci1,id=ehci,multifunction=on,bus=pcie.0,addr=0x1d.0x7"),
+            QemuArgument::new("-device", "ich9-usb-uhci1,id=uhci-1,multifunction=on,bus=pcie.0,addr=0x1d.0x0,masterbus=ehci.0,firstport=0"),
+            QemuArgument::new("-device", "ich9-usb-uhci2,id=uhci-2,multifunction=on,bus=pcie.0,addr=0x1d.0x1,masterbus=ehci.0,firstport=2"),
+            QemuArgument::new("-device", "ich9-usb-uhci3,id=uhci-3,multifunction=on,bus=pcie.0,addr=0x1d.0x2,masterbus=ehci.0,firstport=4"),
+            QemuArgument::new("-device", "ich9-usb-ehci2,id=ehci-2,multifunction=on,bus=pcie.0,addr=0x1a.0x7"),
+            QemuArgument::new("-device", "ich9-usb-uhci4,id=uhci-4,multifunction=on,bus=pcie.0,addr=0x1a.0x0,masterbus=ehci-2.0,firstport=0"),
+            QemuArgument::new("-device", "ich9-usb-uhci5,id=uhci-5,multifunction=on,bus=pcie.0,addr=0x1a.0x1,masterbus=ehci-2.0,firstport=2"),
+            QemuArgument::new("-device", "ich9-usb-uhci6,id=uhci-6,multifunction=on,bus=pcie.0,addr=0x1a.0x2,masterbus=ehci-2.0,firstport=4"),
+            QemuArgument::new("-device", "pcie-root-port,id=ich9-pcie-port-1,multifunction=on,bus=pcie.0,addr=0x1c.0x0,port=1,chassis=1,x-speed=16,x-width=32"),
+            QemuArgument::new("-device", "pcie-root-port,id=ich9-pcie-port-2,multifunction=on,bus=pcie.0,addr=0x1c.0x1,port=2,chassis=2,x-speed=16,x-width=32"),
+            QemuArgument::new("-device", "pcie-root-port,id=ich9-pcie-port-3,multifunction=on,bus=pcie.0,addr=0x1c.0x2,port=3,chassis=3,x-speed=16,x-width=32"),
+            QemuArgument::new("-device", "pcie-root-port,id=ich9-pcie-port-4,multifunction=on,bus=pcie.0,addr=0x1c.0x3,port=4,chassis=4,x-speed=16,x-width=32"),
+            QemuArgument::new("-device", "i82801b11-bridge,id=pcidmi,bus=pcie.0,addr=0x1e"),
+            QemuArgument::new("-device", "pci-bridge,id=pci.0,chassis_nr=1,bus=pcidmi,addr=0x1"),
+            QemuArgument::new("-device", "pci-bridge,id=pci.1,chassis_nr=2,bus=pcidmi,addr=0x2"),
+            QemuArgument::new("-device", "pci-bridge,id=pci.2,chassis_nr=3,bus=pcidmi,addr=0x3"),
+            QemuArgument::new("-device", "qemu-xhci,p2=15,p3=15,id=xhci,bus=pci.1,addr=0x1b"),
+            QemuArgument::new("-iscsi", "initiator-name=iqn.1993-08.org.debian:01:39407ad058b"),
+            QemuArgument::new("-device", "pvscsi,id=scsihw0,bus=pci.0,addr=0x5"),
+            QemuArgument::new("-boot", "menu=on,strict=on,reboot-timeout=1000"),
+            QemuArgument::new("-smbios", "type=1,uuid=181f1a56-e0e2-42d1-a916-bc16dd415a59"),
+            QemuArgument::new("-drive", "if=pflash,unit=0,format=raw,readonly=on,file=/usr/share/ezkvm/OVMF_CODE_4M.secboot.fd"),
+            QemuArgument::new("-drive", "if=pflash,unit=1,id=drive-efidisk0,format=raw,file=/dev/vm1/vm-111-efidisk,size=540672"),
+            QemuArgument::new("-m", "16384"),
+            QemuArgument::new("-smp", "8,sockets=1,cores=8,maxcpus=8"),
+            QemuArgument::new("-cpu", "qemu64,+aes,+pni,+popcnt,+sse4.1,+sse4.2,+ssse3,enforce"),
+            QemuArgument::new("-chardev", "socket,id=chrtpm0,path=/var/ezkvm/windows_desktop_config-tpm.socket"),
+            QemuArgument::new("-tpmdev", "emulator,id=tpm0,chardev=chrtpm0"),
+            QemuArgument::new("-device", "tpm-tis,tpmdev=tpm0"),
+            QemuArgument::new("-device", "virtio-vga-gl,id=vga,bus=pcie.0,addr=0x2"),
+            QemuArgument::new("-spice", "unix=on,addr=/var/ezkvm/windows_desktop_config-spice.socket,gl=on,rendernode=/dev/dri/renderD128,disable-ticketing=on"),
+            QemuArgument::new("-device", "virtio-serial-pci"),
+            QemuArgument::new("-chardev", "spicevmc,id=vdagent,name=vdagent"),
+            QemuArgument::new("-device", "virtserialport,chardev=vdagent,name=com.redhat.spice.0"),
+            QemuArgument::new("-audiodev", "spice,id=spice-backend0"),
+            QemuArgument::new("-device", "ich9-intel-hda,id=audiodev0,bus=pci.2,addr=0xc"),
+            QemuArgument::new("-device", "hda-duplex,id=audiodev0-codec0,bus=audiodev0.0,cad=0,audiodev=spice-backend0"),
+            QemuArgument::new("-drive", "file=/dev/vm1/vm-111-boot,if=none,aio=io_uring,id=drive-scsi0,discard=on,format=raw,cache=none,detect-zeroes=unmap"),
+            QemuArgument::new("-device", "scsi-hd,scsi-id=0,drive=drive-scsi0,id=scsi0,bus=scsihw0.0,rotation_rate=1,bootindex=0"),
+            QemuArgument::new("-netdev", "type=bridge,br=vmbr0,id=netdev0"),
+            QemuArgument::new("-device", "virtio-net-pci,id=net0,bus=pci.1,addr=0x0,netdev=netdev0,mac=BC:24:11:3A:21:7B")
         ];
 
         assert_argument_lists_are_equal(actual, expected);
@@ -457,7 +919,6 @@ mod tests {
 
         gpu:
             type: "virtio-vga-gl"
-            memory: 256
 
         display:
             type: "gtk"
@@ -472,44 +933,64 @@ mod tests {
     "#;
 
     #[test]
+    #[serial]
     fn test_ubuntu_defaults() {
-        let config: Config = serde_yaml::from_str(DEFAULT_UBUNTU_CONFIG).unwrap();
-        let tmp = config.get_qemu_args(0);
-        let actual: Vec<&str> = tmp.iter().map(std::ops::Deref::deref).collect();
-        let expected: Vec<&str> = vec![
-            "-accel kvm",
-            "-nodefaults",
-            "-monitor unix:/var/ezkvm/ubuntu_desktop.monitor,server,nowait",
-            "-chardev socket,id=qmp,path=/var/ezkvm/ubuntu_desktop.qmp,server=on,wait=off",
-            "-mon chardev=qmp,mode=control",
-            "-chardev socket,id=qmp-event,path=/var/run/qmeventd.sock,reconnect=5",
-            "-mon chardev=qmp-event,mode=control",
-            "-machine hpet=off,type=pc-q35-8.1",
-            "-rtc driftfix=slew,base=localtime",
-            "-global kvm-pit.lost_tick_policy=discard",
-            "-readconfig /usr/share/ezkvm/pve-q35-4.0.cfg",
-            "-device qemu-xhci,p2=15,p3=15,id=xhci,bus=pci.1,addr=0x1b",
-            "-iscsi initiator-name=iqn.1993-08.org.debian:01:39407ad058b",
-            "-device pvscsi,id=scsihw0,bus=pci.0,addr=0x5",
-            "-boot menu=on,strict=on,reboot-timeout=1000",
-            "-smbios type=1,uuid=c0e240a5-859a-4378-a2d9-95088f531142",
-            "-drive if=pflash,unit=0,format=raw,readonly=on,file=/usr/share/ezkvm/OVMF_CODE_4M.secboot.fd",
-            "-drive if=pflash,unit=1,id=drive-efidisk0,format=raw,file=/dev/vm1/vm-950-disk-0,size=540672",
-            "-m 16384",
-            "-smp 4,sockets=1,cores=4,maxcpus=4",
-            "-cpu qemu64,+aes,+pni,+popcnt,+sse4.1,+sse4.2,+ssse3,enforce",
-            "-display gtk,gl=on",
-            "-audiodev pipewire,id=audiodev0",
-            "-device usb-tablet",
-            "-device ich9-intel-hda,id=audiodev0,bus=pci.2,addr=0xc",
-            "-device hda-duplex,id=audiodev0-codec0,bus=audiodev0.0,cad=0,audiodev=audiodev0",
-            "-device virtio-vga-gl,id=vga,bus=pcie.0,addr=0x2",
-            "-drive file=/dev/vm1/vm-950-disk-1,if=none,aio=io_uring,id=drive-scsi0,format=raw,cache=none,detect-zeroes=unmap",
-            "-device scsi-hd,scsi-id=0,drive=drive-scsi0,id=scsi0,bus=scsihw0.0,rotation_rate=1,bootindex=1",
-            "-drive file=ubuntu.iso,if=none,aio=io_uring,id=drive-ide1,media=cdrom",
-            "-device ide-cd,bus=ide.1,drive=drive-ide1,id=ide1,unit=0",
-            "-netdev type=bridge,br=vmbr0,id=netdev0",
-            "-device virtio-net-pci,id=net0,bus=pci.1,addr=0x0,netdev=netdev0,mac=BC:24:11:FF:76:89"
+        let get_machine_types = Osal::get_machine_types_context();
+        get_machine_types
+            .expect()
+            .returning(|_| vec!["pc-q35-8.1".to_string()]);
+        let mut config: Config = serde_yaml::from_str(DEFAULT_UBUNTU_CONFIG).unwrap();
+        config.allocate_pci_addresses().unwrap();
+        let actual = config.get_qemu_args(0);
+        let expected: Vec<QemuArgument> = vec![
+            QemuArgument::new("-accel", "kvm"),
+            QemuArgument::flag("-nodefaults"),
+            QemuArgument::new("-monitor", "unix:/var/ezkvm/ubuntu_desktop.monitor,server,nowait"),
+            QemuArgument::new("-chardev", "socket,id=qmp,path=/var/ezkvm/ubuntu_desktop.qmp,server=on,wait=off"),
+            QemuArgument::new("-mon", "chardev=qmp,mode=control"),
+            QemuArgument::new("-chardev", "socket,id=qmp-event,path=/var/run/qmeventd.sock,reconnect=5"),
+            QemuArgument::new("-mon", "chardev=qmp-event,mode=control"),
+            QemuArgument::new("-machine", "hpet=off,type=pc-q35-8.1"),
+            QemuArgument::new("-rtc", "driftfix=slew,base=localtime"),
+            QemuArgument::new("-global", "kvm-pit.lost_tick_policy=discard"),
+            QemuArgument::new("-device", "ich9-usb-ehci1,id=ehci,multifunction=on,bus=pcie.0,addr=0x1d.0x7"),
+            QemuArgument::new("-device", "ich9-usb-uhci1,id=uhci-1,multifunction=on,bus=pcie.0,addr=0x1d.0x0,masterbus=ehci.0,firstport=0"),
+            QemuArgument::new("-device", "ich9-usb-uhci2,id=uhci-2,multifunction=on,bus=pcie.0,addr=0x1d.0x1,masterbus=ehci.0,firstport=2"),
+            QemuArgument::new("-device", "ich9-usb-uhci3,id=uhci-3,multifunction=on,bus=pcie.0,addr=0x1d.0x2,masterbus=ehci.0,firstport=4"),
+            QemuArgument::new("-device", "ich9-usb-ehci2,id=ehci-2,multifunction=on,bus=pcie.0,addr=0x1a.0x7"),
+            QemuArgument::new("-device", "ich9-usb-uhci4,id=uhci-4,multifunction=on,bus=pcie.0,addr=0x1a.0x0,masterbus=ehci-2.0,firstport=0"),
+            QemuArgument::new("-device", "ich9-usb-uhci5,id=uhci-5,multifunction=on,bus=pcie.0,addr=0x1a.0x1,masterbus=ehci-2.0,firstport=2"),
+            QemuArgument::new("-device", "ich9-usb-uhci6,id=uhci-6,multifunction=on,bus=pcie.0,addr=0x1a.0x2,masterbus=ehci-2.0,firstport=4"),
+            QemuArgument::new("-device", "pcie-root-port,id=ich9-pcie-port-1,multifunction=on,bus=pcie.0,addr=0x1c.0x0,port=1,chassis=1,x-speed=16,x-width=32"),
+            QemuArgument::new("-device", "pcie-root-port,id=ich9-pcie-port-2,multifunction=on,bus=pcie.0,addr=0x1c.0x1,port=2,chassis=2,x-speed=16,x-width=32"),
+            QemuArgument::new("-device", "pcie-root-port,id=ich9-pcie-port-3,multifunction=on,bus=pcie.0,addr=0x1c.0x2,port=3,chassis=3,x-speed=16,x-width=32"),
+            QemuArgument::new("-device", "pcie-root-port,id=ich9-pcie-port-4,multifunction=on,bus=pcie.0,addr=0x1c.0x3,port=4,chassis=4,x-speed=16,x-width=32"),
+            QemuArgument::new("-device", "i82801b11-bridge,id=pcidmi,bus=pcie.0,addr=0x1e"),
+            QemuArgument::new("-device", "pci-bridge,id=pci.0,chassis_nr=1,bus=pcidmi,addr=0x1"),
+            QemuArgument::new("-device", "pci-bridge,id=pci.1,chassis_nr=2,bus=pcidmi,addr=0x2"),
+            QemuArgument::new("-device", "pci-bridge,id=pci.2,chassis_nr=3,bus=pcidmi,addr=0x3"),
+            QemuArgument::new("-device", "qemu-xhci,p2=15,p3=15,id=xhci,bus=pci.1,addr=0x1b"),
+            QemuArgument::new("-iscsi", "initiator-name=iqn.1993-08.org.debian:01:39407ad058b"),
+            QemuArgument::new("-device", "pvscsi,id=scsihw0,bus=pci.0,addr=0x5"),
+            QemuArgument::new("-boot", "menu=on,strict=on,reboot-timeout=1000"),
+            QemuArgument::new("-smbios", "type=1,uuid=c0e240a5-859a-4378-a2d9-95088f531142"),
+            QemuArgument::new("-drive", "if=pflash,unit=0,format=raw,readonly=on,file=/usr/share/ezkvm/OVMF_CODE_4M.secboot.fd"),
+            QemuArgument::new("-drive", "if=pflash,unit=1,id=drive-efidisk0,format=raw,file=/dev/vm1/vm-950-disk-0,size=540672"),
+            QemuArgument::new("-m", "16384"),
+            QemuArgument::new("-smp", "4,sockets=1,cores=4,maxcpus=4"),
+            QemuArgument::new("-cpu", "qemu64,+aes,+pni,+popcnt,+sse4.1,+sse4.2,+ssse3,enforce"),
+            QemuArgument::new("-display", "gtk,gl=on"),
+            QemuArgument::new("-audiodev", "pipewire,id=audiodev0"),
+            QemuArgument::new("-device", "usb-tablet"),
+            QemuArgument::new("-device", "ich9-intel-hda,id=audiodev0,bus=pci.2,addr=0xc"),
+            QemuArgument::new("-device", "hda-duplex,id=audiodev0-codec0,bus=audiodev0.0,cad=0,audiodev=audiodev0"),
+            QemuArgument::new("-device", "virtio-vga-gl,id=vga,bus=pcie.0,addr=0x2"),
+            QemuArgument::new("-drive", "file=/dev/vm1/vm-950-disk-1,if=none,aio=io_uring,id=drive-scsi0,format=raw,cache=none,detect-zeroes=unmap"),
+            QemuArgument::new("-device", "scsi-hd,scsi-id=0,drive=drive-scsi0,id=scsi0,bus=scsihw0.0,rotation_rate=1,bootindex=1"),
+            QemuArgument::new("-drive", "file=ubuntu.iso,if=none,aio=io_uring,id=drive-ide1,media=cdrom"),
+            QemuArgument::new("-device", "ide-cd,bus=ide.1,drive=drive-ide1,id=ide1,unit=0"),
+            QemuArgument::new("-netdev", "type=bridge,br=vmbr0,id=netdev0"),
+            QemuArgument::new("-device", "virtio-net-pci,id=net0,bus=pci.1,addr=0x0,netdev=netdev0,mac=BC:24:11:FF:76:89")
         ];
 
         assert_argument_lists_are_equal(actual, expected);
@@ -554,54 +1035,78 @@ mod tests {
     "#;
 
     #[test]
+    #[serial]
     fn test_macos_defaults() {
-        let expected: Vec<&str> = vec![
-            "qemu-system-x86_64",
-            "-accel", "kvm", "-nodefaults",
-            "-monitor", "unix:/var/ezkvm/wakiza.monitor,server,nowait",
-            "-chardev", "socket,id=qmp,path=/var/ezkvm/wakiza.qmp,server=on,wait=off",
-            "-mon", "chardev=qmp,mode=control",
-            "-chardev", "socket,id=qmp-event,path=/var/run/qmeventd.sock,reconnect=5",
-            "-mon", "chardev=qmp-event,mode=control",
-            "-machine", "hpet=off,type=pc-q35-8.1",
-            "-rtc", "driftfix=slew,base=localtime",
-            "-global", "kvm-pit.lost_tick_policy=discard",
-            "-readconfig", "/usr/share/ezkvm/pve-q35-4.0.cfg",
-            "-device", "qemu-xhci,p2=15,p3=15,id=xhci,bus=pci.1,addr=0x1b",
-            "-iscsi", "initiator-name=iqn.1993-08.org.debian:01:39407ad058b",
-            "-device", "pvscsi,id=scsihw0,bus=pci.0,addr=0x5",
-            "-boot", "menu=on,strict=on,reboot-timeout=1000",
-            "-smbios", "type=1,uuid=04d064c3-66a1-4aa7-9589-f8b3ecf91cd7",
-            "-drive", "if=pflash,unit=0,format=raw,readonly=on,file=/usr/share/ezkvm/OVMF_CODE_4M.secboot.fd",
-            "-drive", "if=pflash,unit=1,id=drive-efidisk0,format=raw,file=/dev/vm1/vm-108-efidisk,size=540672",
-            "-m", "16384",
-            "-smp", "8,sockets=1,cores=8,maxcpus=8",
-            "-cpu", "qemu64,+aes,+pni,+popcnt,+sse4.1,+sse4.2,+ssse3,enforce",
-            "-chardev", "socket,id=chrtpm0,path=/var/ezkvm/wakiza-tpm.socket",
-            "-tpmdev", "emulator,id=tpm0,chardev=chrtpm0",
-            "-device", "tpm-tis,tpmdev=tpm0",
-            "-vga", "none", "-nographic",
-            "-device", "virtio-mouse",
-            "-device", "virtio-keyboard",
-            "-device", "ivshmem-plain,memdev=ivshmem0,bus=pcie.0",
-            "-object", "memory-backend-file,id=ivshmem0,share=on,mem-path=/dev/kvmfr0,size=128M",
-            "-device", "vfio-pci,host=0000:03:00.0,id=hostpci0.0,bus=ich9-pcie-port-1,addr=0x0.0,multifunction=on",
-            "-device", "vfio-pci,host=0000:03:00.1,id=hostpci0.1,bus=ich9-pcie-port-1,addr=0x0.1",
-            "-spice", "port=5903,addr=0.0.0.0,disable-ticketing=on",
-            "-device", "virtio-serial-pci",
-            "-chardev", "spicevmc,id=vdagent,name=vdagent",
-            "-device", "virtserialport,chardev=vdagent,name=com.redhat.spice.0",
-            "-audiodev", "spice,id=spice-backend0",
-            "-device", "ich9-intel-hda,id=audiodev0,bus=pci.2,addr=0xc",
-            "-device", "hda-duplex,id=audiodev0-codec0,bus=audiodev0.0,cad=0,audiodev=spice-backend0",
-            "-device", "usb-host,bus=xhci.0,port=1,hostbus=1,hostport=2.2,id=usb0",
-            "-drive", "file=/dev/vm1/vm-108-boot,if=none,aio=io_uring,id=drive-scsi0,discard=on,format=raw,cache=none,detect-zeroes=unmap",
-            "-device", "scsi-hd,scsi-id=0,drive=drive-scsi0,id=scsi0,bus=scsihw0.0,rotation_rate=1,bootindex=0",
-            "-drive", "file=/dev/vm1/vm-108-tmp,if=none,aio=io_uring,id=drive-scsi1,discard=on,format=raw,cache=none,detect-zeroes=unmap",
-            "-device", "scsi-hd,scsi-id=1,drive=drive-scsi1,id=scsi1,bus=scsihw0.0,rotation_rate=1",
-            "-netdev", "type=bridge,br=vmbr0,id=netdev0",
-            "-device", "virtio-net-pci,id=net0,bus=pci.1,addr=0x0,netdev=netdev0,mac=BC:24:11:3A:21:B7"
+        let get_machine_types = Osal::get_machine_types_context();
+        get_machine_types
+            .expect()
+            .returning(|_| vec!["pc-q35-8.1".to_string()]);
+        let mut config: Config = serde_yaml::from_str(DEFAULT_MACOS_CONFIG).unwrap();
+        config.allocate_pci_addresses().unwrap();
+        let actual = config.get_qemu_args(0);
+        let expected: Vec<QemuArgument> = vec![
+            QemuArgument::new("-accel", "kvm"),
+            QemuArgument::flag("-nodefaults"),
+            QemuArgument::new("-monitor", "unix:/var/ezkvm/mygeeto.monitor,server,nowait"),
+            QemuArgument::new("-chardev", "socket,id=qmp,path=/var/ezkvm/mygeeto.qmp,server=on,wait=off"),
+            QemuArgument::new("-mon", "chardev=qmp,mode=control"),
+            QemuArgument::new("-chardev", "socket,id=qmp-event,path=/var/run/qmeventd.sock,reconnect=5"),
+            QemuArgument::new("-mon", "chardev=qmp-event,mode=control"),
+            QemuArgument::new("-machine", "hpet=off,type=pc-q35-8.1"),
+            QemuArgument::new("-rtc", "driftfix=slew,base=localtime"),
+            QemuArgument::new("-global", "kvm-pit.lost_tick_policy=discard"),
+            QemuArgument::new("-device", "ich9-usb-ehci1,id=ehci,multifunction=on,bus=pcie.0,addr=0x1d.0x7"),
+            QemuArgument::new("-device", "ich9-usb-uhci1,id=uhci-1,multifunction=on,bus=pcie.0,addr=0x1d.0x0,masterbus=ehci.0,firstport=0"),
+            QemuArgument::new("-device", "ich9-usb-uhci2,id=uhci-2,multifunction=on,bus=pcie.0,addr=0x1d.0x1,masterbus=ehci.0,firstport=2"),
+            QemuArgument::new("-device", "ich9-usb-uhci3,id=uhci-3,multifunction=on,bus=pcie.0,addr=0x1d.0x2,masterbus=ehci.0,firstport=4"),
+            QemuArgument::new("-device", "ich9-usb-ehci2,id=ehci-2,multifunction=on,bus=pcie.0,addr=0x1a.0x7"),
+            QemuArgument::new("-device", "ich9-usb-uhci4,id=uhci-4,multifunction=on,bus=pcie.0,addr=0x1a.0x0,masterbus=ehci-2.0,firstport=0"),
+            QemuArgument::new("-device", "ich9-usb-uhci5,id=uhci-5,multifunction=on,bus=pcie.0,addr=0x1a.0x1,masterbus=ehci-2.0,firstport=2"),
+            QemuArgument::new("-device", "ich9-usb-uhci6,id=uhci-6,multifunction=on,bus=pcie.0,addr=0x1a.0x2,masterbus=ehci-2.0,firstport=4"),
+            QemuArgument::new("-device", "pcie-root-port,id=ich9-pcie-port-1,multifunction=on,bus=pcie.0,addr=0x1c.0x0,port=1,chassis=1,x-speed=16,x-width=32"),
+            QemuArgument::new("-device", "pcie-root-port,id=ich9-pcie-port-2,multifunction=on,bus=pcie.0,addr=0x1c.0x1,port=2,chassis=2,x-speed=16,x-width=32"),
+            QemuArgument::new("-device", "pcie-root-port,id=ich9-pcie-port-3,multifunction=on,bus=pcie.0,addr=0x1c.0x2,port=3,chassis=3,x-speed=16,x-width=32"),
+            QemuArgument::new("-device", "pcie-root-port,id=ich9-pcie-port-4,multifunction=on,bus=pcie.0,addr=0x1c.0x3,port=4,chassis=4,x-speed=16,x-width=32"),
+            QemuArgument::new("-device", "i82801b11-bridge,id=pcidmi,bus=pcie.0,addr=0x1e"),
+            QemuArgument::new("-device", "pci-bridge,id=pci.0,chassis_nr=1,bus=pcidmi,addr=0x1"),
+            QemuArgument::new("-device", "pci-bridge,id=pci.1,chassis_nr=2,bus=pcidmi,addr=0x2"),
+            QemuArgument::new("-device", "pci-bridge,id=pci.2,chassis_nr=3,bus=pcidmi,addr=0x3"),
+            QemuArgument::new("-device", "qemu-xhci,p2=15,p3=15,id=xhci,bus=pci.1,addr=0x1b"),
+            QemuArgument::new("-iscsi", "initiator-name=iqn.1993-08.org.debian:01:39407ad058b"),
+            QemuArgument::new("-device", "pvscsi,id=scsihw0,bus=pci.0,addr=0x5"),
+            QemuArgument::new("-boot", "menu=on,strict=on,reboot-timeout=1000"),
+            QemuArgument::new("-smbios", "type=1,uuid=05cba597-8096-47c6-9cd7-1481dbb3b95a"),
+            QemuArgument::new("-drive", "if=pflash,unit=0,format=raw,readonly=on,file=/usr/share/ezkvm/OVMF_CODE_4M.secboot.fd"),
+            QemuArgument::new("-drive", "if=pflash,unit=1,id=drive-efidisk0,format=raw,file=/dev/vm1/vm-402-efidisk,size=540672"),
+            QemuArgument::new("-m", "16384"),
+            QemuArgument::new("-smp", "8,sockets=1,cores=8,maxcpus=8"),
+            QemuArgument::new("-cpu", "Haswell-noTSX,vendor=GenuineIntel,+invtsc,+hypervisor,+pcid,+invpcid,+erms,kvm=on,vmware-cpuid-freq=on"),
+            QemuArgument::new("-device", "isa-applesmc,osk=<my_osk_key>"),
+            QemuArgument::new("-vga", "none"),
+            QemuArgument::flag("-nographic"),
+            QemuArgument::new("-device", "virtio-mouse"),
+            QemuArgument::new("-device", "virtio-keyboard"),
+            QemuArgument::new("-device", "ivshmem-plain,memdev=ivshmem0,bus=pcie.0"),
+            QemuArgument::new("-object", "memory-backend-file,id=ivshmem0,share=on,mem-path=/dev/kvmfr0,size=128M"),
+            QemuArgument::new("-device", "vfio-pci,host=0000:03:00.0,id=hostpci0.0,bus=ich9-pcie-port-1,addr=0x0.0,multifunction=on"),
+            QemuArgument::new("-device", "vfio-pci,host=0000:03:00.1,id=hostpci0.1,bus=ich9-pcie-port-1,addr=0x0.1"),
+            QemuArgument::new("-spice", "port=5903,addr=0.0.0.0,disable-ticketing=on"),
+            QemuArgument::new("-device", "virtio-serial-pci"),
+            QemuArgument::new("-chardev", "spicevmc,id=vdagent,name=vdagent"),
+            QemuArgument::new("-device", "virtserialport,chardev=vdagent,name=com.redhat.spice.0"),
+            QemuArgument::new("-audiodev", "spice,id=spice-backend0"),
+            QemuArgument::new("-device", "ich9-intel-hda,id=audiodev0,bus=pci.2,addr=0xc"),
+            QemuArgument::new("-device", "hda-duplex,id=audiodev0-codec0,bus=audiodev0.0,cad=0,audiodev=spice-backend0"),
+            QemuArgument::new("-device", "usb-host,bus=xhci.0,port=1,hostbus=1,hostport=2.2,id=usb0"),
+            QemuArgument::new("-drive", "file=/dev/vm1/vm-108-boot,if=none,aio=io_uring,id=drive-scsi0,discard=on,format=raw,cache=none,detect-zeroes=unmap"),
+            QemuArgument::new("-device", "scsi-hd,scsi-id=0,drive=drive-scsi0,id=scsi0,bus=scsihw0.0,rotation_rate=1,bootindex=0"),
+            QemuArgument::new("-drive", "file=/dev/vm1/vm-108-tmp,if=none,aio=io_uring,id=drive-scsi1,discard=on,format=raw,cache=none,detect-zeroes=unmap"),
+            QemuArgument::new("-device", "scsi-hd,scsi-id=1,drive=drive-scsi1,id=scsi1,bus=scsihw0.0,rotation_rate=1"),
+            QemuArgument::new("-netdev", "type=bridge,br=vmbr0,id=netdev0"),
+            QemuArgument::new("-device", "virtio-net-pci,id=net0,bus=pci.1,addr=0x0,netdev=netdev0,mac=BC:24:11:3A:21:B7")
         ];
+
+        assert_argument_lists_are_equal(actual, expected);
     }
 
     #[test]
@@ -613,7 +1118,7 @@ mod tests {
     #[test]
     fn test_has_no_gtk_display_configured() {
         let config: Config = serde_yaml::from_str(WINDOWS_GAMING_CONFIG).unwrap();
-        assert_eq!(config.has_gtk_display_configured(), false)
+        assert!(!config.has_gtk_display_configured())
     }
 
     #[test]
@@ -655,11 +1160,33 @@ mod tests {
         let find_files = Osal::find_files_context();
         find_files.expect().returning(|p: String, l: Vec<&str>| {
             assert_eq!(p, "wakiza.yaml");
-            assert_eq!(l, vec![".", "~/.ezkvm", "/etc/ezkvm"]);
+            assert_eq!(
+                l,
+                vec![".", "$XDG_CONFIG_HOME/ezkvm", "~/.ezkvm", "/etc/ezkvm"]
+            );
             vec![]
         });
         let result = Config::read("wakiza");
-        assert!(result.is_none());
+        assert!(matches!(result, Err(OsalError::OpenError(_))));
+    }
+
+    #[test]
+    #[serial]
+    fn test_config_read_refuses_duplicate_names() {
+        let find_files = Osal::find_files_context();
+        find_files.expect().returning(|_p: String, _l: Vec<&str>| {
+            vec![
+                PathBuf::from("/home/user/.config/ezkvm/wakiza.yaml"),
+                PathBuf::from("/etc/ezkvm/wakiza.yaml"),
+            ]
+        });
+
+        assert_eq!(
+            Config::find("wakiza"),
+            Err(OsalError::OpenError(Some(
+                "wakiza.yaml, there is more than one: /home/user/.config/ezkvm/wakiza.yaml, /etc/ezkvm/wakiza.yaml".to_string()
+            )))
+        );
     }
 
     #[test]
@@ -668,79 +1195,665 @@ mod tests {
         let find_files = Osal::find_files_context();
         find_files.expect().returning(|p: String, l: Vec<&str>| {
             assert_eq!(p, "wakiza.yaml");
-            assert_eq!(l, vec![".", "~/.ezkvm", "/etc/ezkvm"]);
+            assert_eq!(
+                l,
+                vec![".", "$XDG_CONFIG_HOME/ezkvm", "~/.ezkvm", "/etc/ezkvm"]
+            );
             vec![PathBuf::from("/etc/ezkvm/wakiza.yaml")]
         });
 
-        let read_yaml_file = Osal::read_yaml_file_context();
-        read_yaml_file.expect().returning(|n: PathBuf| {
+        let read_file = Osal::read_file_context();
+        read_file.expect().returning(|n: PathBuf| {
             assert_eq!(n, PathBuf::from("/etc/ezkvm/wakiza.yaml"));
-            Ok(Config {
-                general: Default::default(),
-                system: Default::default(),
-                display: Box::new(NoDisplay {}),
-                gpu: Box::new(NoGpu {}),
-                spice: None,
-                host: None,
-                storage: vec![],
-                network: vec![],
-                extras: vec![],
-            })
+            Ok("general:\n  name: wakiza\n".to_string())
         });
 
         let _config = Config::read("wakiza").unwrap();
     }
-}
 
-/// helper function to compare argument lists independent of order
-#[allow(unused)]
-pub fn assert_argument_lists_are_equal(mut actual: Vec<&str>, mut expected: Vec<&str>) {
-    assert_eq!(actual.len(), expected.len());
-    actual.sort();
-    expected.sort();
-    let mut count = 0;
-    while count < actual.len() {
-        assert_eq!(actual[count], expected[count]);
-        count += 1;
+    #[test]
+    #[serial]
+    fn test_config_read_file_parse_error() {
+        let read_file = Osal::read_file_context();
+        read_file
+            .expect()
+            .returning(|_n: PathBuf| Ok("display: { type: vnc }\n".to_string()));
+
+        match Config::read_file(PathBuf::from("/etc/ezkvm/wakiza.yaml")) {
+            Err(OsalError::Config(error)) => {
+                assert_eq!(error.get_path(), "display.type");
+                assert_eq!(error.get_line(), Some(1));
+            }
+            other => panic!("unexpected result {:?}", other.map(|_| ())),
+        }
     }
-}
 
-/// helper function to compare argument options independent of order
-#[allow(unused)]
-pub fn assert_arguments_are_equal(actual: &str, expected: &str) {
-    // arguments take the form '-<argument> [option,...]'
-    // so split them further
-    let actual_split: Vec<String> = actual.split_whitespace().map(str::to_string).collect();
-    let expected_split: Vec<String> = expected.split_whitespace().map(str::to_string).collect();
-    assert_eq!(actual_split.len(), expected_split.len());
+    #[test]
+    #[serial]
+    fn test_config_list_skips_shadowed_names() {
+        let find_files = Osal::find_files_context();
+        find_files.expect().returning(|p: &str, l: Vec<&str>| {
+            assert_eq!(p, "*.yaml");
+            assert_eq!(l, vec!["$XDG_CONFIG_HOME/ezkvm", "~/.ezkvm", "/etc/ezkvm"]);
+            vec![
+                PathBuf::from("/home/user/.ezkvm/wakiza.yaml"),
+                PathBuf::from("/etc/ezkvm/mygeeto.yaml"),
+                PathBuf::from("/etc/ezkvm/wakiza.yaml"),
+            ]
+        });
 
-    // there should be at most one space, so length after splitting must be 1 or 2
-    assert!(actual_split.len() == 1 || actual_split.len() == 2);
+        assert_eq!(
+            Config::list(),
+            vec![
+                (
+                    "wakiza".to_string(),
+                    PathBuf::from("/home/user/.ezkvm/wakiza.yaml")
+                ),
+                (
+                    "mygeeto".to_string(),
+                    PathBuf::from("/etc/ezkvm/mygeeto.yaml")
+                ),
+            ]
+        );
+    }
 
-    // the argument itself must match
-    let actual_left = actual_split.get(0).unwrap().clone();
-    let expected_left = expected_split.get(0).unwrap().clone();
-    assert_eq!(actual_left, expected_left);
+    #[test]
+    #[serial]
+    fn test_pci_addresses_do_not_collide() {
+        let get_machine_types = Osal::get_machine_types_context();
+        get_machine_types
+            .expect()
+            .returning(|_| vec!["pc-q35-8.1".to_string()]);
+        let mut config: Config = serde_yaml::from_str(
+            r#"
+            storage:
+              - { type: "virtio-blk-pci", file: "/dev/vm1/boot" }
+            network:
+              - { type: "bridge", bridge: "vmbr0", mac: "BC:24:11:3A:21:B7" }
+              - { type: "bridge", bridge: "vmbr1", mac: "BC:24:11:3A:21:B8", pci_address: "pci.1:0x0" }
+              - { type: "bridge", bridge: "vmbr2", mac: "BC:24:11:3A:21:B9" }
+        "#,
+        )
+        .unwrap();
+        config.allocate_pci_addresses().unwrap();
 
-    // if there are options, then split them
-    if actual_split.len() == 2 {
-        let mut actual_split: Vec<String> = actual_split
-            .get(1)
-            .unwrap()
-            .split(",")
-            .map(str::to_string)
+        let args = config.get_qemu_args(0);
+        let devices: Vec<&QemuArgument> = args
+            .iter()
+            .filter(|arg| arg.get_flag() == "-device")
+            .filter(|arg| arg.get_value().unwrap_or_default().starts_with("virtio"))
             .collect();
-        let mut expected_split: Vec<String> = expected_split
-            .get(1)
-            .unwrap()
-            .split(",")
-            .map(str::to_string)
+        assert_eq!(
+            devices,
+            vec![
+                &QemuArgument::new("-device", "virtio-blk-pci,drive=drive-virtio0,id=virtio0,bus=pci.0,addr=0x0"),
+                &QemuArgument::new("-device", "virtio-net-pci,id=net0,bus=pci.1,addr=0x1,netdev=netdev0,mac=BC:24:11:3A:21:B7"),
+                &QemuArgument::new("-device", "virtio-net-pci,id=net1,bus=pci.1,addr=0x0,netdev=netdev1,mac=BC:24:11:3A:21:B8"),
+                &QemuArgument::new("-device", "virtio-net-pci,id=net2,bus=pci.1,addr=0x2,netdev=netdev2,mac=BC:24:11:3A:21:B9"),
+            ]
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn test_i440fx() {
+        let mut config: Config = serde_yaml::from_str(
+            r#"
+            system:
+              chipset: { type: "i440fx", version: "7.2" }
+            gpu:
+              type: "passthrough"
+              pci:
+                - { vm_id: "0.0", host_id: "0000:03:00.0", multi_function: true }
+                - { vm_id: "0.1", host_id: "0000:03:00.1" }
+            display: { type: "looking_glass", device: { path: "/dev/kvmfr0", size: "128M" } }
+            spice: { path: "/var/ezkvm/wakiza.sock" }
+        "#,
+        )
+        .unwrap();
+        config.allocate_pci_addresses().unwrap();
+
+        let args = config.get_qemu_args(0);
+        assert!(args.contains(&QemuArgument::new(
+            "-machine",
+            "hpet=off,type=pc-i440fx-7.2"
+        )));
+        for expected in [
+            "ivshmem-plain,memdev=ivshmem0,bus=pci.0",
+            "vfio-pci,host=0000:03:00.0,id=hostpci0.0,bus=pci.3,addr=0x0.0,multifunction=on",
+            "vfio-pci,host=0000:03:00.1,id=hostpci0.1,bus=pci.3,addr=0x0.1",
+            "intel-hda,id=audiodev0,bus=pci.2,addr=0xc",
+        ] {
+            assert!(
+                args.contains(&QemuArgument::new("-device", expected)),
+                "{}",
+                expected
+            );
+        }
+        assert!(!args
+            .iter()
+            .any(|arg| arg.get_value().unwrap_or_default().contains("pcie")));
+
+        // the vga moves out of the slot of the isa bridge
+        let mut config: Config = serde_yaml::from_str(
+            r#"
+            system: { chipset: { type: "i440fx", version: "7.2" } }
+            gpu: { type: "vmware-svga" }
+            storage:
+              - { type: "ide-cd", file: "/vms/winxp.iso" }
+              - { type: "ide-cd", file: "/vms/drivers.iso" }
+              - { type: "ide-cd", file: "/vms/tools.iso" }
+        "#,
+        )
+        .unwrap();
+        config.allocate_pci_addresses().unwrap();
+        assert!(config.get_qemu_args(0).contains(&QemuArgument::new(
+            "-device",
+            "vmware-svga,id=vga,bus=pci.0,addr=0x2"
+        )));
+
+        // the piix3 only has ide.0 and ide.1
+        let path_exists = Osal::path_exists_context();
+        path_exists.expect().returning(|_path: String| true);
+        let get_machine_types = Osal::get_machine_types_context();
+        get_machine_types
+            .expect()
+            .returning(|_| vec!["pc-i440fx-7.2".to_string()]);
+        let validation = Validation::new(config.validate(&config));
+        let errors: Vec<&str> = validation
+            .errors()
+            .iter()
+            .map(|finding| finding.get_path())
             .collect();
+        assert_eq!(errors, vec!["storage[2]"]);
+    }
 
-        // for the first option in the arguments, order may still be relevant, so compare those first, and the rest after sorting
-        assert_eq!(actual_split.get(0).unwrap(), expected_split.get(0).unwrap());
-        actual_split.sort();
-        expected_split.sort();
-        assert_eq!(actual_split.len(), expected_split.len());
+    #[test]
+    #[serial]
+    fn test_microvm() {
+        let mut config: Config = serde_yaml::from_str(
+            r#"
+            general: { name: "runner" }
+            system:
+              chipset: { type: "microvm" }
+              bios: { type: "no_bios" }
+              boot: { kernel: "/vms/ci/vmlinuz", cmdline: "console=ttyS0 root=/dev/vda" }
+            storage:
+              - { type: "virtio-blk-pci", file: "/vms/ci/root.img" }
+              - { type: "scsi-hd", file: "/vms/ci/cache.img" }
+            network:
+              - { type: "bridge", bridge: "vmbr0", mac: "BC:24:11:3A:21:B7" }
+        "#,
+        )
+        .unwrap();
+        config.allocate_pci_addresses().unwrap();
+
+        let args = config.get_qemu_args(0);
+        for expected in [
+            QemuArgument::new("-kernel", "/vms/ci/vmlinuz"),
+            QemuArgument::new("-append", "console=ttyS0 root=/dev/vda"),
+            QemuArgument::new(
+                "-chardev",
+                "socket,id=console,path=/var/ezkvm/runner.console,server=on,wait=off",
+            ),
+            QemuArgument::new("-serial", "chardev:console"),
+            QemuArgument::new(
+                "-device",
+                "virtio-blk-device,drive=drive-virtio0,id=virtio0",
+            ),
+            QemuArgument::new(
+                "-device",
+                "scsi-hd,scsi-id=1,drive=drive-scsi1,id=scsi1,bus=scsihw0.0,rotation_rate=1",
+            ),
+            QemuArgument::new(
+                "-device",
+                "virtio-net-device,id=net0,netdev=netdev0,mac=BC:24:11:3A:21:B7",
+            ),
+        ] {
+            assert!(args.contains(&expected), "{:?}", expected);
+        }
+        assert!(!args
+            .iter()
+            .any(|arg| arg.get_value().unwrap_or_default().contains("pci")));
+
+        // a microvm has no firmware, nor ide buses
+        let config: Config = serde_yaml::from_str(
+            r#"
+            system: { chipset: { type: "microvm" }, boot: { kernel: "/vms/ci/vmlinuz" } }
+            storage: [ { type: "ide-cd", file: "/vms/ci/seed.iso" } ]
+        "#,
+        )
+        .unwrap();
+        let path_exists = Osal::path_exists_context();
+        path_exists.expect().returning(|_path: String| true);
+        let validation = Validation::new(config.validate(&config));
+        let errors: Vec<&str> = validation
+            .errors()
+            .iter()
+            .map(|finding| finding.get_path())
+            .collect();
+        assert_eq!(errors, vec!["system.bios", "storage[0]"]);
+    }
+
+    /// the paths of the errors in a microvm config with the given devices
+    fn microvm_errors(devices: &str) -> Vec<String> {
+        let config: Config = serde_yaml::from_str(&format!(
+            "system: {{ chipset: {{ type: microvm }}, bios: {{ type: no_bios }}, \
+             boot: {{ kernel: /vms/ci/vmlinuz }} }}\n{}",
+            devices
+        ))
+        .unwrap();
+        let path_exists = Osal::path_exists_context();
+        path_exists.expect().returning(|_path: String| true);
+        let get_pci_driver = Osal::get_pci_driver_context();
+        get_pci_driver
+            .expect()
+            .returning(|_host_id| Some("vfio-pci".to_string()));
+        Validation::new(config.validate(&config))
+            .errors()
+            .iter()
+            .map(|finding| finding.get_path().to_string())
+            .collect()
+    }
+
+    #[test]
+    #[serial]
+    fn test_microvm_rejects_spice() {
+        assert_eq!(
+            microvm_errors("spice: { addr: 127.0.0.1, port: 5901 }"),
+            vec!["spice"]
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn test_microvm_rejects_pci_gpus() {
+        assert_eq!(microvm_errors("gpu: { type: virtio-vga-gl }"), vec!["gpu"]);
+        assert_eq!(microvm_errors("gpu: { type: vmware-svga }"), vec!["gpu"]);
+    }
+
+    #[test]
+    #[serial]
+    fn test_microvm_rejects_vfio_passthrough() {
+        assert_eq!(
+            microvm_errors(
+                "gpu: { type: passthrough, pci: [ { vm_id: '0.0', host_id: '0000:01:00.0' } ] }"
+            ),
+            vec!["gpu.pci[0]"]
+        );
+        assert_eq!(
+            microvm_errors("host: { pci: [ { vm_id: '1.0', host_id: '0000:02:00.0' } ] }"),
+            vec!["host.pci[0]"]
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn test_microvm_rejects_pool_resources() {
+        assert_eq!(
+            microvm_errors("resources: [ { tags: [ gpu ] } ]"),
+            vec!["resources[0]"]
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn test_microvm_rejects_usb_host() {
+        assert_eq!(
+            microvm_errors("host: { usb: [ { vm_port: '1', host_port: '2.2', host_bus: '1' } ] }"),
+            vec!["host.usb[0]"]
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn test_values_with_spaces_and_commas() {
+        let get_machine_types = Osal::get_machine_types_context();
+        get_machine_types
+            .expect()
+            .returning(|_| vec!["pc-q35-8.1".to_string()]);
+        let config: Config = serde_yaml::from_str(
+            r#"
+            general: { name: "my vm" }
+            storage:
+              - { type: "scsi-hd", file: "/vms/my disk,1.img" }
+        "#,
+        )
+        .unwrap();
+
+        let args = config.get_qemu_args(0);
+        assert!(args.contains(&QemuArgument::new(
+            "-monitor",
+            "unix:/var/ezkvm/my vm.monitor,server,nowait"
+        )));
+        assert!(args.contains(&QemuArgument::new(
+            "-drive",
+            "file=/vms/my disk,,1.img,if=none,aio=io_uring,id=drive-scsi0,format=raw,cache=none,detect-zeroes=unmap"
+        )));
+
+        let argv = QemuArgument::flatten(&args);
+        assert!(argv.contains(&"unix:/var/ezkvm/my vm.monitor,server,nowait".to_string()));
+    }
+
+    #[test]
+    #[serial]
+    fn test_helper_commands() {
+        let get_machine_types = Osal::get_machine_types_context();
+        get_machine_types
+            .expect()
+            .returning(|_| vec!["pc-q35-8.1".to_string()]);
+        let config: Config = serde_yaml::from_str(
+            r#"
+            system:
+              tpm: { type: "swtpm", disk: "/dev/vm1/vm-108-tpmstate", socket: "/var/ezkvm/vm-108-tpm.socket" }
+            spice: { addr: "127.0.0.1", port: 5900 }
+            display: { type: "remote-viewer", full_screen: true }
+        "#,
+        )
+        .unwrap();
+
+        let command = config.get_qemu_command();
+        assert_eq!(command[0], "qemu-system-x86_64");
+        assert_eq!(
+            command[1..],
+            QemuArgument::flatten(&config.get_qemu_args(0))
+        );
+
+        assert_eq!(
+            config.get_helper_commands(&config),
+            vec![
+                vec![
+                    "swtpm",
+                    "socket",
+                    "--tpmstate",
+                    "backend-uri=file:///dev/vm1/vm-108-tpmstate",
+                    "--ctrl",
+                    "type=unixio,path=/var/ezkvm/vm-108-tpm.socket,mode=0600",
+                    "--terminate",
+                    "--tpm2"
+                ],
+                vec!["remote-viewer", "spice://127.0.0.1:5900", "--full-screen"],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_conflicting_pci_addresses() {
+        let mut config: Config = serde_yaml::from_str(
+            r#"
+            gpu: { type: "virtio-vga-gl" }
+            network:
+              - { type: "bridge", bridge: "vmbr0", pci_address: "pcie.0:0x2" }
+        "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.allocate_pci_addresses(),
+            Err(OsalError::Invalid(Some(
+                "pci address pcie.0:0x2.0 is used more than once".to_string()
+            )))
+        );
+
+        // the root ports of the q35 chipset are in slot 0x1c of pcie.0
+        let mut config: Config = serde_yaml::from_str(
+            r#"
+            network:
+              - { type: "bridge", bridge: "vmbr0", pci_address: "pcie.0:0x1c.5" }
+        "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.allocate_pci_addresses(),
+            Err(OsalError::Invalid(Some(
+                "pci address pcie.0:0x1c.5 is in a slot of the chipset".to_string()
+            )))
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn test_claimed_resources_are_passed_to_qemu() {
+        let get_machine_types = Osal::get_machine_types_context();
+        get_machine_types
+            .expect()
+            .returning(|_| vec!["pc-q35-8.1".to_string()]);
+        let pool: ResourcePool = serde_yaml::from_str(
+            r#"
+            id: x550t2
+            devices:
+              - { id: lan0, tags: [ lan ], parent: enp14s0, vf: "0", pci: [ "0000:0e:10.0" ] }
+              - { id: lan1, tags: [ lan ], parent: enp14s0, vf: "1", pci: [ "0000:0e:10.2" ] }
+              - { id: lan8, tags: [ lan ], parent: enp14s1, vf: "0", pci: [ "0000:0e:10.1" ] }
+        "#,
+        )
+        .unwrap();
+        let lock = Lock::new("mygeeto".to_string(), 12345, vec!["lan0".to_string()]);
+        let mut data_manager = DataManager::from(
+            HashMap::from([("x550t2".to_string(), pool)]),
+            HashMap::from([("mygeeto".to_string(), lock)]),
+        );
+
+        let mut config: Config = serde_yaml::from_str(
+            r#"
+            resources:
+              - { pool: x550t2, tag: lan }
+        "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.claim_resources(&mut data_manager, &[]),
+            Ok(vec!["lan1".to_string()])
+        );
+        config.allocate_pci_addresses().unwrap();
+        assert!(config.get_qemu_args(0).contains(&QemuArgument::new(
+            "-device",
+            "vfio-pci,host=0000:0e:10.2,id=resource0_0,bus=ich9-pcie-port-1,addr=0x0"
+        )));
+
+        // the remaining lan resource is on another parent, and can not be used
+        let mut config: Config = serde_yaml::from_str(
+            r#"
+            resources:
+              - { tags: "lan && !enp14s1" }
+        "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.claim_resources(&mut data_manager, &[]),
+            Err(OsalError::Busy(Some("lan && !enp14s1".to_string())))
+        );
+    }
+
+    #[test]
+    fn test_sticky_resources() {
+        let data_manager = || {
+            let pool: ResourcePool = serde_yaml::from_str(
+                r#"
+                id: p2200
+                devices:
+                  - { id: nvidia12, tags: [ gpu ], pci: [ "0000:12:00.0" ] }
+                  - { id: nvidia43, tags: [ gpu ], pci: [ "0000:43:00.0" ] }
+            "#,
+            )
+            .unwrap();
+            DataManager::from(HashMap::from([("p2200".to_string(), pool)]), HashMap::new())
+        };
+        let history = vec!["nvidia43".to_string()];
+
+        let mut config: Config = serde_yaml::from_str("resources: [ { tags: gpu } ]").unwrap();
+        assert_eq!(
+            config.claim_resources(&mut data_manager(), &history),
+            Ok(vec!["nvidia12".to_string()])
+        );
+
+        let mut config: Config =
+            serde_yaml::from_str("resources: [ { tags: gpu, sticky: true } ]").unwrap();
+        assert_eq!(
+            config.claim_resources(&mut data_manager(), &history),
+            Ok(vec!["nvidia43".to_string()])
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn test_validation() {
+        let config: Config = serde_yaml::from_str(
+            r#"
+            system:
+              bios: { type: "ovmf", file: "/dev/vm1/wakiza-efidisk" }
+              applesmc: { osk: "<put_the_osk_key_here>" }
+            display: { type: "looking_glass", device: { path: "/dev/kvmfr0", size: "128M" } }
+            spice: { path: "/var/ezkvm/wakiza.sock" }
+            host:
+              pci:
+                - { vm_id: "0.0", host_id: "0000:03:00.0" }
+            storage:
+              - { type: "scsi-hd", file: "/dev/vm1/wakiza-boot", boot_index: 0 }
+              - { type: "ide-cd", file: "/isos/missing.iso", boot_index: 0 }
+            network:
+              - { type: "bridge", mac: "BC:24:11:3A:21:B7" }
+              - { type: "bridge", mac: "bc:24:11:3a:21:b7" }
+            "#,
+        )
+        .unwrap();
+
+        let path_exists = Osal::path_exists_context();
+        path_exists
+            .expect()
+            .returning(|path: String| path != "/isos/missing.iso");
+        let get_file_size = Osal::get_file_size_context();
+        get_file_size
+            .expect()
+            .returning(|_path: String| Some(4194304));
+        let find_executable = Osal::find_executable_context();
+        find_executable
+            .expect()
+            .returning(|name: String| Some(PathBuf::from(format!("/usr/bin/{}", name))));
+        let get_pci_driver = Osal::get_pci_driver_context();
+        get_pci_driver
+            .expect()
+            .returning(|_host_id: String| Some("amdgpu".to_string()));
+        let find_files = Osal::find_files_context();
+        find_files
+            .expect()
+            .returning(|_p: &str, _l: Vec<&str>| vec![]);
+
+        let validation = config.validation("wakiza");
+        let errors: Vec<&str> = validation
+            .errors()
+            .iter()
+            .map(|finding| finding.get_path())
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                "system.applesmc.osk",
+                "display",
+                "host.pci[0].host_id",
+                "storage[1].file",
+                "storage[1].boot_index",
+                "network[1].mac",
+            ]
+        );
+        assert!(validation.warnings().is_empty());
+    }
+
+    #[test]
+    #[serial]
+    fn test_validation_of_macs_used_by_other_vms() {
+        let config: Config =
+            serde_yaml::from_str(r#"network: [ { type: "bridge", mac: "BC:24:11:3A:21:B7" } ]"#)
+                .unwrap();
+
+        let find_files = Osal::find_files_context();
+        find_files.expect().returning(|_p: &str, _l: Vec<&str>| {
+            vec![
+                PathBuf::from("/etc/ezkvm/wakiza.yaml"),
+                PathBuf::from("/etc/ezkvm/mygeeto.yaml"),
+            ]
+        });
+        let read_file = Osal::read_file_context();
+        read_file.expect().returning(|path: PathBuf| {
+            assert_eq!(path, PathBuf::from("/etc/ezkvm/mygeeto.yaml"));
+            Ok(r#"network: [ { type: "bridge", mac: "bc:24:11:3a:21:b7" } ]"#.to_string())
+        });
+
+        let validation = config.validation("wakiza");
+        assert_eq!(
+            validation.get_findings(),
+            &vec![Finding::error(
+                "network[0].mac",
+                "BC:24:11:3A:21:B7 is also used by vm mygeeto"
+            )]
+        );
+    }
+
+    #[test]
+    fn test_get_macs_leaves_out_random_macs() {
+        let config: Config = serde_yaml::from_str(
+            r#"
+            network:
+              - { type: "bridge", bridge: "vmbr0" }
+              - { type: "bridge", bridge: "vmbr1", mac: "bc:24:11:3a:21:b7" }
+        "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.get_macs(),
+            vec![(1, "BC:24:11:3A:21:B7".to_string())]
+        );
+        // the random mac stays the same for every argument it ends up in
+        let footer = config.network[0].footer();
+        assert_eq!(footer.get_device_options(0), footer.get_device_options(0));
+        assert_eq!(footer.configured_mac(), None);
+    }
+
+    #[test]
+    #[serial]
+    fn test_validation_of_host_tpm_used_by_other_vms() {
+        let config: Config = serde_yaml::from_str(
+            r#"system: { tpm: { type: "passthrough", path: "/dev/tpmrm0" } }"#,
+        )
+        .unwrap();
+
+        let path_exists = Osal::path_exists_context();
+        path_exists.expect().returning(|_path: String| true);
+        // wakiza was started with --config, under another name than general.name
+        let find_files = Osal::find_files_context();
+        find_files
+            .expect()
+            .returning(|_p: &str, l: Vec<&str>| match l.as_slice() {
+                ["/var/ezkvm/lock"] => vec![
+                    PathBuf::from("/var/ezkvm/lock/wakiza.yaml"),
+                    PathBuf::from("/var/ezkvm/lock/mygeeto.yaml"),
+                ],
+                _ => vec![],
+            });
+        let read_file = Osal::read_file_context();
+        read_file.expect().returning(|path: String| {
+            let name = path
+                .trim_start_matches("/var/ezkvm/lock/")
+                .trim_end_matches(".yaml");
+            Ok(format!(
+                "{{ name: {}, pid: 12345, resources: [ tpm:host ] }}",
+                name
+            ))
+        });
+        let get_process_name = Osal::get_process_name_context();
+        get_process_name
+            .expect()
+            .returning(|_pid| Some("qemu-system-x86".to_string()));
+
+        assert_eq!(config.general().name(), "anonymous");
+        let validation = config.validation("wakiza");
+        assert_eq!(
+            validation.get_findings(),
+            &vec![Finding::error(
+                "system.tpm",
+                "the tpm of the host is in use by vm mygeeto"
+            )]
+        );
     }
 }
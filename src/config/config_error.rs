@@ -0,0 +1,391 @@
+use crate::config::strict::{find_unsupported_features, UnsupportedFeature};
+use colored::Colorize;
+use log::warn;
+use serde_path_to_error::{Path, Segment};
+use serde_yaml::Value;
+use std::fmt;
+use std::path::PathBuf;
+
+type PathError = serde_path_to_error::Error<serde_yaml::Error>;
+
+/// a config file that could not be parsed, with enough context to point at the culprit
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigError {
+    file: PathBuf,
+    line: Option<usize>,
+    column: Option<usize>,
+    path: String,
+    message: String,
+    source_line: Option<String>,
+}
+
+impl ConfigError {
+    /// parses a config, any error is located in the file by line, column and key path;
+    /// unknown keys and yaml features outside the supported subset are errors too
+    pub fn parse<T>(file: PathBuf, content: &str) -> Result<T, Box<ConfigError>>
+    where
+        T: for<'de> serde::Deserialize<'de>,
+    {
+        if let Some(feature) = find_unsupported_features(content).into_iter().next() {
+            return Err(Self::unsupported(file, content, feature));
+        }
+        Self::deserialize(content).map_err(|error| Self::new(file, content, error))
+    }
+
+    /// parses a config like parse does, but only warns about unknown keys and unsupported
+    /// yaml features, the unknown keys are left out
+    pub fn parse_lenient<T>(file: PathBuf, content: &str) -> Result<T, Box<ConfigError>>
+    where
+        T: for<'de> serde::Deserialize<'de>,
+    {
+        for feature in find_unsupported_features(content) {
+            let error = Self::unsupported(file.clone(), content, feature);
+            warn!(
+                "{}:{}: {}",
+                file.display(),
+                error.line.unwrap_or(0),
+                error.message
+            );
+        }
+
+        match Self::deserialize(content) {
+            Ok(result) => return Ok(result),
+            Err(error) if unknown_field(&error).is_none() => {
+                return Err(Self::new(file, content, error))
+            }
+            Err(_) => {}
+        }
+
+        // drop unknown keys one at a time, the yaml value no longer knows where they were
+        let mut value: Value =
+            Self::deserialize(content).map_err(|error| Self::new(file.clone(), content, error))?;
+        loop {
+            let error = match serde_path_to_error::deserialize(value.clone()) {
+                Ok(result) => return Ok(result),
+                Err(error) => error,
+            };
+            match unknown_field(&error) {
+                Some(field) if remove_key(&mut value, error.path(), &field) => warn!(
+                    "{}: ignoring unknown key `{}` (in {})",
+                    file.display(),
+                    field,
+                    error.path()
+                ),
+                _ => return Err(Self::new(file, content, error)),
+            }
+        }
+    }
+
+    fn deserialize<T>(content: &str) -> Result<T, PathError>
+    where
+        T: for<'de> serde::Deserialize<'de>,
+    {
+        serde_path_to_error::deserialize(serde_yaml::Deserializer::from_str(content))
+    }
+
+    fn new(file: PathBuf, content: &str, error: PathError) -> Box<Self> {
+        let path = error.path().to_string();
+        let error = error.into_inner();
+        let location = error.location();
+        let line = location.as_ref().map(|location| location.line());
+        let column = location.as_ref().map(|location| location.column());
+
+        let mut error = ConfigError {
+            file,
+            line,
+            column,
+            path: if path == "." { String::new() } else { path },
+            message: error.to_string(),
+            source_line: source_line(content, line),
+        };
+        // a flattened type is reported on its parent, point at the type field itself
+        if !error.get_variants().is_empty() && !error.path.ends_with("type") {
+            error.path = format!("{}.type", error.path);
+        }
+        Box::new(error)
+    }
+
+    fn unsupported(file: PathBuf, content: &str, feature: UnsupportedFeature) -> Box<Self> {
+        Box::new(ConfigError {
+            file,
+            line: Some(feature.line),
+            column: Some(feature.column),
+            path: String::new(),
+            message: feature.message,
+            source_line: source_line(content, Some(feature.line)),
+        })
+    }
+
+    pub fn get_file(&self) -> &PathBuf {
+        &self.file
+    }
+
+    pub fn get_line(&self) -> Option<usize> {
+        self.line
+    }
+
+    pub fn get_column(&self) -> Option<usize> {
+        self.column
+    }
+
+    /// the dotted key path of the offending item, like `storage[1].type`
+    pub fn get_path(&self) -> &str {
+        &self.path
+    }
+
+    /// the message without the path and location that serde_yaml adds to it
+    pub fn get_message(&self) -> &str {
+        let mut message = match self.message.find(" at line ") {
+            Some(index) => &self.message[..index],
+            None => &self.message,
+        };
+        // typetag items repeat the path, once for the item and once for its contents
+        while let Some((path, rest)) = message.split_once(": ") {
+            if path.contains(' ') {
+                break;
+            }
+            message = rest;
+        }
+        message
+    }
+
+    /// the valid types, when the error is about an unknown type
+    pub fn get_variants(&self) -> Vec<String> {
+        let message = self.get_message();
+        if !message.starts_with("unknown variant") {
+            return vec![];
+        }
+
+        match message.find("expected") {
+            Some(index) => message[index..]
+                .split('`')
+                .skip(1)
+                .step_by(2)
+                .map(str::to_string)
+                .collect(),
+            None => vec![],
+        }
+    }
+}
+
+fn source_line(content: &str, line: Option<usize>) -> Option<String> {
+    line.and_then(|line| content.lines().nth(line.saturating_sub(1)))
+        .map(str::to_string)
+}
+
+/// the name of the key, when the error is about an unknown key
+fn unknown_field(error: &PathError) -> Option<String> {
+    let message = error.inner().to_string();
+    let index = message.find("unknown field `")? + "unknown field `".len();
+    message[index..].split('`').next().map(str::to_string)
+}
+
+/// removes an unknown key, from the item at the end of the path or from the one above it
+fn remove_key(value: &mut Value, path: &Path, field: &str) -> bool {
+    let mut current = value;
+    for segment in path.iter() {
+        current = match segment {
+            Segment::Map { key } if key == field && current.get(field).is_some() => break,
+            Segment::Map { key } if current.get(key).is_some() => current.get_mut(key).unwrap(),
+            Segment::Seq { index } if current.get(index).is_some() => {
+                current.get_mut(index).unwrap()
+            }
+            Segment::Enum { .. } | Segment::Unknown => continue,
+            _ => break,
+        };
+    }
+    match current.as_mapping_mut() {
+        Some(mapping) => mapping.remove(field).is_some(),
+        None => false,
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let variants = self.get_variants();
+        let mut message = match self.get_message().split_once(", expected") {
+            Some((unknown, _)) if !variants.is_empty() => unknown.replace("variant", "type"),
+            _ => self.get_message().to_string(),
+        };
+        if !self.path.is_empty() {
+            message = format!("{} (in {})", message, self.path);
+        }
+        writeln!(f, "{}: {}", "error".red().bold(), message.bold())?;
+
+        let location = match (self.line, self.column) {
+            (Some(line), Some(column)) => format!("{}:{}:{}", self.file.display(), line, column),
+            _ => format!("{}", self.file.display()),
+        };
+        writeln!(f, "  {} {}", "-->".blue().bold(), location)?;
+
+        if let (Some(line), Some(source_line)) = (self.line, &self.source_line) {
+            let number = line.to_string();
+            let gutter = " ".repeat(number.len());
+            writeln!(f, "{} {}", gutter, "|".blue().bold())?;
+            writeln!(
+                f,
+                "{} {} {}",
+                number.blue().bold(),
+                "|".blue().bold(),
+                source_line
+            )?;
+            if let Some(column) = self.column {
+                writeln!(
+                    f,
+                    "{} {} {}{}",
+                    gutter,
+                    "|".blue().bold(),
+                    " ".repeat(column.saturating_sub(1)),
+                    "^".red().bold()
+                )?;
+            }
+        }
+
+        if !variants.is_empty() {
+            writeln!(
+                f,
+                "  {} valid types are: {}",
+                "=".blue().bold(),
+                variants.join(", ")
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use serial_test::serial;
+
+    fn parse_error(content: &str) -> Box<ConfigError> {
+        ConfigError::parse::<Config>(PathBuf::from("/etc/ezkvm/wakiza.yaml"), content).unwrap_err()
+    }
+
+    #[test]
+    fn test_unknown_type() {
+        let error = parse_error(
+            r#"
+system:
+  bios: { type: "seabois" }
+"#,
+        );
+        assert_eq!(error.get_path(), "system.bios.type");
+        assert_eq!(error.get_line(), Some(3));
+        assert!(error.get_variants().contains(&"seabios".to_string()));
+        assert!(error.get_variants().contains(&"ovmf".to_string()));
+    }
+
+    #[test]
+    fn test_invalid_value() {
+        let error = parse_error(
+            r#"
+general:
+  name: wakiza
+storage:
+  - { type: "scsi-hd", file: "/dev/vm/wakiza" }
+  - { type: "nvme", file: "/dev/vm/wakiza2" }
+"#,
+        );
+        assert_eq!(error.get_line(), Some(6));
+        assert_eq!(error.get_path(), "storage[1].type");
+        assert_eq!(
+            error.get_variants(),
+            vec!["ide-cd", "scsi-hd", "virtio-blk-pci"]
+        );
+    }
+
+    #[test]
+    fn test_unknown_keys() {
+        let error = parse_error("system:\n  cpu: { model: host, cores: 4, smt: 2 }\n");
+        assert_eq!(error.get_path(), "system.cpu.smt");
+        assert_eq!(error.get_line(), Some(2));
+        assert!(error.get_message().starts_with("unknown field `smt`"));
+
+        let error = parse_error("system:\n  bios: { type: seabios, file: /dev/vm1/efi }\n");
+        assert_eq!(error.get_path(), "system.bios.file");
+        assert!(error.get_message().starts_with("unknown field `file`"));
+
+        let error = parse_error(
+            r#"
+storage:
+  - { type: "scsi-hd", file: "/dev/vm1/boot", boot_index: 0 }
+  - { type: "scsi-hd", file: "/dev/vm1/data", boot_idx: 1 }
+"#,
+        );
+        assert_eq!(error.get_path(), "storage[1]");
+        assert_eq!(error.get_line(), Some(4));
+        assert!(error.get_message().starts_with("unknown field `boot_idx`"));
+
+        let error = parse_error("spice: { port: 5901, addr: 0.0.0.0, gl: on, rendernode: x }\n");
+        assert_eq!(error.get_path(), "spice");
+        assert!(error
+            .get_message()
+            .starts_with("unknown field `rendernode`"));
+    }
+
+    #[test]
+    fn test_unsupported_yaml() {
+        let error = parse_error("general: &general\n  name: wakiza\n");
+        assert_eq!((error.get_line(), error.get_column()), (Some(1), Some(10)));
+        assert_eq!(
+            error.get_message(),
+            "anchors are not supported in ezkvm configs"
+        );
+
+        let error = parse_error("general: { name: !!str wakiza }\n");
+        assert_eq!(
+            error.get_message(),
+            "tags are not supported in ezkvm configs"
+        );
+
+        let error = parse_error("general:\n  <<: { name: wakiza }\n");
+        assert_eq!((error.get_line(), error.get_column()), (Some(2), Some(3)));
+        assert_eq!(
+            error.get_message(),
+            "merge keys are not supported in ezkvm configs"
+        );
+    }
+
+    #[test]
+    fn test_lenient() {
+        let content = r#"
+strict: false
+general: { name: wakiza, owner: mark }
+system:
+  memory: { max: 4096, hugepages: true }
+storage:
+  - { type: scsi-hd, file: /dev/vm1/boot, boot_idx: 0 }
+"#;
+        let file = PathBuf::from("/etc/ezkvm/wakiza.yaml");
+        let config = ConfigError::parse_lenient::<Config>(file.clone(), content).unwrap();
+        assert_eq!(config.general().name(), "wakiza");
+        assert_eq!(config.storage().len(), 1);
+
+        let error = ConfigError::parse_lenient::<Config>(file, "system: { memory: { max: lots } }")
+            .unwrap_err();
+        assert_eq!(error.get_path(), "system.memory.max");
+    }
+
+    #[test]
+    #[serial]
+    fn test_display() {
+        colored::control::set_override(false);
+        let error = parse_error("system:\n  memory: { max: lots }\n");
+        let text = error.to_string();
+        colored::control::unset_override();
+
+        assert!(text.starts_with("error: "));
+        assert!(text.contains("(in system.memory.max)"));
+        assert!(text.contains("--> /etc/ezkvm/wakiza.yaml:2:"));
+        assert!(text.contains("2 |   memory: { max: lots }"));
+
+        colored::control::set_override(false);
+        let text = parse_error("display: { type: vnc }\n").to_string();
+        colored::control::unset_override();
+
+        assert!(text.starts_with("error: unknown type `vnc` (in display.type)"));
+        assert!(text.contains("= valid types are: "));
+    }
+}
@@ -5,6 +5,8 @@ pub use crate::config::display::gtk::Gtk;
 pub use crate::config::display::looking_glass::LookingGlass;
 #[allow(unused)]
 pub use crate::config::display::no_display::NoDisplay;
+#[allow(unused)]
+pub use crate::config::display::remote_viewer::RemoteViewer;
 
 use crate::config::QemuDevice;
 
@@ -1,29 +1,64 @@
 use crate::config::display::Display;
-use crate::config::types::QemuDevice;
+use crate::config::system::Chipset;
+use crate::config::types::{PciAddress, PciSlot, QemuArgument, QemuDevice};
 use serde::Deserialize;
 
 #[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct Gtk {
     #[serde(default = "default_gl")]
     gl: bool,
+    #[serde(default = "default_audio_pci_address")]
+    audio_pci_address: Option<PciAddress>,
+    #[serde(skip, default = "default_audio_device")]
+    audio_device: &'static str,
 }
 
 pub fn default_gl() -> bool {
     true
 }
 
+pub fn default_audio_pci_address() -> Option<PciAddress> {
+    Some(PciAddress::new("pci.2", 0xc, 0))
+}
+
+pub fn default_audio_device() -> &'static str {
+    "ich9-intel-hda"
+}
+
 impl QemuDevice for Gtk {
-    fn get_qemu_args(&self, _index: usize) -> Vec<String> {
+    fn get_qemu_args(&self, _index: usize) -> Vec<QemuArgument> {
         let gl = if self.gl { ",gl=on" } else { "" };
+        let audio = match &self.audio_pci_address {
+            Some(pci_address) => QemuArgument::new(
+                "-device",
+                format!(
+                    "{},id=audiodev0,{}",
+                    self.audio_device,
+                    pci_address.get_device_options()
+                ),
+            ),
+            None => QemuArgument::new("-device", format!("{},id=audiodev0", self.audio_device)),
+        };
         vec![
-            format!("-display gtk{}", gl),
-            "-audiodev pipewire,id=audiodev0".to_string(),
-            "-device usb-tablet".to_string(),
-            "-device ich9-intel-hda,id=audiodev0,bus=pci.2,addr=0xc".to_string(),
-            "-device hda-duplex,id=audiodev0-codec0,bus=audiodev0.0,cad=0,audiodev=audiodev0"
-                .to_string(),
+            QemuArgument::new("-display", format!("gtk{}", gl)),
+            QemuArgument::new("-audiodev", "pipewire,id=audiodev0"),
+            QemuArgument::new("-device", "usb-tablet"),
+            audio,
+            QemuArgument::new(
+                "-device",
+                "hda-duplex,id=audiodev0-codec0,bus=audiodev0.0,cad=0,audiodev=audiodev0",
+            ),
         ]
     }
+
+    fn use_chipset(&mut self, chipset: &dyn Chipset) {
+        self.audio_device = chipset.get_audio_device();
+    }
+
+    fn get_pci_slots(&mut self) -> Vec<PciSlot<'_>> {
+        vec![PciSlot::new("pci.2", &mut self.audio_pci_address)]
+    }
 }
 
 #[typetag::deserialize(name = "gtk")]
@@ -35,14 +70,20 @@ mod tests {
 
     #[test]
     fn unit_test() {
-        let display = Gtk { gl: true };
-        let expected: Vec<String> = vec![
-            "-display gtk,gl=on".to_string(),
-            "-audiodev pipewire,id=audiodev0".to_string(),
-            "-device usb-tablet".to_string(),
-            "-device ich9-intel-hda,id=audiodev0,bus=pci.2,addr=0xc".to_string(),
-            "-device hda-duplex,id=audiodev0-codec0,bus=audiodev0.0,cad=0,audiodev=audiodev0"
-                .to_string(),
+        let display = Gtk {
+            gl: true,
+            audio_pci_address: default_audio_pci_address(),
+            audio_device: default_audio_device(),
+        };
+        let expected: Vec<QemuArgument> = vec![
+            QemuArgument::new("-display", "gtk,gl=on"),
+            QemuArgument::new("-audiodev", "pipewire,id=audiodev0"),
+            QemuArgument::new("-device", "usb-tablet"),
+            QemuArgument::new("-device", "ich9-intel-hda,id=audiodev0,bus=pci.2,addr=0xc"),
+            QemuArgument::new(
+                "-device",
+                "hda-duplex,id=audiodev0-codec0,bus=audiodev0.0,cad=0,audiodev=audiodev0",
+            ),
         ];
         assert_eq!(display.get_qemu_args(0), expected);
     }
@@ -1,7 +1,11 @@
 use crate::config::display::Display;
-use crate::config::types::QemuDevice;
-use crate::config::{default_when_missing, Config};
-use crate::osal::{Osal, OsalError};
+use crate::config::gpu::PassthroughGpu;
+use crate::config::system::Chipset;
+use crate::config::types::{QemuArgument, QemuDevice};
+use crate::config::{default_when_missing, Config, Finding};
+#[mockall_double::double]
+use crate::osal::Osal;
+use crate::osal::OsalError;
 use derive_getters::Getters;
 use log::{debug, warn};
 use serde::Deserialize;
@@ -10,6 +14,7 @@ use std::process::{Child, Command};
 use crate::config::spice::SpiceSocket;
 
 #[derive(Deserialize, Debug, Getters)]
+#[serde(deny_unknown_fields)]
 pub struct LookingGlass {
     device: Device,
     #[serde(default, deserialize_with = "default_when_missing")]
@@ -58,12 +63,17 @@ impl LookingGlass {
         result
     }
 
+    fn get_lg_client_command(&self, config: &Config) -> Vec<String> {
+        let mut result = vec!["looking-glass-client".to_string()];
+        result.extend(self.get_lg_client_args(config));
+        result
+    }
+
     fn start_lg_client(&self, config: &Config) -> Result<Child, OsalError> {
         let (uid, gid) = config.get_default_uid_and_gid();
         debug!("start_lg_client() uid: {}, gid: {}", uid, gid);
 
-        let mut args = vec!["looking-glass-client".to_string()];
-        args.extend(self.get_lg_client_args(config));
+        let args = self.get_lg_client_command(config);
 
         Osal::execute_command(
             Command::new("/usr/bin/env").args(args).uid(uid).gid(gid),
@@ -73,12 +83,12 @@ impl LookingGlass {
 }
 
 impl QemuDevice for LookingGlass {
-    fn get_qemu_args(&self, index: usize) -> Vec<String> {
+    fn get_qemu_args(&self, index: usize) -> Vec<QemuArgument> {
         let mut result = vec![
-            "-vga none".to_string(),
-            "-nographic".to_string(),
-            "-device virtio-mouse".to_string(),
-            "-device virtio-keyboard".to_string(),
+            QemuArgument::new("-vga", "none"),
+            QemuArgument::flag("-nographic"),
+            QemuArgument::new("-device", "virtio-mouse"),
+            QemuArgument::new("-device", "virtio-keyboard"),
         ];
 
         result.extend(self.device.get_qemu_args(index));
@@ -86,8 +96,34 @@ impl QemuDevice for LookingGlass {
         result
     }
 
+    fn use_chipset(&mut self, chipset: &dyn Chipset) {
+        self.device.use_chipset(chipset);
+    }
+
+    fn get_helper_commands(&self, config: &Config) -> Vec<Vec<String>> {
+        vec![self.get_lg_client_command(config)]
+    }
+
+    fn validate(&self, config: &Config) -> Vec<Finding> {
+        let mut result = vec![];
+        // the frames are captured on a passthrough gpu, which may also come from a resource pool
+        if !config.has_gpu::<PassthroughGpu>() && config.resources().is_empty() {
+            result.push(Finding::error(
+                "",
+                "looking glass needs a passthrough gpu to capture the display from",
+            ));
+        }
+        if Osal::find_executable("looking-glass-client".to_string()).is_none() {
+            result.push(Finding::warning(
+                "",
+                "looking-glass-client is not installed, the display will not open",
+            ));
+        }
+        result
+    }
+
     fn post_start(&self, config: &Config) {
-        match self.start_lg_client(&config) {
+        match self.start_lg_client(config) {
             Ok(_child) => debug!("LookingGlass::post_start() succeeded"),
             Err(_error) => warn!("LookingGlass::post_start() failed"),
         }
@@ -98,30 +134,56 @@ impl QemuDevice for LookingGlass {
 impl Display for LookingGlass {}
 
 #[derive(Debug, Deserialize, PartialEq, Getters)]
+#[serde(deny_unknown_fields)]
 pub struct Device {
     path: String,
     size: String,
+    #[serde(skip, default = "Device::root_bus_default")]
+    root_bus: &'static str,
+}
+
+impl Device {
+    fn root_bus_default() -> &'static str {
+        "pcie.0"
+    }
 }
 
 impl QemuDevice for Device {
-    fn get_qemu_args(&self, index: usize) -> Vec<String> {
+    fn get_qemu_args(&self, index: usize) -> Vec<QemuArgument> {
         vec![
-            format!("-device ivshmem-plain,memdev=ivshmem{},bus=pcie.0", index),
-            format!(
-                "-object memory-backend-file,id=ivshmem{},share=on,mem-path={},size={}",
-                index, self.path, self.size
+            QemuArgument::new(
+                "-device",
+                format!(
+                    "ivshmem-plain,memdev=ivshmem{},bus={}",
+                    index, self.root_bus
+                ),
+            ),
+            QemuArgument::new(
+                "-object",
+                format!(
+                    "memory-backend-file,id=ivshmem{},share=on,mem-path={},size={}",
+                    index,
+                    QemuArgument::escape(&self.path),
+                    self.size
+                ),
             ),
         ]
     }
+
+    fn use_chipset(&mut self, chipset: &dyn Chipset) {
+        self.root_bus = chipset.get_root_bus();
+    }
 }
 
 #[derive(Debug, Deserialize, PartialEq, Getters)]
+#[serde(deny_unknown_fields)]
 pub struct Window {
     size: String,
     full_screen: bool,
 }
 
 #[derive(Debug, Deserialize, PartialEq, Getters)]
+#[serde(deny_unknown_fields)]
 pub struct Input {
     grab_keyboard: bool,
     escape_key: String,
@@ -137,17 +199,21 @@ mod tests {
             device: Device {
                 path: "".to_string(),
                 size: "".to_string(),
+                root_bus: Device::root_bus_default(),
             },
             window: None,
             input: None,
         };
-        let expected: Vec<String> = vec![
-            "-vga none".to_string(),
-            "-nographic".to_string(),
-            "-device virtio-mouse".to_string(),
-            "-device virtio-keyboard".to_string(),
-            "-device ivshmem-plain,memdev=ivshmem0,bus=pcie.0".to_string(),
-            "-object memory-backend-file,id=ivshmem0,share=on,mem-path=,size=".to_string(),
+        let expected: Vec<QemuArgument> = vec![
+            QemuArgument::new("-vga", "none"),
+            QemuArgument::flag("-nographic"),
+            QemuArgument::new("-device", "virtio-mouse"),
+            QemuArgument::new("-device", "virtio-keyboard"),
+            QemuArgument::new("-device", "ivshmem-plain,memdev=ivshmem0,bus=pcie.0"),
+            QemuArgument::new(
+                "-object",
+                "memory-backend-file,id=ivshmem0,share=on,mem-path=,size=",
+            ),
         ];
         assert_eq!(display.get_qemu_args(0), expected);
     }
@@ -1,8 +1,9 @@
 use crate::config::display::Display;
-use crate::config::types::QemuDevice;
+use crate::config::types::{QemuArgument, QemuDevice};
 use serde::Deserialize;
 
 #[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct NoDisplay {}
 impl NoDisplay {
     pub fn boxed_default() -> Box<Self> {
@@ -11,7 +12,7 @@ impl NoDisplay {
 }
 
 impl QemuDevice for NoDisplay {
-    fn get_qemu_args(&self, _index: usize) -> Vec<String> {
+    fn get_qemu_args(&self, _index: usize) -> Vec<QemuArgument> {
         vec![]
     }
 }
@@ -26,7 +27,7 @@ mod tests {
     #[test]
     fn unit_test() {
         let display = NoDisplay {};
-        let expected: Vec<String> = vec![];
+        let expected: Vec<QemuArgument> = vec![];
         assert_eq!(display.get_qemu_args(0), expected);
     }
 }
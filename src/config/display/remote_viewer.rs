@@ -1,21 +1,24 @@
 use crate::config::display::Display;
-use crate::config::types::QemuDevice;
-use crate::config::{Config};
-use crate::osal::{Osal, OsalError};
+use crate::config::types::{QemuArgument, QemuDevice};
+use crate::config::{Config, Finding};
+#[mockall_double::double]
+use crate::osal::Osal;
+use crate::osal::OsalError;
 use derive_getters::Getters;
 use log::{debug, warn};
 use serde::Deserialize;
 use std::os::unix::prelude::CommandExt;
 use std::process::{Child, Command};
-use crate::config::spice::SpiceSocket;
 
 fn yes() -> bool { true }
 #[derive(Deserialize, Debug, Getters)]
+#[serde(deny_unknown_fields)]
 pub struct RemoteViewer {
     #[serde(default="yes")]
     auto_resize: bool,
     #[serde(default)]
     full_screen: bool,
+    #[allow(dead_code)]
     render_node: Option<String>
     // cursor
     // hotkeys
@@ -25,22 +28,8 @@ pub struct RemoteViewer {
 impl RemoteViewer {
     fn get_args(&self, config: &Config) -> Vec<String> {
         let mut result = vec![];
-        match config.spice() {
-            None => {},
-            Some(spice) => {
-                match spice.socket() {
-                    SpiceSocket::TcpPort { addr, port } => {
-                        result.extend(vec![
-                            format!("spice://{}:{}", addr, port)
-                        ])
-                    },
-                    SpiceSocket::UnixSocket { path, .. } => {
-                        result.extend(vec![
-                            format!("spice+unix://{}", path)
-                        ])
-                    }
-                }
-            }
+        if let Some(spice) = config.spice() {
+            result.push(spice.uri());
         }
 
         if !*self.auto_resize() {
@@ -53,12 +42,17 @@ impl RemoteViewer {
         result
     }
 
+    fn get_command(&self, config: &Config) -> Vec<String> {
+        let mut result = vec!["remote-viewer".to_string()];
+        result.extend(self.get_args(config));
+        result
+    }
+
     fn start(&self, config: &Config) -> Result<Child, OsalError> {
         let (uid, gid) = config.get_default_uid_and_gid();
         debug!("start() uid: {}, gid: {}", uid, gid);
 
-        let mut args = vec!["remote-viewer".to_string()];
-        args.extend(self.get_args(config));
+        let args = self.get_command(config);
 
         Osal::execute_command(
             Command::new("/usr/bin/env").args(args).uid(uid).gid(gid),
@@ -68,12 +62,30 @@ impl RemoteViewer {
 }
 
 impl QemuDevice for RemoteViewer {
-    fn get_qemu_args(&self, _index: usize) -> Vec<String> {
+    fn get_qemu_args(&self, _index: usize) -> Vec<QemuArgument> {
         vec![]
     }
 
+    fn get_helper_commands(&self, config: &Config) -> Vec<Vec<String>> {
+        vec![self.get_command(config)]
+    }
+
+    fn validate(&self, config: &Config) -> Vec<Finding> {
+        let mut result = vec![];
+        if config.spice().is_none() {
+            result.push(Finding::error(
+                "",
+                "remote-viewer needs a spice section to connect to",
+            ));
+        }
+        if Osal::find_executable("remote-viewer".to_string()).is_none() {
+            result.push(Finding::error("", "remote-viewer is not installed"));
+        }
+        result
+    }
+
     fn post_start(&self, config: &Config) {
-        match self.start(&config) {
+        match self.start(config) {
             Ok(_child) => debug!("RemoteViewer::post_start() succeeded"),
             Err(_error) => warn!("RemoteViewer::post_start() failed"),
         }
@@ -95,7 +107,7 @@ mod tests {
             full_screen: true,
             render_node: None,
         };
-        let expected: Vec<String> = vec![];
+        let expected: Vec<QemuArgument> = vec![];
         assert_eq!(display.get_qemu_args(0), expected);
 
         let expected: Vec<String> = vec!["--full-screen".to_string()];
@@ -108,7 +120,7 @@ mod tests {
             full_screen: false,
             render_node: Some("/dev/dri/renderD128".to_string()),
         };
-        let expected: Vec<String> = vec![];
+        let expected: Vec<QemuArgument> = vec![];
         assert_eq!(display.get_qemu_args(0), expected);
 
         let expected: Vec<String> = vec!["spice://127.0.0.1:5900".to_string(),"--auto-resize=never".to_string()];
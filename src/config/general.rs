@@ -1,8 +1,9 @@
-use crate::config::QemuDevice;
+use crate::config::{QemuArgument, QemuDevice};
 use derive_getters::Getters;
 use serde::Deserialize;
 
 #[derive(Debug, Deserialize, Getters)]
+#[serde(deny_unknown_fields)]
 pub struct General {
     name: String,
     uuid: Option<String>,
@@ -16,23 +17,71 @@ impl Default for General {
     }
 }
 
+impl General {
+    pub fn qmp_socket(&self) -> String {
+        format!("/var/ezkvm/{}.qmp", self.name)
+    }
+
+    /// where a hibernated vm keeps its state until the next start
+    pub fn state_file(&self) -> String {
+        format!("/var/ezkvm/{}.state", self.name)
+    }
+
+    /// where the serial console of the vm can be reached, for chipsets that have one
+    pub fn console_socket(&self) -> String {
+        format!("/var/ezkvm/{}.console", self.name)
+    }
+
+    pub fn get_serial_console_args(&self) -> Vec<QemuArgument> {
+        vec![
+            QemuArgument::new(
+                "-chardev",
+                format!(
+                    "socket,id=console,path={},server=on,wait=off",
+                    QemuArgument::escape(&self.console_socket())
+                ),
+            ),
+            QemuArgument::new("-serial", "chardev:console"),
+        ]
+    }
+
+    /// where helper processes that ezkvm manages for the vm keep their sockets
+    pub fn runtime_dir(&self) -> String {
+        format!("/var/ezkvm/run/{}", self.name)
+    }
+
+    /// the resources claimed by the last start, used by sticky resource requests
+    pub fn resource_history_file(&self) -> String {
+        format!("/var/ezkvm/{}.resources", self.name)
+    }
+}
+
 impl QemuDevice for General {
-    fn get_qemu_args(&self, _index: usize) -> Vec<String> {
+    fn get_qemu_args(&self, _index: usize) -> Vec<QemuArgument> {
         vec![
-            "-accel kvm".to_string(),
-            //"-daemonize".to_string(),
-            "-nodefaults".to_string(),
-            format!(
-                "-monitor unix:/var/ezkvm/{}.monitor,server,nowait",
-                self.name
+            QemuArgument::new("-accel", "kvm"),
+            //QemuArgument::flag("-daemonize"),
+            QemuArgument::flag("-nodefaults"),
+            QemuArgument::new(
+                "-monitor",
+                format!(
+                    "unix:/var/ezkvm/{}.monitor,server,nowait",
+                    QemuArgument::escape(&self.name)
+                ),
+            ),
+            QemuArgument::new(
+                "-chardev",
+                format!(
+                    "socket,id=qmp,path={},server=on,wait=off",
+                    QemuArgument::escape(&self.qmp_socket())
+                ),
             ),
-            format!(
-                "-chardev socket,id=qmp,path=/var/ezkvm/{}.qmp,server=on,wait=off",
-                self.name
+            QemuArgument::new("-mon", "chardev=qmp,mode=control"),
+            QemuArgument::new(
+                "-chardev",
+                "socket,id=qmp-event,path=/var/run/qmeventd.sock,reconnect=5",
             ),
-            "-mon chardev=qmp,mode=control".to_string(),
-            "-chardev socket,id=qmp-event,path=/var/run/qmeventd.sock,reconnect=5".to_string(),
-            "-mon chardev=qmp-event,mode=control".to_string(),
+            QemuArgument::new("-mon", "chardev=qmp-event,mode=control"),
         ]
     }
 }
@@ -6,6 +6,7 @@ pub use crate::config::gpu::passthrough_gpu::PassthroughGpu;
 pub use crate::config::gpu::virtio_vga_gl::VirtioVgaGl;
 
 use crate::config::QemuDevice;
+use std::any::Any;
 
 mod no_gpu;
 mod passthrough_gpu;
@@ -13,7 +14,7 @@ mod virtio_vga_gl;
 mod vmware_svga;
 
 #[typetag::deserialize(tag = "type")]
-pub trait Gpu: QemuDevice {}
+pub trait Gpu: 'static + Any + QemuDevice {}
 impl Default for Box<dyn Gpu> {
     fn default() -> Self {
         NoGpu::boxed_default()
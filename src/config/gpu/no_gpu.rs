@@ -1,8 +1,9 @@
 use crate::config::gpu::Gpu;
-use crate::config::types::QemuDevice;
+use crate::config::types::{QemuArgument, QemuDevice};
 use serde::Deserialize;
 
 #[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct NoGpu {}
 impl NoGpu {
     pub fn boxed_default() -> Box<Self> {
@@ -11,7 +12,7 @@ impl NoGpu {
 }
 
 impl QemuDevice for NoGpu {
-    fn get_qemu_args(&self, _index: usize) -> Vec<String> {
+    fn get_qemu_args(&self, _index: usize) -> Vec<QemuArgument> {
         vec![]
     }
 }
@@ -26,7 +27,7 @@ mod tests {
     #[test]
     fn unit_test() {
         let no_gpu = NoGpu {};
-        let expected: Vec<String> = vec![];
+        let expected: Vec<QemuArgument> = vec![];
         assert_eq!(no_gpu.get_qemu_args(0), expected);
     }
 }
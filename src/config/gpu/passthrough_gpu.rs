@@ -1,15 +1,17 @@
 use crate::config::gpu::Gpu;
-use crate::config::types::Pci;
-use crate::config::types::QemuDevice;
+use crate::config::types::{Pci, PciSlot};
+use crate::config::types::{QemuArgument, QemuDevice};
+use crate::config::{within, Config, Finding};
 use serde::Deserialize;
 
 #[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
 pub struct PassthroughGpu {
     pci: Vec<Pci>,
 }
 
 impl QemuDevice for PassthroughGpu {
-    fn get_qemu_args(&self, _index: usize) -> Vec<String> {
+    fn get_qemu_args(&self, _index: usize) -> Vec<QemuArgument> {
         let mut result = vec![];
 
         for (index, item) in self.pci.iter().enumerate() {
@@ -18,6 +20,21 @@ impl QemuDevice for PassthroughGpu {
 
         result
     }
+
+    fn get_pci_slots(&mut self) -> Vec<PciSlot<'_>> {
+        self.pci
+            .iter_mut()
+            .flat_map(|item| item.get_pci_slots())
+            .collect()
+    }
+
+    fn validate(&self, config: &Config) -> Vec<Finding> {
+        let mut result = vec![];
+        for (index, item) in self.pci.iter().enumerate() {
+            result.extend(within(&format!("pci[{}]", index), item.validate(config)));
+        }
+        result
+    }
 }
 
 #[typetag::deserialize(name = "passthrough")]
@@ -30,7 +47,7 @@ mod tests {
     #[test]
     fn unit_test() {
         let gpu = PassthroughGpu { pci: vec![] };
-        let expected: Vec<String> = vec![];
+        let expected: Vec<QemuArgument> = vec![];
         assert_eq!(gpu.get_qemu_args(0), expected);
     }
 }
@@ -1,23 +1,42 @@
 use crate::config::gpu::Gpu;
-use crate::config::types::QemuDevice;
+use crate::config::types::{PciAddress, PciSlot, QemuArgument, QemuDevice};
+use crate::config::{Config, Finding};
 use serde::Deserialize;
 
 #[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct VirtioVgaGl {
     #[serde(default = "default_pci_address")]
-    pci_address: String,
+    pci_address: Option<PciAddress>,
 }
 
-fn default_pci_address() -> String {
-    "0x2".to_string()
+fn default_pci_address() -> Option<PciAddress> {
+    Some(PciAddress::new("pcie.0", 0x2, 0))
 }
 
 impl QemuDevice for VirtioVgaGl {
-    fn get_qemu_args(&self, _index: usize) -> Vec<String> {
-        vec![format!(
-            "-device virtio-vga-gl,id=vga,bus=pcie.0,addr={}",
-            self.pci_address
-        )]
+    fn get_qemu_args(&self, _index: usize) -> Vec<QemuArgument> {
+        match &self.pci_address {
+            Some(pci_address) => vec![QemuArgument::new(
+                "-device",
+                format!("virtio-vga-gl,id=vga,{}", pci_address.get_device_options()),
+            )],
+            None => vec![QemuArgument::new("-device", "virtio-vga-gl,id=vga")],
+        }
+    }
+
+    fn get_pci_slots(&mut self) -> Vec<PciSlot<'_>> {
+        vec![PciSlot::new("pcie.0", &mut self.pci_address)]
+    }
+
+    fn validate(&self, config: &Config) -> Vec<Finding> {
+        match config.system().chipset().has_pci_bus() {
+            true => vec![],
+            false => vec![Finding::error(
+                "",
+                "the chipset has no pci bus for virtio-vga-gl",
+            )],
+        }
     }
 }
 
@@ -31,10 +50,12 @@ mod tests {
     #[test]
     fn unit_test() {
         let gpu = VirtioVgaGl {
-            pci_address: "0x2".to_string(),
+            pci_address: Some("0x2".parse().unwrap()),
         };
-        let expected: Vec<String> =
-            vec!["-device virtio-vga-gl,id=vga,bus=pcie.0,addr=0x2".to_string()];
+        let expected: Vec<QemuArgument> = vec![QemuArgument::new(
+            "-device",
+            "virtio-vga-gl,id=vga,bus=pcie.0,addr=0x2",
+        )];
         assert_eq!(gpu.get_qemu_args(0), expected);
     }
 }
@@ -1,23 +1,50 @@
 use crate::config::gpu::Gpu;
-use crate::config::types::QemuDevice;
+use crate::config::system::Chipset;
+use crate::config::types::{PciAddress, PciSlot, QemuArgument, QemuDevice};
+use crate::config::{Config, Finding};
 use serde::Deserialize;
 
 #[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct VmwareSvga {
     #[serde(default = "default_pci_address")]
-    pci_address: String,
+    pci_address: Option<PciAddress>,
 }
 
-fn default_pci_address() -> String {
-    "0x1".to_string()
+fn default_pci_address() -> Option<PciAddress> {
+    Some(PciAddress::new("pcie.0", 0x1, 0))
 }
 
 impl QemuDevice for VmwareSvga {
-    fn get_qemu_args(&self, _index: usize) -> Vec<String> {
-        vec![format!(
-            "-device vmware-svga,id=vga,bus=pcie.0,addr={}",
-            self.pci_address
-        )]
+    fn get_qemu_args(&self, _index: usize) -> Vec<QemuArgument> {
+        match &self.pci_address {
+            Some(pci_address) => vec![QemuArgument::new(
+                "-device",
+                format!("vmware-svga,id=vga,{}", pci_address.get_device_options()),
+            )],
+            None => vec![QemuArgument::new("-device", "vmware-svga,id=vga")],
+        }
+    }
+
+    fn use_chipset(&mut self, chipset: &dyn Chipset) {
+        // on a pc machine the isa bridge sits in slot 1, qemu puts the vga in slot 2 there
+        if chipset.get_root_bus() != "pcie.0" && self.pci_address == default_pci_address() {
+            self.pci_address = Some(PciAddress::new(chipset.get_root_bus(), 0x2, 0));
+        }
+    }
+
+    fn get_pci_slots(&mut self) -> Vec<PciSlot<'_>> {
+        vec![PciSlot::new("pcie.0", &mut self.pci_address)]
+    }
+
+    fn validate(&self, config: &Config) -> Vec<Finding> {
+        match config.system().chipset().has_pci_bus() {
+            true => vec![],
+            false => vec![Finding::error(
+                "",
+                "the chipset has no pci bus for vmware-svga",
+            )],
+        }
     }
 }
 
@@ -31,10 +58,12 @@ mod tests {
     #[test]
     fn unit_test() {
         let gpu = VmwareSvga {
-            pci_address: "0x2".to_string(),
+            pci_address: Some("0x2".parse().unwrap()),
         };
-        let expected: Vec<String> =
-            vec!["-device vmware-svga,id=vga,bus=pcie.0,addr=0x2".to_string()];
+        let expected: Vec<QemuArgument> = vec![QemuArgument::new(
+            "-device",
+            "vmware-svga,id=vga,bus=pcie.0,addr=0x2",
+        )];
         assert_eq!(gpu.get_qemu_args(0), expected);
     }
 }
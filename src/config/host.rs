@@ -1,8 +1,10 @@
 use crate::config::default_when_missing;
-use crate::config::{Pci, QemuDevice, Usb};
+use crate::config::types::{PciSlot, QemuArgument};
+use crate::config::{within, Config, Finding, Pci, QemuDevice, Usb};
 use serde::Deserialize;
 
 #[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Host {
     #[serde(default, deserialize_with = "default_when_missing")]
     pci: Vec<Pci>,
@@ -11,7 +13,7 @@ pub struct Host {
 }
 
 impl QemuDevice for Host {
-    fn get_qemu_args(&self, _index: usize) -> Vec<String> {
+    fn get_qemu_args(&self, _index: usize) -> Vec<QemuArgument> {
         let mut result = vec![];
 
         for (index, item) in self.pci.iter().enumerate() {
@@ -24,4 +26,22 @@ impl QemuDevice for Host {
 
         result
     }
+
+    fn get_pci_slots(&mut self) -> Vec<PciSlot<'_>> {
+        self.pci
+            .iter_mut()
+            .flat_map(|item| item.get_pci_slots())
+            .collect()
+    }
+
+    fn validate(&self, config: &Config) -> Vec<Finding> {
+        let mut result = vec![];
+        for (index, item) in self.pci.iter().enumerate() {
+            result.extend(within(&format!("pci[{}]", index), item.validate(config)));
+        }
+        for (index, item) in self.usb.iter().enumerate() {
+            result.extend(within(&format!("usb[{}]", index), item.validate(config)));
+        }
+        result
+    }
 }
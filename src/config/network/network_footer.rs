@@ -1,18 +1,31 @@
+use crate::config::system::Chipset;
+use crate::config::types::{PciAddress, PciSlot};
+use derive_getters::Getters;
 use rand::Rng;
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Getters)]
+#[serde(deny_unknown_fields)]
 pub struct NetworkFooter {
-    // bus=pci.<#n>,
-    // addr=<#>.
-    #[serde(default = "NetworkFooter::mac_default")]
-    mac: String,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pci_address: Option<PciAddress>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[getter(skip)]
+    mac: Option<String>,
+    /// used when no mac is configured, drawn once so every argument gets the same one
+    #[serde(skip, default = "NetworkFooter::mac_default")]
+    #[getter(skip)]
+    random_mac: String,
     #[serde(default)]
     #[serde(skip_serializing_if = "Vec::is_empty")]
     extra_netdev_options: Vec<String>,
     #[serde(default)]
     #[serde(skip_serializing_if = "Vec::is_empty")]
     extra_device_options: Vec<String>,
+    #[serde(skip, default = "NetworkFooter::pci_default")]
+    pci: bool,
 }
 
 impl NetworkFooter {
@@ -25,6 +38,23 @@ impl NetworkFooter {
         result.join(":")
     }
 
+    pub fn mac(&self) -> &String {
+        self.mac.as_ref().unwrap_or(&self.random_mac)
+    }
+
+    /// the mac address from the config, if any
+    pub fn configured_mac(&self) -> Option<&String> {
+        self.mac.as_ref()
+    }
+
+    pub fn pci_default() -> bool {
+        true
+    }
+
+    pub fn use_chipset(&mut self, chipset: &dyn Chipset) {
+        self.pci = chipset.has_pci_bus();
+    }
+
     pub fn get_netdev_options(&self, index: usize) -> Vec<String> {
         let mut result = vec![format!("id=netdev{}", index)];
         result.extend(self.extra_netdev_options.clone());
@@ -32,11 +62,22 @@ impl NetworkFooter {
     }
 
     pub fn get_device_options(&self, index: usize) -> Vec<String> {
-        let mut result = vec![
+        let mut result = vec![];
+        if let Some(pci_address) = &self.pci_address {
+            result.push(pci_address.get_device_options());
+        }
+        result.extend(vec![
             format!("netdev=netdev{}", index),
-            format!("mac={}", self.mac),
-        ];
+            format!("mac={}", self.mac()),
+        ]);
         result.extend(self.extra_device_options.clone());
         result
     }
+
+    pub fn get_pci_slots(&mut self) -> Vec<PciSlot<'_>> {
+        match self.pci {
+            true => vec![PciSlot::new("pci.1", &mut self.pci_address)],
+            false => vec![],
+        }
+    }
 }
@@ -2,41 +2,63 @@ use crate::config::network::network_footer::NetworkFooter;
 
 use crate::config::network::network_header::NetworkHeader;
 use crate::config::network::network_payload::NetworkPayload;
+use crate::config::strict::{deserialize_mapping, from_mapping, split_mapping, struct_fields};
+use crate::config::system::Chipset;
+use crate::config::types::{PciSlot, QemuArgument};
 use crate::config::{Config, QemuDevice};
+use crate::osal::OsalError;
 use derive_getters::Getters;
-use serde::Deserialize;
+use serde::{Deserialize, Deserializer};
 
-#[derive(Deserialize, Debug, Getters)]
+#[derive(Debug, Getters)]
 pub struct NetworkItem {
-    #[serde(flatten)]
     header: NetworkHeader,
-    #[serde(flatten)]
     payload: Box<dyn NetworkPayload>,
-    #[serde(flatten)]
     footer: NetworkFooter,
 }
 
+impl<'de> Deserialize<'de> for NetworkItem {
+    /// the header and footer are flattened into the item, all other keys belong to the payload
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserialize_mapping(deserializer, |mapping| {
+            let ([header, footer], payload) = split_mapping(
+                mapping,
+                [
+                    struct_fields::<NetworkHeader>(),
+                    struct_fields::<NetworkFooter>(),
+                ],
+            );
+            Ok(Self {
+                header: from_mapping(header)?,
+                payload: from_mapping(payload)?,
+                footer: from_mapping(footer)?,
+            })
+        })
+    }
+}
+
 impl QemuDevice for NetworkItem {
-    fn pre_start(&self, config: &Config) {
-        self.payload.pre_start(&self, config);
+    fn pre_start(&self, config: &Config) -> Result<(), OsalError> {
+        self.payload.pre_start(self, config);
+        Ok(())
     }
     fn post_start(&self, config: &Config) {
-        self.payload.post_start(&self, config);
+        self.payload.post_start(self, config);
     }
     fn pre_stop(&self, config: &Config) {
-        self.payload.pre_stop(&self, config);
+        self.payload.pre_stop(self, config);
     }
     fn post_stop(&self, config: &Config) {
-        self.payload.post_stop(&self, config);
+        self.payload.post_stop(self, config);
     }
     fn pre_hibernate(&self, config: &Config) {
-        self.payload.pre_hibernate(&self, config);
+        self.payload.pre_hibernate(self, config);
     }
     fn post_hibernate(&self, config: &Config) {
-        self.payload.post_hibernate(&self, config);
+        self.payload.post_hibernate(self, config);
     }
 
-    fn get_qemu_args(&self, index: usize) -> Vec<String> {
+    fn get_qemu_args(&self, index: usize) -> Vec<QemuArgument> {
         let mut netdev_args: Vec<String> = vec![];
         netdev_args.extend(self.header().get_netdev_options());
         netdev_args.extend(self.payload().get_netdev_options(index));
@@ -48,13 +70,22 @@ impl QemuDevice for NetworkItem {
         device_args.extend(self.footer().get_device_options(index));
 
         let mut result = vec![];
-        if netdev_args.len() > 0 {
-            result.push(format!("-netdev {}", netdev_args.join(",")));
+        if !netdev_args.is_empty() {
+            result.push(QemuArgument::new("-netdev", netdev_args.join(",")));
         }
-        if device_args.len() > 0 {
-            result.push(format!("-device {}", device_args.join(",")));
+        if !device_args.is_empty() {
+            result.push(QemuArgument::new("-device", device_args.join(",")));
         }
 
         result
     }
+
+    fn use_chipset(&mut self, chipset: &dyn Chipset) {
+        self.payload.use_chipset(chipset);
+        self.footer.use_chipset(chipset);
+    }
+
+    fn get_pci_slots(&mut self) -> Vec<PciSlot<'_>> {
+        self.footer.get_pci_slots()
+    }
 }
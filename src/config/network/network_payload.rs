@@ -1,4 +1,5 @@
 use crate::config::network::NetworkItem;
+use crate::config::system::Chipset;
 use crate::config::Config;
 use std::fmt::Debug;
 
@@ -10,6 +11,7 @@ pub trait NetworkPayload: Debug {
     fn post_stop(&self, _parent: &NetworkItem, _config: &Config) {}
     fn pre_hibernate(&self, _parent: &NetworkItem, _config: &Config) {}
     fn post_hibernate(&self, _parent: &NetworkItem, _config: &Config) {}
+    fn use_chipset(&mut self, _chipset: &dyn Chipset) {}
     fn get_netdev_options(&self, _index: usize) -> Vec<String> {
         vec![]
     }
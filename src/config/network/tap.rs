@@ -1,9 +1,12 @@
 use crate::config::network::network_payload::NetworkPayload;
+use crate::config::system::{virtio_mmio_device, Chipset};
+use crate::config::QemuArgument;
 use crate::required_value_getter;
 use paste::paste;
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct Tap {
     #[serde(default = "Tap::ifname_default")]
     ifname: String,
@@ -27,18 +30,23 @@ impl Tap {
 
 #[typetag::deserialize(name = "tap")]
 impl NetworkPayload for Tap {
+    fn use_chipset(&mut self, chipset: &dyn Chipset) {
+        if !chipset.has_pci_bus() {
+            self.driver = virtio_mmio_device(&self.driver);
+        }
+    }
+
     fn get_netdev_options(&self, _index: usize) -> Vec<String> {
         vec![format!(
             "type=tap,script={},downscript={},vhost={}",
-            self.upscript, self.downscript, self.vhost
+            QemuArgument::escape(&self.upscript),
+            QemuArgument::escape(&self.downscript),
+            self.vhost
         )]
     }
 
     fn get_device_options(&self, index: usize) -> Vec<String> {
-        vec![format!(
-            "{},id=net{},bus=pci.1,addr=0x0",
-            self.driver, index
-        )]
+        vec![format!("{},id=net{}", self.driver, index)]
     }
 }
 
@@ -61,7 +69,7 @@ mod tests {
         ];
         assert_eq!(expected_netdev_options, network.get_netdev_options(0));
 
-        let expected_device_options = vec!["virtio-net-pci,id=net0,bus=pci.1,addr=0x0".to_string()];
+        let expected_device_options = vec!["virtio-net-pci,id=net0".to_string()];
         assert_eq!(expected_device_options, network.get_device_options(0));
     }
 
@@ -81,7 +89,7 @@ mod tests {
         ];
         assert_eq!(expected_netdev_options, network.get_netdev_options(3));
 
-        let expected_device_options = vec!["ne2000,id=net3,bus=pci.1,addr=0x0".to_string()];
+        let expected_device_options = vec!["ne2000,id=net3".to_string()];
         assert_eq!(expected_device_options, network.get_device_options(3));
     }
 }
@@ -6,6 +6,7 @@ use serde::{Deserialize, Serialize};
 use crate::config::network::NetworkItem;
 
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct X550vf {
     #[serde(default = "X550vf::parent_default")]
     parent: String,
@@ -29,7 +30,7 @@ impl NetworkPayload for X550vf {
 
     fn get_device_options(&self, index: usize) -> Vec<String> {
         vec![format!(
-            "vfio-pci,id=net{},host={},bus=pci.1,addr=0x0,rombar=0",
+            "vfio-pci,id=net{},host={},rombar=0",
             index, self.pci
         )]
     }
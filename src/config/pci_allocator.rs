@@ -0,0 +1,292 @@
+use crate::config::types::{PciAddress, PciSlot};
+use crate::osal::OsalError;
+use log::debug;
+use std::collections::HashSet;
+use std::ops::RangeInclusive;
+
+/// a bus that the chipset provides, on which devices can be placed
+#[derive(Debug, Clone, PartialEq)]
+pub struct PciBus {
+    name: String,
+    slots: RangeInclusive<u8>,
+    reserved: Vec<u8>,
+    aliases: Vec<String>,
+}
+
+impl PciBus {
+    pub fn new(name: &str, slots: RangeInclusive<u8>) -> Self {
+        Self {
+            name: name.to_string(),
+            slots,
+            reserved: vec![],
+            aliases: vec![],
+        }
+    }
+
+    /// slots that are taken by devices the chipset itself adds
+    pub fn with_reserved(self, reserved: Vec<u8>) -> Self {
+        Self { reserved, ..self }
+    }
+
+    /// other names for this bus, so devices that ask for a bus of another chipset still fit
+    pub fn with_aliases(self, aliases: Vec<&str>) -> Self {
+        Self {
+            aliases: aliases.into_iter().map(str::to_string).collect(),
+            ..self
+        }
+    }
+
+    fn is_named(&self, name: &str) -> bool {
+        self.name == name || self.aliases.iter().any(|alias| alias == name)
+    }
+}
+
+/// hands out pci addresses, so that devices never collide; pinned addresses are reserved
+/// first, every other device gets the first free slot on its preferred bus, or on
+/// the first bus that still has a free slot
+#[derive(Debug)]
+pub struct PciAllocator {
+    buses: Vec<PciBus>,
+    /// whole slots, taken by the chipset or handed out to a device
+    taken: HashSet<(String, u8)>,
+    /// pinned functions, the functions of a pinned slot may be shared by several devices
+    used: HashSet<PciAddress>,
+}
+
+impl PciAllocator {
+    pub fn new(buses: Vec<PciBus>) -> Self {
+        let taken = buses
+            .iter()
+            .flat_map(|bus| bus.reserved.iter().map(|slot| (bus.name.clone(), *slot)))
+            .collect();
+        Self {
+            buses,
+            taken,
+            used: HashSet::new(),
+        }
+    }
+
+    pub fn assign(&mut self, mut slots: Vec<PciSlot>) -> Result<(), OsalError> {
+        for slot in slots.iter_mut() {
+            if let Some(address) = slot.address().clone() {
+                let address = self.resolve(address);
+                self.reserve(&address)?;
+                slot.assign(address);
+            }
+        }
+
+        for slot in slots.iter_mut() {
+            if slot.address().is_none() {
+                let address = self.allocate(slot.preferred_bus())?;
+                slot.assign(address);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// a pinned address on an alias is moved to the bus that the alias stands for
+    fn resolve(&self, address: PciAddress) -> PciAddress {
+        match self
+            .buses
+            .iter()
+            .find(|bus| bus.aliases.iter().any(|alias| alias == address.bus()))
+        {
+            Some(bus) => PciAddress::new(&bus.name, address.slot(), address.function()),
+            None => address,
+        }
+    }
+
+    fn reserve(&mut self, address: &PciAddress) -> Result<(), OsalError> {
+        debug!("PciAllocator::reserve({})", address);
+        let Some(bus) = self.buses.iter().find(|bus| bus.name == address.bus()) else {
+            return Err(OsalError::Invalid(Some(format!(
+                "pci address {} is on a bus that does not exist",
+                address
+            ))));
+        };
+        if !bus.slots.contains(&address.slot()) {
+            return Err(OsalError::Invalid(Some(format!(
+                "pci address {} is not a slot of bus {}, which has slots {:#x} to {:#x}",
+                address,
+                bus.name,
+                bus.slots.start(),
+                bus.slots.end()
+            ))));
+        }
+        if self
+            .taken
+            .contains(&(address.bus().to_string(), address.slot()))
+        {
+            return Err(OsalError::Invalid(Some(format!(
+                "pci address {} is in a slot of the chipset",
+                address
+            ))));
+        }
+        if !self.used.insert(address.clone()) {
+            return Err(OsalError::Invalid(Some(format!(
+                "pci address {} is used more than once",
+                address
+            ))));
+        }
+        Ok(())
+    }
+
+    fn is_free(&self, bus: &PciBus, slot: u8) -> bool {
+        !self.taken.contains(&(bus.name.clone(), slot))
+            && !self
+                .used
+                .iter()
+                .any(|address| address.bus() == bus.name && address.slot() == slot)
+    }
+
+    fn allocate(&mut self, preferred_bus: &str) -> Result<PciAddress, OsalError> {
+        let preferred = self.buses.iter().filter(|bus| bus.is_named(preferred_bus));
+        let others = self.buses.iter().filter(|bus| !bus.is_named(preferred_bus));
+
+        let address = preferred
+            .chain(others)
+            .find_map(|bus| {
+                bus.slots
+                    .clone()
+                    .find(|slot| self.is_free(bus, *slot))
+                    .map(|slot| PciAddress::new(&bus.name, slot, 0))
+            })
+            .ok_or(OsalError::Invalid(Some(
+                "no free pci slot left".to_string(),
+            )))?;
+
+        debug!("PciAllocator::allocate({}) -> {}", preferred_bus, address);
+        self.taken
+            .insert((address.bus().to_string(), address.slot()));
+        Ok(address)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn allocator() -> PciAllocator {
+        PciAllocator::new(vec![
+            PciBus::new("pci.0", 0x0..=0x2).with_reserved(vec![0x1]),
+            PciBus::new("pci.1", 0x0..=0x1),
+            PciBus::new("port-1", 0x0..=0x0),
+        ])
+    }
+
+    #[test]
+    fn test_pinned_addresses_are_honoured() {
+        let mut pinned = Some(PciAddress::new("pci.1", 0x0, 0));
+        let mut first = None;
+        let mut second = None;
+
+        allocator()
+            .assign(vec![
+                PciSlot::new("pci.1", &mut first),
+                PciSlot::new("pci.1", &mut pinned),
+                PciSlot::new("pci.1", &mut second),
+            ])
+            .unwrap();
+
+        assert_eq!(pinned, Some(PciAddress::new("pci.1", 0x0, 0)));
+        assert_eq!(first, Some(PciAddress::new("pci.1", 0x1, 0)));
+        // the preferred bus is full, so the first free slot elsewhere is used
+        assert_eq!(second, Some(PciAddress::new("pci.0", 0x0, 0)));
+    }
+
+    #[test]
+    fn test_functions_of_a_pinned_slot() {
+        let mut function_0 = Some(PciAddress::new("port-1", 0x0, 0));
+        let mut function_1 = Some(PciAddress::new("port-1", 0x0, 1));
+        let mut other = None;
+
+        allocator()
+            .assign(vec![
+                PciSlot::new("port-1", &mut function_0),
+                PciSlot::new("port-1", &mut function_1),
+                PciSlot::new("port-1", &mut other),
+            ])
+            .unwrap();
+
+        assert_eq!(other, Some(PciAddress::new("pci.0", 0x0, 0)));
+    }
+
+    #[test]
+    fn test_conflicts() {
+        fn pin(bus: &'static str, slot: u8, function: u8) -> Result<(), OsalError> {
+            let mut pinned = Some(PciAddress::new(bus, slot, function));
+            allocator().assign(vec![PciSlot::new(bus, &mut pinned)])
+        }
+        // the whole slot of the chipset is taken, not only its first function
+        assert_eq!(
+            pin("pci.0", 0x1, 1),
+            Err(OsalError::Invalid(Some(
+                "pci address pci.0:0x1.1 is in a slot of the chipset".to_string()
+            )))
+        );
+        assert_eq!(
+            pin("pci.5", 0x0, 0),
+            Err(OsalError::Invalid(Some(
+                "pci address pci.5:0x0.0 is on a bus that does not exist".to_string()
+            )))
+        );
+        assert_eq!(
+            pin("pci.1", 0x2, 0),
+            Err(OsalError::Invalid(Some(
+                "pci address pci.1:0x2.0 is not a slot of bus pci.1, which has slots 0x0 to 0x1"
+                    .to_string()
+            )))
+        );
+
+        let mut pinned = Some(PciAddress::new("pci.1", 0x1, 0));
+        let mut twice = Some(PciAddress::new("pci.1", 0x1, 0));
+        assert_eq!(
+            allocator().assign(vec![
+                PciSlot::new("pci.1", &mut pinned),
+                PciSlot::new("pci.1", &mut twice)
+            ]),
+            Err(OsalError::Invalid(Some(
+                "pci address pci.1:0x1.0 is used more than once".to_string()
+            )))
+        );
+
+        let mut slots: Vec<Option<PciAddress>> = vec![None; 6];
+        assert_eq!(
+            allocator().assign(
+                slots
+                    .iter_mut()
+                    .map(|address| PciSlot::new("pci.0", address))
+                    .collect()
+            ),
+            Err(OsalError::Invalid(Some(
+                "no free pci slot left".to_string()
+            )))
+        );
+    }
+
+    #[test]
+    fn test_aliases() {
+        let mut allocator = PciAllocator::new(vec![
+            PciBus::new("pci.0", 0x0..=0x2)
+                .with_reserved(vec![0x1])
+                .with_aliases(vec!["pcie.0"]),
+            PciBus::new("pci.3", 0x0..=0x1).with_aliases(vec!["ich9-pcie-port-1"]),
+        ]);
+        let mut pinned = Some(PciAddress::new("pcie.0", 0x2, 0));
+        let mut passthrough = Some(PciAddress::new("ich9-pcie-port-1", 0x0, 1));
+        let mut preferred = None;
+
+        allocator
+            .assign(vec![
+                PciSlot::new("pcie.0", &mut pinned),
+                PciSlot::new("ich9-pcie-port-1", &mut passthrough),
+                PciSlot::new("ich9-pcie-port-1", &mut preferred),
+            ])
+            .unwrap();
+
+        assert_eq!(pinned, Some(PciAddress::new("pci.0", 0x2, 0)));
+        assert_eq!(passthrough, Some(PciAddress::new("pci.3", 0x0, 1)));
+        assert_eq!(preferred, Some(PciAddress::new("pci.3", 0x1, 0)));
+    }
+}
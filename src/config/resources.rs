@@ -0,0 +1,56 @@
+use crate::config::default_when_missing;
+use crate::resource::tag_expression::TagExpression;
+use derive_getters::Getters;
+use serde::Deserialize;
+
+/// a request for one device, from a specific pool or from any pool, optionally restricted
+/// to devices matching a tag expression; a sticky request prefers the device it got last time
+#[derive(Debug, Clone, PartialEq, Deserialize, Getters)]
+#[serde(deny_unknown_fields)]
+pub struct ResourceRequest {
+    #[serde(default, deserialize_with = "default_when_missing")]
+    pool: Option<String>,
+    #[serde(default, alias = "tag", deserialize_with = "default_when_missing")]
+    tags: Option<TagExpression>,
+    #[serde(default)]
+    sticky: bool,
+}
+
+impl ResourceRequest {
+    #[allow(dead_code)]
+    pub fn new(pool: Option<String>, tags: Option<TagExpression>, sticky: bool) -> Self {
+        Self { pool, tags, sticky }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resource_request() {
+        let requests: Vec<ResourceRequest> = serde_yaml::from_str(
+            r#"
+            - { pool: p2200 }
+            - { pool: x550t2, tag: lan }
+            - { tags: [ gpu, nvidia ], sticky: true }
+            - { tags: "lan && !enp14s1" }
+        "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            requests,
+            vec![
+                ResourceRequest::new(Some("p2200".to_string()), None, false),
+                ResourceRequest::new(
+                    Some("x550t2".to_string()),
+                    Some("lan".parse().unwrap()),
+                    false
+                ),
+                ResourceRequest::new(None, Some("gpu && nvidia".parse().unwrap()), true),
+                ResourceRequest::new(None, Some("lan && !enp14s1".parse().unwrap()), false),
+            ]
+        );
+    }
+}
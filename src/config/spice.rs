@@ -1,9 +1,15 @@
-use crate::config::{default_when_missing, QemuDevice};
+use crate::config::display::{LookingGlass, RemoteViewer};
+use crate::config::strict::{
+    deserialize_mapping, from_mapping, reject_unknown_keys, split_mapping,
+};
+use crate::config::system::Chipset;
+use crate::config::types::{PciAddress, PciSlot, QemuArgument};
+use crate::config::{Config, Finding, QemuDevice};
 use derive_getters::Getters;
-use serde::Deserialize;
+use serde::{Deserialize, Deserializer};
 
 #[derive(Debug, PartialEq, Deserialize)]
-#[serde(untagged)]
+#[serde(untagged, deny_unknown_fields)]
 pub enum SpiceSocket {
     TcpPort { addr: String, port: u16 },
     UnixSocket { path: String },
@@ -18,7 +24,7 @@ impl Default for SpiceSocket {
 }
 
 #[derive(Debug, Default, PartialEq, Deserialize)]
-#[serde(tag = "gl")]
+#[serde(tag = "gl", deny_unknown_fields)]
 pub enum SpiceDisplay {
     #[default]
     #[serde(rename = "off")]
@@ -27,20 +33,78 @@ pub enum SpiceDisplay {
     Enabled { render_node: Option<String> },
 }
 
-#[derive(Debug, PartialEq, Default, Deserialize, Getters)]
-#[serde(default)]
+#[derive(Debug, PartialEq, Getters)]
 pub struct Spice {
-    #[serde(default, flatten, deserialize_with = "default_when_missing")]
     socket: SpiceSocket,
-    #[serde(default, flatten, deserialize_with = "default_when_missing")]
     display: SpiceDisplay,
+    audio_pci_address: Option<PciAddress>,
+    audio_device: &'static str,
+}
+
+const SOCKET_FIELDS: &[&str] = &["addr", "port", "path"];
+const DISPLAY_FIELDS: &[&str] = &["gl", "render_node"];
+const SPICE_FIELDS: &[&str] = &[
+    "addr",
+    "port",
+    "path",
+    "gl",
+    "render_node",
+    "audio_pci_address",
+];
+
+impl<'de> Deserialize<'de> for Spice {
+    /// the socket and display are flattened into spice, and may both be left out
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserialize_mapping(deserializer, |mapping| {
+            let ([socket, display], mut rest) =
+                split_mapping(mapping, [SOCKET_FIELDS, DISPLAY_FIELDS]);
+            let audio_pci_address = match rest.remove("audio_pci_address") {
+                Some(value) => serde_yaml::from_value(value)?,
+                None => Spice::audio_pci_address_default(),
+            };
+            reject_unknown_keys(&rest, SPICE_FIELDS)?;
+
+            Ok(Self {
+                socket: match socket.is_empty() {
+                    true => Default::default(),
+                    false => from_mapping(socket)?,
+                },
+                display: match display.is_empty() {
+                    true => Default::default(),
+                    false => from_mapping(display)?,
+                },
+                audio_pci_address,
+                audio_device: Spice::audio_device_default(),
+            })
+        })
+    }
+}
+
+impl Default for Spice {
+    fn default() -> Self {
+        Self {
+            socket: Default::default(),
+            display: Default::default(),
+            audio_pci_address: Spice::audio_pci_address_default(),
+            audio_device: Spice::audio_device_default(),
+        }
+    }
 }
 
 impl Spice {
+    pub fn audio_pci_address_default() -> Option<PciAddress> {
+        Some(PciAddress::new("pci.2", 0xc, 0))
+    }
+
+    pub fn audio_device_default() -> &'static str {
+        "ich9-intel-hda"
+    }
+
     pub fn new_with_address_and_port(addr: String, port: u16) -> Self {
         Self {
             socket: SpiceSocket::TcpPort { addr, port },
             display: SpiceDisplay::Disabled,
+            ..Default::default()
         }
     }
 
@@ -52,8 +116,17 @@ impl Spice {
         Self {
             socket: SpiceSocket::TcpPort { addr, port },
             display: SpiceDisplay::Enabled {
-                render_node: render_node,
+                render_node,
             },
+            ..Default::default()
+        }
+    }
+
+    /// how a spice client such as remote-viewer should connect to this vm
+    pub fn uri(&self) -> String {
+        match &self.socket {
+            SpiceSocket::TcpPort { addr, port } => format!("spice://{}:{}", addr, port),
+            SpiceSocket::UnixSocket { path } => format!("spice+unix://{}", path),
         }
     }
 
@@ -61,29 +134,35 @@ impl Spice {
         Self {
             socket: SpiceSocket::UnixSocket { path },
             display: SpiceDisplay::Enabled {
-                render_node: render_node,
+                render_node,
             },
+            ..Default::default()
         }
     }
 }
 
 impl QemuDevice for Spice {
-    fn get_qemu_args(&self, _index: usize) -> Vec<String> {
+    fn get_qemu_args(&self, _index: usize) -> Vec<QemuArgument> {
         let mut result = vec![];
         match self.socket {
             SpiceSocket::TcpPort { ref addr, ref port } => {
-                result.extend(vec![format!(
-                    "-spice port={},addr={},disable-ticketing=on",
-                    port, addr
+                result.extend(vec![QemuArgument::new(
+                    "-spice",
+                    format!("port={},addr={},disable-ticketing=on", port, addr),
                 )]);
                 match &self.display {
                     SpiceDisplay::Disabled => {}
                     SpiceDisplay::Enabled { render_node } => {
                         let render_node = match render_node {
-                            Some(render_node) => format!(",rendernode={}", render_node),
+                            Some(render_node) => {
+                                format!(",rendernode={}", QemuArgument::escape(render_node))
+                            }
                             None => "".to_string(),
                         };
-                        result.extend(vec![format!("-display egl-headless{}", render_node)]);
+                        result.extend(vec![QemuArgument::new(
+                            "-display",
+                            format!("egl-headless{}", render_node),
+                        )]);
                     }
                 }
             }
@@ -93,32 +172,91 @@ impl QemuDevice for Spice {
                     SpiceDisplay::Disabled => "".to_string(),
                     SpiceDisplay::Enabled { render_node } => {
                         let render_node = match render_node {
-                            Some(render_node) => format!(",rendernode={}", render_node),
+                            Some(render_node) => {
+                                format!(",rendernode={}", QemuArgument::escape(render_node))
+                            }
                             None => "".to_string(),
                         };
                         format!(",gl=on{}", render_node)
                     }
                 };
 
-                result.extend(vec![format!(
-                    "-spice unix=on,addr={}{},disable-ticketing=on",
-                    path, gl_options
+                result.extend(vec![QemuArgument::new(
+                    "-spice",
+                    format!(
+                        "unix=on,addr={}{},disable-ticketing=on",
+                        QemuArgument::escape(path),
+                        gl_options
+                    ),
                 )]);
             }
         }
 
         result.extend(vec![
-            "-device virtio-serial-pci".to_string(),
-            "-chardev spicevmc,id=vdagent,name=vdagent".to_string(),
-            "-device virtserialport,chardev=vdagent,name=com.redhat.spice.0".to_string(),
-            "-audiodev spice,id=spice-backend0".to_string(),
-            "-device ich9-intel-hda,id=audiodev0,bus=pci.2,addr=0xc".to_string(),
-            "-device hda-duplex,id=audiodev0-codec0,bus=audiodev0.0,cad=0,audiodev=spice-backend0"
-                .to_string(),
+            QemuArgument::new("-device", "virtio-serial-pci"),
+            QemuArgument::new("-chardev", "spicevmc,id=vdagent,name=vdagent"),
+            QemuArgument::new(
+                "-device",
+                "virtserialport,chardev=vdagent,name=com.redhat.spice.0",
+            ),
+            QemuArgument::new("-audiodev", "spice,id=spice-backend0"),
         ]);
+        result.extend(match &self.audio_pci_address {
+            Some(pci_address) => vec![QemuArgument::new(
+                "-device",
+                format!(
+                    "{},id=audiodev0,{}",
+                    self.audio_device,
+                    pci_address.get_device_options()
+                ),
+            )],
+            None => vec![QemuArgument::new(
+                "-device",
+                format!("{},id=audiodev0", self.audio_device),
+            )],
+        });
+        result.push(QemuArgument::new(
+            "-device",
+            "hda-duplex,id=audiodev0-codec0,bus=audiodev0.0,cad=0,audiodev=spice-backend0",
+        ));
 
         result
     }
+
+    fn validate(&self, config: &Config) -> Vec<Finding> {
+        let mut result = vec![];
+        if !config.system().chipset().has_pci_bus() {
+            result.push(Finding::error(
+                "",
+                "the chipset has no pci bus for the virtio-serial-pci and audio devices of spice",
+            ));
+        }
+        result.extend(match &self.socket {
+            // unlike a tcp port, nobody finds a unix socket unless a display connects to it
+            SpiceSocket::UnixSocket { path }
+                if !config.has_display::<RemoteViewer>()
+                    && !config.has_display::<LookingGlass>() =>
+            {
+                vec![Finding::warning(
+                    "path",
+                    format!(
+                        "no display connects to {}, use a remote-viewer display to open it",
+                        path
+                    ),
+                )]
+            }
+            _ => vec![],
+        });
+        result
+    }
+
+    fn use_chipset(&mut self, chipset: &dyn Chipset) {
+        self.audio_device = chipset.get_audio_device();
+    }
+
+    fn get_pci_slots(&mut self) -> Vec<PciSlot<'_>> {
+        vec![PciSlot::new("pci.2", &mut self.audio_pci_address)]
+    }
 }
 
 #[cfg(test)]
@@ -132,17 +270,24 @@ mod tests {
         let data = Spice {
             socket: Default::default(),
             display: Default::default(),
+            audio_pci_address: Spice::audio_pci_address_default(),
+            audio_device: Spice::audio_device_default(),
         };
 
-        let output: Vec<String> = vec![
-            "-spice port=5900,addr=127.0.0.1,disable-ticketing=on".to_string(),
-            "-device virtio-serial-pci".to_string(),
-            "-chardev spicevmc,id=vdagent,name=vdagent".to_string(),
-            "-device virtserialport,chardev=vdagent,name=com.redhat.spice.0".to_string(),
-            "-audiodev spice,id=spice-backend0".to_string(),
-            "-device ich9-intel-hda,id=audiodev0,bus=pci.2,addr=0xc".to_string(),
-            "-device hda-duplex,id=audiodev0-codec0,bus=audiodev0.0,cad=0,audiodev=spice-backend0"
-                .to_string(),
+        let output: Vec<QemuArgument> = vec![
+            QemuArgument::new("-spice", "port=5900,addr=127.0.0.1,disable-ticketing=on"),
+            QemuArgument::new("-device", "virtio-serial-pci"),
+            QemuArgument::new("-chardev", "spicevmc,id=vdagent,name=vdagent"),
+            QemuArgument::new(
+                "-device",
+                "virtserialport,chardev=vdagent,name=com.redhat.spice.0",
+            ),
+            QemuArgument::new("-audiodev", "spice,id=spice-backend0"),
+            QemuArgument::new("-device", "ich9-intel-hda,id=audiodev0,bus=pci.2,addr=0xc"),
+            QemuArgument::new(
+                "-device",
+                "hda-duplex,id=audiodev0-codec0,bus=audiodev0.0,cad=0,audiodev=spice-backend0",
+            ),
         ];
 
         assert_eq!(serde_yaml::from_str::<Spice>(input).unwrap(), data);
@@ -166,18 +311,25 @@ mod tests {
             display: SpiceDisplay::Enabled {
                 render_node: Some("/dev/dri/renderD128".to_string()),
             },
+            audio_pci_address: Spice::audio_pci_address_default(),
+            audio_device: Spice::audio_device_default(),
         };
 
-        let output: Vec<String> = vec![
-            "-spice port=5900,addr=127.0.0.1,disable-ticketing=on".to_string(),
-            "-display egl-headless,rendernode=/dev/dri/renderD128".to_string(),
-            "-device virtio-serial-pci".to_string(),
-            "-chardev spicevmc,id=vdagent,name=vdagent".to_string(),
-            "-device virtserialport,chardev=vdagent,name=com.redhat.spice.0".to_string(),
-            "-audiodev spice,id=spice-backend0".to_string(),
-            "-device ich9-intel-hda,id=audiodev0,bus=pci.2,addr=0xc".to_string(),
-            "-device hda-duplex,id=audiodev0-codec0,bus=audiodev0.0,cad=0,audiodev=spice-backend0"
-                .to_string(),
+        let output: Vec<QemuArgument> = vec![
+            QemuArgument::new("-spice", "port=5900,addr=127.0.0.1,disable-ticketing=on"),
+            QemuArgument::new("-display", "egl-headless,rendernode=/dev/dri/renderD128"),
+            QemuArgument::new("-device", "virtio-serial-pci"),
+            QemuArgument::new("-chardev", "spicevmc,id=vdagent,name=vdagent"),
+            QemuArgument::new(
+                "-device",
+                "virtserialport,chardev=vdagent,name=com.redhat.spice.0",
+            ),
+            QemuArgument::new("-audiodev", "spice,id=spice-backend0"),
+            QemuArgument::new("-device", "ich9-intel-hda,id=audiodev0,bus=pci.2,addr=0xc"),
+            QemuArgument::new(
+                "-device",
+                "hda-duplex,id=audiodev0-codec0,bus=audiodev0.0,cad=0,audiodev=spice-backend0",
+            ),
         ];
 
         assert_eq!(serde_yaml::from_str::<Spice>(input).unwrap(), data);
@@ -199,16 +351,18 @@ mod tests {
             display: SpiceDisplay::Enabled {
                 render_node: Some("/dev/dri/renderD128".to_string()),
             },
+            audio_pci_address: Spice::audio_pci_address_default(),
+            audio_device: Spice::audio_device_default(),
         };
 
-        let output: Vec<String> = vec![
-            "-spice unix=on,addr=/var/ezkvm/unix.socket,gl=on,rendernode=/dev/dri/renderD128,disable-ticketing=on".to_string(),
-            "-device virtio-serial-pci".to_string(),
-            "-chardev spicevmc,id=vdagent,name=vdagent".to_string(),
-            "-device virtserialport,chardev=vdagent,name=com.redhat.spice.0".to_string(),
-            "-audiodev spice,id=spice-backend0".to_string(),
-            "-device ich9-intel-hda,id=audiodev0,bus=pci.2,addr=0xc".to_string(),
-            "-device hda-duplex,id=audiodev0-codec0,bus=audiodev0.0,cad=0,audiodev=spice-backend0".to_string()
+        let output: Vec<QemuArgument> = vec![
+            QemuArgument::new("-spice", "unix=on,addr=/var/ezkvm/unix.socket,gl=on,rendernode=/dev/dri/renderD128,disable-ticketing=on"),
+            QemuArgument::new("-device", "virtio-serial-pci"),
+            QemuArgument::new("-chardev", "spicevmc,id=vdagent,name=vdagent"),
+            QemuArgument::new("-device", "virtserialport,chardev=vdagent,name=com.redhat.spice.0"),
+            QemuArgument::new("-audiodev", "spice,id=spice-backend0"),
+            QemuArgument::new("-device", "ich9-intel-hda,id=audiodev0,bus=pci.2,addr=0xc"),
+            QemuArgument::new("-device", "hda-duplex,id=audiodev0-codec0,bus=audiodev0.0,cad=0,audiodev=spice-backend0")
         ];
 
         assert_eq!(serde_yaml::from_str::<Spice>(input).unwrap(), data);
@@ -4,6 +4,7 @@ use paste::paste;
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct IdeCd {
     #[serde(default = "IdeCd::media_default")]
     media: String,
@@ -27,13 +28,18 @@ impl StoragePayload for IdeCd {
             index, index, index, self.unit
         )]
     }
+
+    fn get_ide_bus(&self, index: usize) -> Option<usize> {
+        Some(index)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::storage::storage_item::payload_from_yaml;
     use crate::config::storage::StorageItem;
-    use crate::config::QemuDevice;
+    use crate::config::{QemuArgument, QemuDevice};
 
     #[test]
     fn test_all_default_values() {
@@ -46,7 +52,7 @@ mod tests {
             type: "ide-cd"
             file: "default_file"
         "#;
-        let from_yaml: IdeCd = serde_yaml::from_str(yaml).unwrap();
+        let from_yaml: IdeCd = payload_from_yaml(yaml);
         assert_eq!(storage, from_yaml);
 
         let drive_args: Vec<String> = vec!["id=drive-ide0,media=cdrom".to_string()];
@@ -57,9 +63,15 @@ mod tests {
         assert_eq!(storage.get_device_options(0), device_args);
 
         let from_yaml: StorageItem = serde_yaml::from_str(yaml).unwrap();
-        let qemu_args: Vec<String> = vec![
-            "-drive file=default_file,if=none,aio=io_uring,id=drive-ide5,media=cdrom".to_string(),
-            "-device ide-cd,bus=ide.5,drive=drive-ide5,id=ide5,unit=0".to_string(),
+        let qemu_args: Vec<QemuArgument> = vec![
+            QemuArgument::new(
+                "-drive",
+                "file=default_file,if=none,aio=io_uring,id=drive-ide5,media=cdrom",
+            ),
+            QemuArgument::new(
+                "-device",
+                "ide-cd,bus=ide.5,drive=drive-ide5,id=ide5,unit=0",
+            ),
         ];
         assert_eq!(from_yaml.get_qemu_args(5), qemu_args);
     }
@@ -85,7 +97,7 @@ mod tests {
             extra_device_options:
             - "option_3"
         "#;
-        let from_yaml: IdeCd = serde_yaml::from_str(yaml).unwrap();
+        let from_yaml: IdeCd = payload_from_yaml(yaml);
         assert_eq!(storage, from_yaml);
 
         let drive_args: Vec<String> = vec!["id=drive-ide5,media=dvd".to_string()];
@@ -96,11 +108,15 @@ mod tests {
         assert_eq!(storage.get_device_options(5), device_args);
 
         let from_yaml: StorageItem = serde_yaml::from_str(yaml).unwrap();
-        let qemu_args: Vec<String> = vec![
-            "-drive file=valid_file,if=none,aio=io_uring,id=drive-ide5,media=dvd,option_1,option_2"
-                .to_string(),
-            "-device ide-cd,bus=ide.5,drive=drive-ide5,id=ide5,unit=3,bootindex=2,option_3"
-                .to_string(),
+        let qemu_args: Vec<QemuArgument> = vec![
+            QemuArgument::new(
+                "-drive",
+                "file=valid_file,if=none,aio=io_uring,id=drive-ide5,media=dvd,option_1,option_2",
+            ),
+            QemuArgument::new(
+                "-device",
+                "ide-cd,bus=ide.5,drive=drive-ide5,id=ide5,unit=3,bootindex=2,option_3",
+            ),
         ];
         assert_eq!(from_yaml.get_qemu_args(5), qemu_args);
     }
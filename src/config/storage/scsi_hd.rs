@@ -4,6 +4,7 @@ use paste::paste;
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct ScsiHd {
     #[serde(default)]
     discard: Option<String>,
@@ -56,8 +57,9 @@ impl StoragePayload for ScsiHd {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::storage::storage_item::payload_from_yaml;
     use crate::config::storage::StorageItem;
-    use crate::config::QemuDevice;
+    use crate::config::{QemuArgument, QemuDevice};
 
     #[test]
     fn test_all_default_values() {
@@ -74,7 +76,7 @@ mod tests {
             type: "scsi-hd"
             file: "default_file"
         "#;
-        let from_yaml: ScsiHd = serde_yaml::from_str(yaml).unwrap();
+        let from_yaml: ScsiHd = payload_from_yaml(yaml);
         assert_eq!(storage, from_yaml);
 
         let drive_args: Vec<String> =
@@ -88,9 +90,9 @@ mod tests {
         assert_eq!(storage.get_device_options(0), device_args);
 
         let from_yaml: StorageItem = serde_yaml::from_str(yaml).unwrap();
-        let expected: Vec<String> = vec![
-            "-drive file=default_file,if=none,aio=io_uring,id=drive-scsi5,format=raw,cache=none,detect-zeroes=unmap".to_string(),
-            "-device scsi-hd,scsi-id=5,drive=drive-scsi5,id=scsi5,bus=scsihw0.0,rotation_rate=1".to_string()
+        let expected: Vec<QemuArgument> = vec![
+            QemuArgument::new("-drive", "file=default_file,if=none,aio=io_uring,id=drive-scsi5,format=raw,cache=none,detect-zeroes=unmap"),
+            QemuArgument::new("-device", "scsi-hd,scsi-id=5,drive=drive-scsi5,id=scsi5,bus=scsihw0.0,rotation_rate=1")
         ];
 
         assert_eq!(from_yaml.get_qemu_args(5), expected);
@@ -125,7 +127,7 @@ mod tests {
             extra_device_options:
                 - "option_3"
         "#;
-        let from_yaml: ScsiHd = serde_yaml::from_str(yaml).unwrap();
+        let from_yaml: ScsiHd = payload_from_yaml(yaml);
         assert_eq!(storage, from_yaml);
 
         let drive_args: Vec<String> = vec![
@@ -140,9 +142,9 @@ mod tests {
         assert_eq!(storage.get_device_options(5), device_args);
 
         let from_yaml: StorageItem = serde_yaml::from_str(yaml).unwrap();
-        let expected: Vec<String> = vec![
-           "-drive file=valid_file,if=none,aio=io_uring,id=drive-scsi5,discard=on,format=qcow2,cache=write-back,detect-zeroes=off,option_1,option_2".to_string(),
-           "-device scsi-hd,scsi-id=5,drive=drive-scsi5,id=scsi5,bus=scsihw1.2,rotation_rate=3,bootindex=1,option_3".to_string()
+        let expected: Vec<QemuArgument> = vec![
+           QemuArgument::new("-drive", "file=valid_file,if=none,aio=io_uring,id=drive-scsi5,discard=on,format=qcow2,cache=write-back,detect-zeroes=off,option_1,option_2"),
+           QemuArgument::new("-device", "scsi-hd,scsi-id=5,drive=drive-scsi5,id=scsi5,bus=scsihw1.2,rotation_rate=3,bootindex=1,option_3")
         ];
 
         assert_eq!(from_yaml.get_qemu_args(5), expected);
@@ -1,6 +1,8 @@
+use derive_getters::Getters;
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Getters)]
+#[serde(deny_unknown_fields)]
 pub struct StorageFooter {
     #[serde(skip_serializing_if = "Option::is_none")]
     boot_index: Option<u8>,
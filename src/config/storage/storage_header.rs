@@ -1,13 +1,19 @@
+use crate::config::QemuArgument;
+use derive_getters::Getters;
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Getters)]
+#[serde(deny_unknown_fields)]
 pub struct StorageHeader {
     file: String,
 }
 
 impl StorageHeader {
     pub fn get_drive_options(&self) -> Vec<String> {
-        vec![format!("file={},if=none,aio=io_uring", self.file)]
+        vec![format!(
+            "file={},if=none,aio=io_uring",
+            QemuArgument::escape(&self.file)
+        )]
     }
 
     pub fn get_device_options(&self) -> Vec<String> {
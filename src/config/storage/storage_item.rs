@@ -1,22 +1,59 @@
 use crate::config::storage::storage_footer::StorageFooter;
 use crate::config::storage::storage_header::StorageHeader;
 use crate::config::storage::storage_payload::StoragePayload;
-use crate::config::QemuDevice;
+use crate::config::strict::{deserialize_mapping, from_mapping, split_mapping, struct_fields};
+use crate::config::system::Chipset;
+use crate::config::types::{PciSlot, QemuArgument};
+use crate::config::{Config, Finding, QemuDevice};
+#[mockall_double::double]
+use crate::osal::Osal;
 use derive_getters::Getters;
-use serde::Deserialize;
+use serde::{Deserialize, Deserializer};
 
-#[derive(Deserialize, Debug, Getters)]
+#[derive(Debug, Getters)]
 pub struct StorageItem {
-    #[serde(flatten)]
     header: StorageHeader,
-    #[serde(flatten)]
     payload: Box<dyn StoragePayload>,
-    #[serde(flatten)]
     footer: StorageFooter,
 }
 
+impl<'de> Deserialize<'de> for StorageItem {
+    /// the header and footer are flattened into the item, all other keys belong to the payload
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserialize_mapping(deserializer, |mapping| {
+            let ([header, footer], payload) = split_mapping(
+                mapping,
+                [
+                    struct_fields::<StorageHeader>(),
+                    struct_fields::<StorageFooter>(),
+                ],
+            );
+            Ok(Self {
+                header: from_mapping(header)?,
+                payload: from_mapping(payload)?,
+                footer: from_mapping(footer)?,
+            })
+        })
+    }
+}
+
+/// deserializes only the payload of a storage item, as typetag would see it
+#[cfg(test)]
+pub fn payload_from_yaml<T: for<'de> Deserialize<'de>>(yaml: &str) -> T {
+    let mapping: serde_yaml::Mapping = serde_yaml::from_str(yaml).unwrap();
+    let (_, mut payload) = split_mapping(
+        mapping,
+        [
+            struct_fields::<StorageHeader>(),
+            struct_fields::<StorageFooter>(),
+        ],
+    );
+    payload.remove("type");
+    from_mapping(payload).unwrap()
+}
+
 impl QemuDevice for StorageItem {
-    fn get_qemu_args(&self, index: usize) -> Vec<String> {
+    fn get_qemu_args(&self, index: usize) -> Vec<QemuArgument> {
         let mut drive_args: Vec<String> = vec![];
         drive_args.extend(self.header().get_drive_options());
         drive_args.extend(self.payload().get_drive_options(index));
@@ -28,8 +65,25 @@ impl QemuDevice for StorageItem {
         device_args.extend(self.footer().get_device_options());
 
         vec![
-            format!("-drive {}", drive_args.join(",")),
-            format!("-device {}", device_args.join(",")),
+            QemuArgument::new("-drive", drive_args.join(",")),
+            QemuArgument::new("-device", device_args.join(",")),
         ]
     }
+
+    fn use_chipset(&mut self, chipset: &dyn Chipset) {
+        self.payload.use_chipset(chipset);
+    }
+
+    fn get_pci_slots(&mut self) -> Vec<PciSlot<'_>> {
+        self.payload.get_pci_slots()
+    }
+
+    fn validate(&self, _config: &Config) -> Vec<Finding> {
+        let file = self.header.file();
+        if Osal::path_exists(file.clone()) {
+            vec![]
+        } else {
+            vec![Finding::error("file", format!("{} does not exist", file))]
+        }
+    }
 }
@@ -1,3 +1,5 @@
+use crate::config::system::Chipset;
+use crate::config::types::PciSlot;
 use std::fmt::Debug;
 
 #[typetag::deserialize(tag = "type")]
@@ -8,4 +10,12 @@ pub trait StoragePayload: Debug {
     fn get_device_options(&self, _index: usize) -> Vec<String> {
         vec![]
     }
+    fn use_chipset(&mut self, _chipset: &dyn Chipset) {}
+    /// the ide bus this drive is connected to, if any
+    fn get_ide_bus(&self, _index: usize) -> Option<usize> {
+        None
+    }
+    fn get_pci_slots(&mut self) -> Vec<PciSlot<'_>> {
+        vec![]
+    }
 }
@@ -1,9 +1,12 @@
 use crate::config::storage::storage_payload::StoragePayload;
+use crate::config::system::{virtio_mmio_device, Chipset};
+use crate::config::types::{PciAddress, PciSlot};
 use crate::{optional_value_getter, required_value_getter};
 use paste::paste;
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct VirtioBlkPci {
     #[serde(default)]
     discard: Option<String>,
@@ -13,8 +16,10 @@ pub struct VirtioBlkPci {
     format: String,
     #[serde(default = "VirtioBlkPci::detect_zeroes_default")]
     detect_zeroes: String,
-    #[serde(default = "VirtioBlkPci::bus_default")]
-    bus: String,
+    #[serde(default)]
+    pci_address: Option<PciAddress>,
+    #[serde(skip, default = "VirtioBlkPci::device_default")]
+    device: String,
 }
 
 impl VirtioBlkPci {
@@ -22,7 +27,10 @@ impl VirtioBlkPci {
     required_value_getter!(cache("cache"): String = "none".to_string());
     required_value_getter!(format("format"): String = "raw".to_string());
     required_value_getter!(detect_zeroes("detect-zeroes"): String = "unmap".to_string());
-    required_value_getter!(bus("bus"): String = "pci.0".to_string());
+
+    fn device_default() -> String {
+        "virtio-blk-pci".to_string()
+    }
 }
 
 #[typetag::deserialize(name = "virtio-blk-pci")]
@@ -39,20 +47,36 @@ impl StoragePayload for VirtioBlkPci {
     }
 
     fn get_device_options(&self, index: usize) -> Vec<String> {
+        let pci_address = match &self.pci_address {
+            Some(pci_address) => format!(",{}", pci_address.get_device_options()),
+            None => "".to_string(),
+        };
         vec![format!(
-            "virtio-blk-pci,drive=drive-virtio{},id=virtio{}{}",
-            index,
-            index,
-            self.bus(),
+            "{},drive=drive-virtio{},id=virtio{}{}",
+            self.device, index, index, pci_address,
         )]
     }
+
+    fn use_chipset(&mut self, chipset: &dyn Chipset) {
+        if !chipset.has_pci_bus() {
+            self.device = virtio_mmio_device(&self.device);
+        }
+    }
+
+    fn get_pci_slots(&mut self) -> Vec<PciSlot<'_>> {
+        match self.device == VirtioBlkPci::device_default() {
+            true => vec![PciSlot::new("pci.0", &mut self.pci_address)],
+            false => vec![],
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::storage::storage_item::payload_from_yaml;
     use crate::config::storage::StorageItem;
-    use crate::config::QemuDevice;
+    use crate::config::{QemuArgument, QemuDevice};
 
     #[test]
     fn test_all_default_values() {
@@ -61,14 +85,15 @@ mod tests {
             cache: VirtioBlkPci::cache_default(),
             format: VirtioBlkPci::format_default(),
             detect_zeroes: VirtioBlkPci::detect_zeroes_default(),
-            bus: VirtioBlkPci::bus_default(),
+            pci_address: None,
+            device: VirtioBlkPci::device_default(),
         };
 
         let yaml = r#"
             type: "virtio-blk-pci"
             file: "default_file"
         "#;
-        let from_yaml: VirtioBlkPci = serde_yaml::from_str(yaml).unwrap();
+        let from_yaml: VirtioBlkPci = payload_from_yaml(yaml);
         assert_eq!(storage, from_yaml);
 
         let drive_args: Vec<String> =
@@ -76,13 +101,13 @@ mod tests {
         assert_eq!(storage.get_drive_options(0), drive_args);
 
         let device_args: Vec<String> =
-            vec!["virtio-blk-pci,drive=drive-virtio0,id=virtio0,bus=pci.0".to_string()];
+            vec!["virtio-blk-pci,drive=drive-virtio0,id=virtio0".to_string()];
         assert_eq!(storage.get_device_options(0), device_args);
 
         let from_yaml: StorageItem = serde_yaml::from_str(yaml).unwrap();
-        let expected: Vec<String> = vec![
-            "-drive file=default_file,if=none,aio=io_uring,id=drive-virtio5,format=raw,cache=none,detect-zeroes=unmap".to_string(),
-            "-device virtio-blk-pci,drive=drive-virtio5,id=virtio5,bus=pci.0".to_string()
+        let expected: Vec<QemuArgument> = vec![
+            QemuArgument::new("-drive", "file=default_file,if=none,aio=io_uring,id=drive-virtio5,format=raw,cache=none,detect-zeroes=unmap"),
+            QemuArgument::new("-device", "virtio-blk-pci,drive=drive-virtio5,id=virtio5")
         ];
 
         assert_eq!(from_yaml.get_qemu_args(5), expected);
@@ -95,7 +120,8 @@ mod tests {
             cache: "write-back".to_string(),
             format: "qcow2".to_string(),
             detect_zeroes: "off".to_string(),
-            bus: "pci.2".to_string(),
+            pci_address: Some(PciAddress::new("pci.2", 0x3, 0)),
+            device: VirtioBlkPci::device_default(),
         };
 
         let yaml = r#"
@@ -106,7 +132,7 @@ mod tests {
             cache: "write-back"
             format: "qcow2"
             detect_zeroes: "off"
-            bus: "pci.2"
+            pci_address: "pci.2:0x3"
 
             extra_drive_options:
                 - "option_1"
@@ -115,7 +141,7 @@ mod tests {
             extra_device_options:
                 - "option_3"
         "#;
-        let from_yaml: VirtioBlkPci = serde_yaml::from_str(yaml).unwrap();
+        let from_yaml: VirtioBlkPci = payload_from_yaml(yaml);
         assert_eq!(storage, from_yaml);
 
         let drive_args: Vec<String> = vec![
@@ -125,13 +151,13 @@ mod tests {
         assert_eq!(storage.get_drive_options(5), drive_args);
 
         let device_args: Vec<String> =
-            vec!["virtio-blk-pci,drive=drive-virtio5,id=virtio5,bus=pci.2".to_string()];
+            vec!["virtio-blk-pci,drive=drive-virtio5,id=virtio5,bus=pci.2,addr=0x3".to_string()];
         assert_eq!(storage.get_device_options(5), device_args);
 
         let from_yaml: StorageItem = serde_yaml::from_str(yaml).unwrap();
-        let expected: Vec<String> = vec![
-            "-drive file=valid_file,if=none,aio=io_uring,id=drive-virtio5,discard=on,format=qcow2,cache=write-back,detect-zeroes=off,option_1,option_2".to_string(),
-            "-device virtio-blk-pci,drive=drive-virtio5,id=virtio5,bus=pci.2,bootindex=1,option_3".to_string()
+        let expected: Vec<QemuArgument> = vec![
+            QemuArgument::new("-drive", "file=valid_file,if=none,aio=io_uring,id=drive-virtio5,discard=on,format=qcow2,cache=write-back,detect-zeroes=off,option_1,option_2"),
+            QemuArgument::new("-device", "virtio-blk-pci,drive=drive-virtio5,id=virtio5,bus=pci.2,addr=0x3,bootindex=1,option_3")
         ];
 
         assert_eq!(from_yaml.get_qemu_args(5), expected);
@@ -0,0 +1,276 @@
+use serde::de::{self, Deserialize, Deserializer, MapAccess, Visitor};
+use serde::forward_to_deserialize_any;
+use serde_yaml::{Mapping, Value};
+
+/// the field names of a struct, as they appear in a config file
+pub fn struct_fields<'de, T: Deserialize<'de>>() -> &'static [&'static str] {
+    struct Introspect<'a>(&'a mut &'static [&'static str]);
+
+    impl<'de> Deserializer<'de> for Introspect<'_> {
+        type Error = de::value::Error;
+
+        fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
+            Err(de::Error::custom("not a struct"))
+        }
+
+        fn deserialize_struct<V: Visitor<'de>>(
+            self,
+            _name: &'static str,
+            fields: &'static [&'static str],
+            _visitor: V,
+        ) -> Result<V::Value, Self::Error> {
+            *self.0 = fields;
+            Err(de::Error::custom("introspected"))
+        }
+
+        forward_to_deserialize_any! {
+            bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+            bytes byte_buf option unit unit_struct newtype_struct seq tuple
+            tuple_struct map enum identifier ignored_any
+        }
+    }
+
+    let mut fields: &'static [&'static str] = &[];
+    let _ = T::deserialize(Introspect(&mut fields));
+    fields
+}
+
+/// deserializes a mapping and builds the item from it while still inside the mapping,
+/// so errors are located at the item instead of at the list it is in
+pub fn deserialize_mapping<'de, D, T, F>(deserializer: D, build: F) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    F: FnOnce(Mapping) -> Result<T, serde_yaml::Error>,
+{
+    struct MappingVisitor<F>(F);
+
+    impl<'de, T, F> Visitor<'de> for MappingVisitor<F>
+    where
+        F: FnOnce(Mapping) -> Result<T, serde_yaml::Error>,
+    {
+        type Value = T;
+
+        fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+            formatter.write_str("a mapping")
+        }
+
+        fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<T, A::Error> {
+            let mut mapping = Mapping::new();
+            while let Some((key, value)) = map.next_entry::<Value, Value>()? {
+                mapping.insert(key, value);
+            }
+            (self.0)(mapping).map_err(de::Error::custom)
+        }
+    }
+
+    deserializer.deserialize_map(MappingVisitor(build))
+}
+
+/// splits a mapping over flattened parts: every part gets the keys in its field list, all
+/// other keys go into the last mapping, for a part that knows its own fields, like a typetag
+/// payload; serde can not do this by itself, it will not reject unknown keys with flatten
+pub fn split_mapping<const N: usize>(
+    mapping: Mapping,
+    fields: [&[&str]; N],
+) -> ([Mapping; N], Mapping) {
+    let mut parts: [Mapping; N] = std::array::from_fn(|_| Mapping::new());
+    let mut rest = Mapping::new();
+
+    for (key, value) in mapping {
+        let part = key
+            .as_str()
+            .and_then(|name| fields.iter().position(|fields| fields.contains(&name)));
+        match part {
+            Some(index) => parts[index].insert(key, value),
+            None => rest.insert(key, value),
+        };
+    }
+
+    (parts, rest)
+}
+
+/// deserializes one of the parts returned by split_mapping
+pub fn from_mapping<T>(mapping: Mapping) -> Result<T, serde_yaml::Error>
+where
+    T: for<'de> Deserialize<'de>,
+{
+    serde_yaml::from_value(Value::Mapping(mapping))
+}
+
+/// the error for the first key left over after splitting a mapping
+pub fn reject_unknown_keys(
+    mapping: &Mapping,
+    expected: &'static [&'static str],
+) -> Result<(), serde_yaml::Error> {
+    match mapping.keys().next() {
+        Some(key) => Err(de::Error::unknown_field(
+            key.as_str().unwrap_or("?"),
+            expected,
+        )),
+        None => Ok(()),
+    }
+}
+
+/// a config is strict, unless it says `strict: false` at the top level
+pub fn is_strict(content: &str) -> bool {
+    let value: Value = serde_yaml::from_str(content).unwrap_or_default();
+    value.get("strict") != Some(&Value::Bool(false))
+}
+
+/// a yaml feature outside the subset that ezkvm configs may use, with its line and column
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnsupportedFeature {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+/// finds anchors, aliases, tags and merge keys; these are not needed to describe a vm,
+/// and make it hard to see what a config does, so they are left out as in StrictYAML
+pub fn find_unsupported_features(content: &str) -> Vec<UnsupportedFeature> {
+    let mut result = vec![];
+    let mut block_indent: Option<usize> = None;
+
+    for (index, line) in content.lines().enumerate() {
+        let indent = line.len() - line.trim_start().len();
+        if let Some(block) = block_indent {
+            // the lines of a block scalar are text, until the indentation drops back
+            if line.trim().is_empty() || indent > block {
+                continue;
+            }
+            block_indent = None;
+        }
+
+        let mut quote: Option<char> = None;
+        let mut previous = ' ';
+        let mut last = ' ';
+        // anchors, aliases, tags and quotes only count where a node starts: at the start of
+        // the line, after `: `, `- `, `[`, `{` or `,`; elsewhere they are part of plain text
+        let mut node_start = true;
+        let mut indicator = false;
+        let mut closed_single = false;
+        for (column, c) in line.char_indices() {
+            match quote {
+                Some('"') if c == '"' && previous == '\\' => {}
+                Some(q) if c == q => {
+                    quote = None;
+                    closed_single = q == '\'';
+                }
+                Some(_) => {}
+                None => {
+                    let reopen = std::mem::take(&mut closed_single);
+                    if c.is_whitespace() {
+                        node_start |= indicator;
+                        indicator = false;
+                        previous = c;
+                        continue;
+                    }
+                    let feature = match c {
+                        '#' if previous == ' ' || previous == '\t' || column == 0 => break,
+                        // a '' inside single quotes is an escaped quote, not the end
+                        '"' | '\'' if node_start || (c == '\'' && reopen) => {
+                            quote = Some(c);
+                            None
+                        }
+                        '&' if node_start => Some("anchors are"),
+                        '*' if node_start => Some("aliases are"),
+                        '!' if node_start => Some("tags are"),
+                        '<' if node_start && line[column..].starts_with("<<") => {
+                            Some("merge keys are")
+                        }
+                        _ => None,
+                    };
+                    if let Some(feature) = feature {
+                        result.push(UnsupportedFeature {
+                            line: index + 1,
+                            column: column + 1,
+                            message: format!("{} not supported in ezkvm configs", feature),
+                        });
+                        break;
+                    }
+                    indicator = c == ':' || (c == '-' && node_start);
+                    node_start = "[{,".contains(c);
+                    last = c;
+                }
+            }
+            previous = c;
+        }
+
+        if quote.is_none() && (last == '|' || last == '>') {
+            block_indent = Some(indent);
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Deserialize)]
+    #[allow(dead_code)]
+    struct Header {
+        file: String,
+        #[serde(rename = "boot_index")]
+        index: u8,
+    }
+
+    #[test]
+    fn test_struct_fields() {
+        assert_eq!(struct_fields::<Header>(), &["file", "boot_index"]);
+        assert!(struct_fields::<String>().is_empty());
+    }
+
+    #[test]
+    fn test_split_mapping() {
+        let mapping: Mapping =
+            serde_yaml::from_str("{ type: scsi-hd, file: /dev/vm1/boot, boot_index: 0 }").unwrap();
+        let ([header, footer], payload) = split_mapping(mapping, [&["file"], &["boot_index"]]);
+
+        assert_eq!(
+            header,
+            serde_yaml::from_str("{ file: /dev/vm1/boot }").unwrap()
+        );
+        assert_eq!(footer, serde_yaml::from_str("{ boot_index: 0 }").unwrap());
+        assert_eq!(payload, serde_yaml::from_str("{ type: scsi-hd }").unwrap());
+    }
+
+    #[test]
+    fn test_find_unsupported_features() {
+        let content = r#"
+general: &general { name: "wakiza & co" }   # an anchor
+other: *general
+tagged: !!str 42
+merged: { <<: *general }
+comment: plain  # with *stars* and !bangs
+quoted: 'it''s & fine'
+text: |
+  *not* an alias
+storage: [ { file: "/dev/vm1/a&b" } ]
+"#;
+        let features: Vec<(usize, usize)> = find_unsupported_features(content)
+            .iter()
+            .map(|feature| (feature.line, feature.column))
+            .collect();
+        assert_eq!(features, vec![(2, 10), (3, 8), (4, 9), (5, 11)]);
+    }
+
+    #[test]
+    fn test_plain_text_is_supported() {
+        let content = r#"
+resources:
+  - tags: lan && !enp14s1
+  - { tags: lan && !enp14s1, sticky: true }
+general: { name: rock&roll }
+cpu: { flags: "+aes", model: it's *the* host }
+"#;
+        assert_eq!(find_unsupported_features(content), vec![]);
+
+        let value: Value = serde_yaml::from_str(content).unwrap();
+        assert_eq!(value["resources"][0]["tags"], "lan && !enp14s1");
+        assert_eq!(value["resources"][1]["tags"], "lan && !enp14s1");
+        assert_eq!(value["cpu"]["model"], "it's *the* host");
+    }
+}
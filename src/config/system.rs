@@ -1,17 +1,21 @@
 use crate::config::system::applesmc::AppleSmc;
-use crate::config::system::bios::Bios;
-use crate::config::system::chipset::Chipset;
+use crate::config::system::bios::{Bios, NoBios};
+use crate::config::system::boot::Boot;
+pub use crate::config::system::chipset::{installed_qemu_version, virtio_mmio_device, Chipset};
 use crate::config::system::cpu::Cpu;
 use crate::config::system::memory::Memory;
-use crate::config::system::tpm::Tpm;
-use crate::config::types::QemuDevice;
-use crate::config::{default_when_missing, Config};
+use crate::config::system::tpm::{Tpm, TpmModel};
+use crate::config::types::{QemuArgument, QemuDevice};
+use crate::config::{default_when_missing, within, Config, Finding};
+use crate::osal::OsalError;
 use derive_getters::Getters;
 use serde::Deserialize;
-use typetag::serde;
+use std::any::TypeId;
+use std::ops::Deref;
 
 mod applesmc;
 mod bios;
+mod boot;
 mod chipset;
 mod cpu;
 mod memory;
@@ -19,12 +23,15 @@ mod tpm;
 
 #[allow(dead_code)]
 #[derive(Deserialize, Default, Debug, Getters)]
+#[serde(deny_unknown_fields)]
 pub struct System {
     #[serde(default, deserialize_with = "default_when_missing")]
     chipset: Box<dyn Chipset>,
     #[serde(default, deserialize_with = "default_when_missing")]
     bios: Box<dyn Bios>,
     #[serde(default, deserialize_with = "default_when_missing")]
+    boot: Boot,
+    #[serde(default, deserialize_with = "default_when_missing")]
     memory: Memory,
     #[serde(default, deserialize_with = "default_when_missing")]
     cpu: Cpu,
@@ -45,27 +52,94 @@ impl System {
         Self {
             chipset,
             bios,
+            boot: Boot::default(),
             memory,
             cpu,
             tpm,
             applesmc: None,
         }
     }
+
+    /// whether the bios is a B, like NoBios
+    pub fn has_bios<B: 'static>(&self) -> bool {
+        let bios: &dyn Bios = self.bios.deref();
+        (*bios).type_id() == TypeId::of::<B>()
+    }
+
+    pub fn find_firmware(&mut self) {
+        self.bios.find_firmware();
+    }
+
+    pub fn use_runtime_dir(&mut self, runtime_dir: &str) {
+        self.tpm.use_runtime_dir(runtime_dir);
+    }
 }
 
 impl QemuDevice for System {
-    fn get_qemu_args(&self, _index: usize) -> Vec<String> {
+    fn get_qemu_args(&self, _index: usize) -> Vec<QemuArgument> {
         let mut result = vec![];
         result.extend(self.chipset.get_qemu_args(0));
+        if self.chipset.needs_firmware() {
+            result.extend(self.boot.get_firmware_args(self.bios.get_boot_splash()));
+        }
+        let cmdline = self.chipset.get_default_cmdline();
+        result.extend(self.boot.get_kernel_args(cmdline));
         result.extend(self.bios.get_qemu_args(0));
         result.extend(self.memory.get_qemu_args(0));
         result.extend(self.cpu.get_qemu_args(0));
+        if let Some(applesmc) = &self.applesmc {
+            result.extend(applesmc.get_qemu_args(0));
+        }
         result.extend(self.tpm.get_qemu_args(0));
         result
     }
 
-    fn pre_start(&self, config: &Config) {
-        self.tpm.pre_start(config);
+    fn get_helper_commands(&self, config: &Config) -> Vec<Vec<String>> {
+        self.tpm.get_helper_commands(config)
+    }
+
+    fn validate(&self, config: &Config) -> Vec<Finding> {
+        let mut result = vec![];
+        result.extend(within("chipset", self.chipset.validate(config)));
+        result.extend(within("bios", self.bios.validate(config)));
+        result.extend(within("boot", self.boot.validate(config)));
+        if !self.chipset.needs_firmware() && self.boot.kernel().is_none() {
+            result.push(Finding::error(
+                "boot.kernel",
+                "the chipset has no firmware, it needs a kernel to boot",
+            ));
+        }
+        if !self.chipset.needs_firmware() && !self.has_bios::<NoBios>() {
+            result.push(Finding::error(
+                "bios",
+                "the chipset boots the kernel directly, use a bios of type no_bios",
+            ));
+        }
+        result.extend(within("cpu", self.cpu.validate(config)));
+        result.extend(within("tpm", self.tpm.validate(config)));
+        if let Some(model) = self.tpm.get_model() {
+            let models = self.chipset.get_tpm_models();
+            if !models.contains(&model) {
+                let names: Vec<&str> = models.iter().map(TpmModel::device).collect();
+                result.push(Finding::error(
+                    "tpm.model",
+                    format!(
+                        "the chipset has no {}, only {}",
+                        model.device(),
+                        names.join(", ")
+                    ),
+                ));
+            }
+        }
+        if let Some(applesmc) = &self.applesmc {
+            result.extend(within("applesmc", applesmc.validate(config)));
+        }
+        result
+    }
+
+    fn pre_start(&self, config: &Config) -> Result<(), OsalError> {
+        self.bios.pre_start(config)?;
+        self.tpm.pre_start(config)
     }
 }
 
@@ -74,11 +148,19 @@ mod tests {
     use super::*;
     use crate::config::system::bios::SeaBios;
     use crate::config::system::tpm::{NoTpm, SwTpm};
+    #[mockall_double::double]
+    use crate::osal::Osal;
     use bios::OVMF;
     use chipset::Q35;
+    use serial_test::serial;
 
     #[test]
+    #[serial]
     fn test_defaults() {
+        let get_machine_types = Osal::get_machine_types_context();
+        get_machine_types
+            .expect()
+            .returning(|_| vec!["pc-q35-8.1".to_string()]);
         let actual: System = serde_yaml::from_str(
             r#"
               "#,
@@ -103,11 +185,11 @@ mod tests {
                   bios:    { type: "ovmf", uuid: "04d064c3-66a1-4aa7-9589-f8b3ecf91cd7", file: "/dev/vm1/vm-108-efidisk" }
                   memory:  { max: 16384, balloon: false }
                   cpu:     { model: "qemu64", sockets: 1, cores: 8, flags: "+aes,+pni,+popcnt,+sse4.1,+sse4.2,+ssse3,enforce" }
-                  tpm:     { type: "swtpm", version: 2.0, disk: "/dev/vm1/vm-108-tpmstate", socket: "/var/ezkvm/wakiza-tpm.socket" }
+                  tpm:     { type: "swtpm", disk: "/dev/vm1/vm-108-tpmstate", socket: "/var/ezkvm/wakiza-tpm.socket" }
               "#).unwrap();
 
         let expected = System::new(
-            Box::new(Q35::new()),
+            Box::new(Q35::new().with_version("8.1")),
             Box::new(OVMF::new(
                 "/dev/vm1/vm-108-efidisk".to_string(),
                 Some("04d064c3-66a1-4aa7-9589-f8b3ecf91cd7".to_string()),
@@ -141,7 +223,7 @@ mod tests {
               "#).unwrap();
 
         let expected = System::new(
-            Box::new(Q35::new()),
+            Box::new(Q35::new().with_version("8.1")),
             Box::new(OVMF::new(
                 "/dev/vm1/vm-950-disk-0".to_string(),
                 Some("c0e240a5-859a-4378-a2d9-95088f531142".to_string()),
@@ -162,4 +244,98 @@ mod tests {
 
         assert_eq!(actual.get_qemu_args(0), expected.get_qemu_args(0));
     }
+
+    #[test]
+    fn test_boot() {
+        let system: System = serde_yaml::from_str(
+            r#"
+              chipset: { type: "q35", version: "8.1" }
+              boot:    { kernel: "/vms/bzImage", cmdline: "root=/dev/sda2", order: "c", menu: false }
+            "#,
+        )
+        .unwrap();
+        let args = system.get_qemu_args(0);
+        let boot = args
+            .iter()
+            .position(|arg| arg.get_flag() == "-boot")
+            .unwrap();
+        assert_eq!(
+            args[boot..boot + 4],
+            [
+                QemuArgument::new(
+                    "-boot",
+                    "order=c,menu=off,strict=on,reboot-timeout=1000,splash=/usr/share/ezkvm/bootsplash.jpg"
+                ),
+                QemuArgument::new("-kernel", "/vms/bzImage"),
+                QemuArgument::new("-append", "root=/dev/sda2"),
+                QemuArgument::new("-smbios", "type=1,uuid="),
+            ]
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn test_microvm() {
+        let path_exists = Osal::path_exists_context();
+        path_exists.expect().returning(|_path: String| true);
+
+        let system: System = serde_yaml::from_str(
+            r#"
+              chipset: { type: "microvm" }
+              bios:    { type: "no_bios" }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            system.validate(&Config::default()),
+            vec![Finding::error(
+                "boot.kernel",
+                "the chipset has no firmware, it needs a kernel to boot"
+            )]
+        );
+
+        let system: System = serde_yaml::from_str(
+            r#"
+              chipset: { type: "microvm" }
+              bios:    { type: "no_bios" }
+              boot:    { kernel: "/vms/ci/vmlinuz", initrd: "/vms/ci/initrd.img" }
+            "#,
+        )
+        .unwrap();
+        assert!(system.validate(&Config::default()).is_empty());
+        let args = system.get_qemu_args(0);
+        assert_eq!(
+            args[2..5],
+            [
+                QemuArgument::new("-kernel", "/vms/ci/vmlinuz"),
+                QemuArgument::new("-initrd", "/vms/ci/initrd.img"),
+                QemuArgument::new("-append", "console=ttyS0"),
+            ]
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn test_tpm_model() {
+        let get_machine_types = Osal::get_machine_types_context();
+        get_machine_types
+            .expect()
+            .returning(|_| vec!["pc-q35-8.1".to_string()]);
+        let path_exists = Osal::path_exists_context();
+        path_exists.expect().returning(|_path: String| true);
+
+        let system: System = serde_yaml::from_str(
+            r#"
+              tpm: { type: "swtpm", disk: "/dev/vm1/vm-108-tpmstate", model: "spapr" }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            system.validate(&Config::default()),
+            vec![Finding::error(
+                "tpm.model",
+                "the chipset has no tpm-spapr, only tpm-tis, tpm-crb"
+            )]
+        );
+    }
 }
@@ -1,7 +1,9 @@
-use crate::config::types::QemuDevice;
+use crate::config::types::{QemuArgument, QemuDevice};
+use crate::config::{Config, Finding};
 use serde::Deserialize;
 
 #[derive(Deserialize, PartialEq, Debug, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct AppleSmc {
     osk: String,
 }
@@ -15,8 +17,23 @@ impl Default for AppleSmc {
 }
 
 impl QemuDevice for AppleSmc {
-    fn get_qemu_args(&self, _index: usize) -> Vec<String> {
-        vec![format!("-device isa-applesmc,osk={}", self.osk)]
+    fn get_qemu_args(&self, _index: usize) -> Vec<QemuArgument> {
+        vec![QemuArgument::new(
+            "-device",
+            format!("isa-applesmc,osk={}", self.osk),
+        )]
+    }
+
+    fn validate(&self, _config: &Config) -> Vec<Finding> {
+        // the example configs ship with a placeholder, as the key may not be distributed
+        if self.osk.is_empty() || self.osk.starts_with('<') {
+            vec![Finding::error(
+                "osk",
+                "the osk is not set, macOS will not boot without the real key",
+            )]
+        } else {
+            vec![]
+        }
     }
 }
 
@@ -29,7 +46,21 @@ mod tests {
         let memory = AppleSmc {
             osk: "my-osk-key".to_string(),
         };
-        let expected: Vec<String> = vec!["-device isa-applesmc,osk=my-osk-key".to_string()];
+        let expected: Vec<QemuArgument> =
+            vec![QemuArgument::new("-device", "isa-applesmc,osk=my-osk-key")];
         assert_eq!(memory.get_qemu_args(0), expected);
     }
+
+    #[test]
+    fn test_validate() {
+        let config = Config::default();
+        let applesmc: AppleSmc =
+            serde_yaml::from_str(r#"{ osk: "<put_the_osk_key_here>" }"#).unwrap();
+        assert_eq!(applesmc.validate(&config).len(), 1);
+
+        let applesmc = AppleSmc {
+            osk: "my-osk-key".to_string(),
+        };
+        assert!(applesmc.validate(&config).is_empty());
+    }
 }
@@ -1,14 +1,27 @@
+mod firmware;
+mod no_bios;
 mod ovmf;
 mod seabios;
 
+#[allow(unused)]
+pub use no_bios::NoBios;
 #[allow(unused)]
 pub use ovmf::{OVMF, OVMFArch, OVMFSize};
 #[allow(unused)]
 pub use seabios::SeaBios;
 use crate::config::types::QemuDevice;
+use std::any::Any;
 
 #[typetag::deserialize(tag = "type")]
-pub trait Bios: QemuDevice {}
+pub trait Bios: 'static + Any + QemuDevice {
+    /// the splash image of the boot menu, when the boot options do not name one
+    fn get_boot_splash(&self) -> Option<&'static str> {
+        None
+    }
+    /// lets the bios look up its firmware files on the host, before the vm is validated
+    /// or started
+    fn find_firmware(&mut self) {}
+}
 impl Default for Box<dyn Bios> {
     fn default() -> Self {
         SeaBios::boxed_default()
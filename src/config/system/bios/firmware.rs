@@ -0,0 +1,292 @@
+#[mockall_double::double]
+use crate::osal::Osal;
+use derive_getters::Getters;
+use log::{debug, warn};
+use serde::Deserialize;
+use std::path::PathBuf;
+
+/// where qemu distributions install their firmware descriptors, highest precedence first
+const FIRMWARE_LOCATIONS: [&str; 3] = [
+    "$XDG_CONFIG_HOME/qemu/firmware",
+    "/etc/qemu/firmware",
+    "/usr/share/qemu/firmware",
+];
+
+/// features of firmware that is built for confidential guests, which ezkvm does not set up
+const CONFIDENTIAL_FEATURES: [&str; 4] = ["amd-sev", "amd-sev-es", "amd-sev-snp", "intel-tdx"];
+
+/// a firmware descriptor, as described in qemu's docs/interop/firmware.json
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+struct FirmwareDescriptor {
+    #[serde(default)]
+    description: String,
+    interface_types: Vec<String>,
+    mapping: Mapping,
+    targets: Vec<Target>,
+    #[serde(default)]
+    features: Vec<String>,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+struct Mapping {
+    device: String,
+    #[serde(default = "Mapping::mode_default")]
+    mode: String,
+    #[serde(default)]
+    executable: Option<FlashFile>,
+    #[serde(default)]
+    nvram_template: Option<FlashFile>,
+}
+
+impl Mapping {
+    fn mode_default() -> String {
+        "split".to_string()
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+struct FlashFile {
+    filename: String,
+    format: String,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+struct Target {
+    architecture: String,
+}
+
+impl FirmwareDescriptor {
+    /// uefi firmware with a read only code and a separate vars flash, both raw, like the
+    /// ovmf bios passes to qemu
+    fn is_split_flash(&self) -> bool {
+        self.interface_types
+            .iter()
+            .any(|interface| interface == "uefi")
+            && self.mapping.device == "flash"
+            && self.mapping.mode == "split"
+            && self
+                .mapping
+                .executable
+                .as_ref()
+                .is_some_and(|executable| executable.format == "raw")
+            && self
+                .mapping
+                .nvram_template
+                .as_ref()
+                .is_none_or(|template| template.format == "raw")
+    }
+
+    fn supports(&self, architecture: &str) -> bool {
+        self.targets
+            .iter()
+            .any(|target| target.architecture == architecture)
+    }
+
+    fn has_feature(&self, feature: &str) -> bool {
+        self.features.iter().any(|known| known == feature)
+    }
+}
+
+/// the code and vars template of an installed uefi firmware
+#[derive(Debug, Clone, PartialEq, Getters)]
+pub struct Firmware {
+    code: String,
+    vars_template: Option<String>,
+}
+
+impl Firmware {
+    /// the first descriptor, in qemu's order, that describes firmware for the architecture
+    /// with a code flash of rom_size bytes; secure_boot is only matched when given, and
+    /// enrolled_keys picks the vars template with the microsoft keys already enrolled
+    pub fn find(
+        architecture: &str,
+        secure_boot: Option<bool>,
+        enrolled_keys: bool,
+        rom_size: u64,
+    ) -> Option<Self> {
+        for path in Self::descriptor_files() {
+            let descriptor = match Self::read_descriptor(path) {
+                Some(descriptor) => descriptor,
+                None => continue,
+            };
+            if !descriptor.is_split_flash()
+                || !descriptor.supports(architecture)
+                || CONFIDENTIAL_FEATURES
+                    .iter()
+                    .any(|feature| descriptor.has_feature(feature))
+                || secure_boot.is_some_and(|secure| descriptor.has_feature("secure-boot") != secure)
+                || descriptor.has_feature("enrolled-keys") != enrolled_keys
+            {
+                continue;
+            }
+
+            // the descriptor says nothing about the flash size, the code file does
+            let code = descriptor.mapping.executable.unwrap().filename;
+            if Osal::get_file_size(code.clone()) != Some(rom_size) {
+                continue;
+            }
+            debug!("Firmware::find(): {} ({})", code, descriptor.description);
+            return Some(Self {
+                code,
+                vars_template: descriptor
+                    .mapping
+                    .nvram_template
+                    .map(|template| template.filename),
+            });
+        }
+        None
+    }
+
+    /// a descriptor shadows the ones with the same file name in the locations after it, an
+    /// empty one just hides them; the rest is used in the order of their file names
+    fn descriptor_files() -> Vec<PathBuf> {
+        let mut result: Vec<PathBuf> = vec![];
+        for path in Osal::find_files("*.json", FIRMWARE_LOCATIONS.to_vec()) {
+            if !result
+                .iter()
+                .any(|known| known.file_name() == path.file_name())
+            {
+                result.push(path);
+            }
+        }
+        result.sort_by(|a, b| a.file_name().cmp(&b.file_name()));
+        result
+    }
+
+    fn read_descriptor(path: PathBuf) -> Option<FirmwareDescriptor> {
+        let content = Osal::read_file(path.clone()).ok()?;
+        if content.trim().is_empty() {
+            return None;
+        }
+        match serde_json::from_str(&content) {
+            Ok(descriptor) => Some(descriptor),
+            Err(error) => {
+                warn!("Ignoring firmware descriptor {}: {}", path.display(), error);
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serial_test::serial;
+    use std::fs;
+
+    const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/firmware");
+
+    /// find_files, on a copy of the firmware locations in the fixtures
+    fn find_fixtures(_pattern: &str, locations: Vec<&str>) -> Vec<PathBuf> {
+        let mut result = vec![];
+        for location in locations {
+            let location = location.replace("$XDG_CONFIG_HOME", "/home/user/.config");
+            if let Ok(entries) = fs::read_dir(format!("{}{}", FIXTURES, location)) {
+                let mut paths: Vec<PathBuf> = entries.flatten().map(|entry| entry.path()).collect();
+                paths.sort();
+                result.extend(paths);
+            }
+        }
+        result
+    }
+
+    /// the size of the code files in the fixtures
+    fn file_size(path: String) -> Option<u64> {
+        match path.as_str() {
+            "/usr/share/OVMF/OVMF_CODE.fd" => Some(1966080),
+            "/usr/share/OVMF/OVMF_CODE_4M.secboot.fd"
+            | "/usr/share/OVMF/OVMF_CODE_4M.ms.fd"
+            | "/usr/share/OVMF/OVMF_CODE_4M.snakeoil.fd"
+            | "/usr/share/OVMF/OVMF32_CODE_4M.secboot.fd"
+            | "/usr/share/OVMF/OVMF.amdsev.fd"
+            | "/usr/local/share/OVMF/OVMF_CODE_4M.fd" => Some(3653632),
+            _ => None,
+        }
+    }
+
+    #[test]
+    #[serial]
+    fn test_find() {
+        let find_files = Osal::find_files_context();
+        find_files.expect().returning(find_fixtures);
+        let read_file = Osal::read_file_context();
+        read_file
+            .expect()
+            .returning(|path: PathBuf| Ok(fs::read_to_string(path).unwrap()));
+        let get_file_size = Osal::get_file_size_context();
+        get_file_size.expect().returning(file_size);
+
+        // the snakeoil descriptor is hidden by an empty one in ~/.config
+        assert_eq!(
+            Firmware::find("x86_64", Some(true), false, 3653632),
+            Some(Firmware {
+                code: "/usr/share/OVMF/OVMF_CODE_4M.secboot.fd".to_string(),
+                vars_template: Some("/usr/share/OVMF/OVMF_VARS_4M.fd".to_string()),
+            })
+        );
+        assert_eq!(
+            Firmware::find("x86_64", Some(true), true, 3653632),
+            Some(Firmware {
+                code: "/usr/share/OVMF/OVMF_CODE_4M.ms.fd".to_string(),
+                vars_template: Some("/usr/share/OVMF/OVMF_VARS_4M.ms.fd".to_string()),
+            })
+        );
+        // the one in /etc replaces the one in /usr/share
+        assert_eq!(
+            Firmware::find("x86_64", Some(false), false, 3653632),
+            Some(Firmware {
+                code: "/usr/local/share/OVMF/OVMF_CODE_4M.fd".to_string(),
+                vars_template: Some("/usr/local/share/OVMF/OVMF_VARS_4M.fd".to_string()),
+            })
+        );
+        assert_eq!(
+            Firmware::find("x86_64", Some(false), false, 1966080),
+            Some(Firmware {
+                code: "/usr/share/OVMF/OVMF_CODE.fd".to_string(),
+                vars_template: Some("/usr/share/OVMF/OVMF_VARS.fd".to_string()),
+            })
+        );
+        assert_eq!(
+            Firmware::find("i386", None, false, 3653632),
+            Some(Firmware {
+                code: "/usr/share/OVMF/OVMF32_CODE_4M.secboot.fd".to_string(),
+                vars_template: Some("/usr/share/OVMF/OVMF32_VARS_4M.fd".to_string()),
+            })
+        );
+        // the sev firmware is never picked, and there is no 2M secure boot firmware
+        assert_eq!(
+            Firmware::find("x86_64", None, false, 3653632)
+                .unwrap()
+                .code(),
+            "/usr/share/OVMF/OVMF_CODE_4M.secboot.fd"
+        );
+        assert_eq!(Firmware::find("x86_64", Some(true), false, 1966080), None);
+    }
+
+    #[test]
+    #[serial]
+    fn test_descriptor_files() {
+        let find_files = Osal::find_files_context();
+        find_files.expect().returning(find_fixtures);
+
+        let files: Vec<String> = Firmware::descriptor_files()
+            .iter()
+            .map(|path| path.display().to_string().replacen(FIXTURES, "", 1))
+            .collect();
+        assert_eq!(
+            files,
+            vec![
+                "/home/user/.config/qemu/firmware/20-edk2-x86_64-secure-snakeoil.json",
+                "/usr/share/qemu/firmware/30-edk2-x86_64-sev.json",
+                "/usr/share/qemu/firmware/40-edk2-x86_64-secure-enrolled.json",
+                "/usr/share/qemu/firmware/50-edk2-x86_64-secure.json",
+                "/etc/qemu/firmware/60-edk2-x86_64.json",
+                "/usr/share/qemu/firmware/70-edk2-i386.json",
+                "/usr/share/qemu/firmware/80-edk2-x86_64-2m.json",
+                "/etc/qemu/firmware/90-broken.json",
+            ]
+        );
+    }
+}
@@ -0,0 +1,29 @@
+use crate::config::system::bios::Bios;
+use crate::config::types::{QemuArgument, QemuDevice};
+use serde::Deserialize;
+
+/// no firmware, for machines that boot a kernel directly, like microvm
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct NoBios {}
+
+impl QemuDevice for NoBios {
+    fn get_qemu_args(&self, _index: usize) -> Vec<QemuArgument> {
+        vec![]
+    }
+}
+
+#[typetag::deserialize(name = "no_bios")]
+impl Bios for NoBios {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unit_test() {
+        let no_bios = NoBios {};
+        let expected: Vec<QemuArgument> = vec![];
+        assert_eq!(no_bios.get_qemu_args(0), expected);
+    }
+}
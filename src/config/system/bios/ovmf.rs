@@ -1,6 +1,12 @@
+use crate::config::system::bios::firmware::Firmware;
 use crate::config::system::bios::Bios;
-use crate::config::types::QemuDevice;
+use crate::config::types::{QemuArgument, QemuDevice};
+use crate::config::{Config, Finding};
+#[mockall_double::double]
+use crate::osal::Osal;
+use crate::osal::OsalError;
 use crate::{optional_value_getter, required_value_getter};
+use log::info;
 use paste::paste;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
@@ -11,6 +17,11 @@ const OVMF64_2M_SECURE_BOOT_ROM: &str = "/usr/share/ezkvm/OVMF_CODE.secboot.fd";
 const OVMF64_4M_BOOT_ROM: &str = "/usr/share/ezkvm/OVMF_CODE_4M.fd";
 const OVMF64_4M_SECURE_BOOT_ROM: &str = "/usr/share/ezkvm/OVMF_CODE_4M.secboot.fd";
 const OVMF32_BOOT_ROM: &str = "/usr/share/ezkvm/OVMF32_CODE_4M.fd";
+const OVMF64_2M_VARS: &str = "/usr/share/ezkvm/OVMF_VARS.fd";
+const OVMF64_2M_MS_VARS: &str = "/usr/share/ezkvm/OVMF_VARS.ms.fd";
+const OVMF64_4M_VARS: &str = "/usr/share/ezkvm/OVMF_VARS_4M.fd";
+const OVMF64_4M_MS_VARS: &str = "/usr/share/ezkvm/OVMF_VARS_4M.ms.fd";
+const OVMF32_VARS: &str = "/usr/share/ezkvm/OVMF32_VARS_4M.fd";
 
 #[derive(Deserialize, Serialize, Default, Debug, Copy, Clone, Hash, PartialEq, Eq)]
 pub enum OVMFArch {
@@ -36,8 +47,9 @@ static OVMF_SIZE: LazyLock<HashMap<OVMFSize, (u64, u64)>> = LazyLock::new(|| {
     ])
 });
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Deserialize, Serialize, Default, Debug, Clone, PartialEq)]
-#[serde(default)]
+#[serde(default, deny_unknown_fields)]
 pub struct OVMF {
     #[serde(default = "OVMF::settings_file_default", rename = "file")]
     settings_file: String,
@@ -49,6 +61,13 @@ pub struct OVMF {
     size: Option<OVMFSize>,
     #[serde(default = "OVMF::secure_boot_default")]
     secure_boot: Option<bool>,
+    /// start with the microsoft secure boot keys enrolled, like windows 11 expects; only
+    /// used when the settings file is created
+    #[serde(default)]
+    ms_keys: bool,
+    /// the firmware of the distro, see find_firmware()
+    #[serde(skip)]
+    firmware: Option<Firmware>,
 }
 
 impl OVMF {
@@ -73,12 +92,24 @@ impl OVMF {
             arch,
             size,
             secure_boot,
+            ms_keys: false,
+            firmware: None,
         }
     }
 
     fn boot_rom_file(&self) -> String {
+        format!(",file={}", QemuArgument::escape(&self.boot_rom()))
+    }
+    fn boot_rom(&self) -> String {
+        match &self.firmware {
+            Some(firmware) => firmware.code().clone(),
+            None => self.ezkvm_boot_rom().to_string(),
+        }
+    }
+    /// the firmware in /usr/share/ezkvm, which overrides the firmware of the distro
+    fn ezkvm_boot_rom(&self) -> &'static str {
         let arch = self.arch.unwrap_or_default();
-        let result = match arch {
+        match arch {
             OVMFArch::Arch32 => OVMF32_BOOT_ROM,
             OVMFArch::Arch64 => {
                 let size = self.size.unwrap_or_default();
@@ -98,44 +129,163 @@ impl OVMF {
                     }
                 }
             }
-        };
-
-        format!(",file={}", result)
+        }
+    }
+    /// the architecture of the firmware, in the notation of the firmware descriptors
+    fn target_architecture(&self) -> &'static str {
+        match self.arch.unwrap_or_default() {
+            OVMFArch::Arch32 => "i386",
+            OVMFArch::Arch64 => "x86_64",
+        }
+    }
+    /// the template that a new settings file is copied from
+    fn vars_template(&self) -> Option<String> {
+        match &self.firmware {
+            Some(firmware) => firmware.vars_template().clone(),
+            None => Some(self.ezkvm_vars_template().to_string()),
+        }
+    }
+    fn ezkvm_vars_template(&self) -> &'static str {
+        match (self.arch.unwrap_or_default(), self.size.unwrap_or_default()) {
+            (OVMFArch::Arch32, _) => OVMF32_VARS,
+            (OVMFArch::Arch64, OVMFSize::Size4M) if self.ms_keys => OVMF64_4M_MS_VARS,
+            (OVMFArch::Arch64, OVMFSize::Size4M) => OVMF64_4M_VARS,
+            (OVMFArch::Arch64, OVMFSize::Size2M) if self.ms_keys => OVMF64_2M_MS_VARS,
+            (OVMFArch::Arch64, OVMFSize::Size2M) => OVMF64_2M_VARS,
+        }
+    }
+    /// the vars template, if it exists and has the size of the settings
+    fn usable_vars_template(&self) -> Option<String> {
+        self.vars_template()
+            .filter(|template| Osal::get_file_size(template.clone()) == Some(self.vars_size()))
     }
     fn boot_rom_size(&self) -> String {
         "".to_string()
     }
+    fn vars_size(&self) -> u64 {
+        OVMF_SIZE.get(&self.size.unwrap_or_default()).unwrap().1
+    }
     fn settings_size(&self) -> String {
-        match &self.size {
-            None => format!(",size={}", OVMF_SIZE.get(&OVMFSize::Size4M).unwrap().1),
-            Some(value) => format!(",size={}", OVMF_SIZE.get(value).unwrap().1),
-        }
+        format!(",size={}", self.vars_size())
     }
 }
 impl QemuDevice for OVMF {
-    fn get_qemu_args(&self, _index: usize) -> Vec<String> {
+    fn get_qemu_args(&self, _index: usize) -> Vec<QemuArgument> {
         vec![
-            "-boot menu=on,strict=on,reboot-timeout=1000".to_string(),
-            format!("-smbios type=1{}", self.uuid()),
-            format!(
-                "-drive if=pflash,unit=0,format=raw,readonly=on{}{}",
-                self.boot_rom_file(),
-                self.boot_rom_size()
+            QemuArgument::new("-smbios", format!("type=1{}", self.uuid())),
+            QemuArgument::new(
+                "-drive",
+                format!(
+                    "if=pflash,unit=0,format=raw,readonly=on{}{}",
+                    self.boot_rom_file(),
+                    self.boot_rom_size()
+                ),
             ),
-            format!(
-                "-drive if=pflash,unit=1,id=drive-efidisk0,format=raw{}{}",
-                self.settings_file(),
-                self.settings_size()
+            QemuArgument::new(
+                "-drive",
+                format!(
+                    "if=pflash,unit=1,id=drive-efidisk0,format=raw{}{}",
+                    self.settings_file(),
+                    self.settings_size()
+                ),
             ),
         ]
     }
+
+    fn validate(&self, _config: &Config) -> Vec<Finding> {
+        let mut result = vec![];
+        if !Osal::path_exists(self.boot_rom()) {
+            result.push(Finding::error(
+                "",
+                format!(
+                    "the firmware {} does not exist, and no qemu firmware descriptor matches",
+                    self.boot_rom()
+                ),
+            ));
+        }
+        if self.settings_file == OVMF::settings_file_default() {
+            result.push(Finding::error("file", "no settings file is given"));
+            return result;
+        }
+        // qemu only uses the first vars_size bytes, a larger block device is fine
+        match Osal::get_file_size(self.settings_file.clone()) {
+            Some(size) if size < self.vars_size() => result.push(Finding::error(
+                "file",
+                format!(
+                    "the settings file {} is {} bytes, the firmware needs {}",
+                    self.settings_file,
+                    size,
+                    self.vars_size()
+                ),
+            )),
+            Some(_) => {}
+            // a block device is not created here, but by the volume manager
+            None if self.settings_file.starts_with("/dev/") => result.push(Finding::error(
+                "file",
+                format!("the settings file {} does not exist", self.settings_file),
+            )),
+            None if self.usable_vars_template().is_none() => result.push(Finding::error(
+                "file",
+                format!(
+                    "the settings file {} does not exist, and there is no vars template of {} bytes to create it from",
+                    self.settings_file,
+                    self.vars_size()
+                ),
+            )),
+            None => {}
+        }
+        if self.ms_keys && self.secure_boot != Some(true) {
+            result.push(Finding::error(
+                "ms_keys",
+                "is only used together with secure_boot",
+            ));
+        }
+        result
+    }
+
+    /// a new vm starts with a copy of the vars template
+    fn pre_start(&self, _config: &Config) -> Result<(), OsalError> {
+        if Osal::path_exists(self.settings_file.clone()) {
+            return Ok(());
+        }
+        // qemu would not start without the settings file, or start without its settings
+        let Some(template) = self.usable_vars_template() else {
+            return Err(OsalError::OpenError(Some(format!(
+                "the vars template to create {} from",
+                self.settings_file
+            ))));
+        };
+        Osal::copy_file(template.clone(), self.settings_file.clone())?;
+        info!("Created {} from {}", self.settings_file, template);
+        Ok(())
+    }
 }
 #[typetag::deserialize(name = "ovmf")]
-impl Bios for OVMF {}
+impl Bios for OVMF {
+    fn find_firmware(&mut self) {
+        if Osal::path_exists(self.ezkvm_boot_rom().to_string()) {
+            return;
+        }
+        // the 32 bit firmware has always been used regardless of secure boot
+        let secure_boot = match self.arch.unwrap_or_default() {
+            OVMFArch::Arch32 => None,
+            OVMFArch::Arch64 => Some(self.secure_boot.unwrap_or_default()),
+        };
+        let rom_size = OVMF_SIZE.get(&self.size.unwrap_or_default()).unwrap().0;
+        self.firmware = Firmware::find(
+            self.target_architecture(),
+            secure_boot,
+            self.ms_keys,
+            rom_size,
+        );
+    }
+}
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use serial_test::serial;
+    use std::path::PathBuf;
 
     #[test]
     fn test_defaults() {
@@ -152,14 +302,15 @@ mod tests {
             arch: None,
             size: None,
             secure_boot: OVMF::secure_boot_default(),
+            ms_keys: false,
+            firmware: None,
         };
         assert_eq!(actual, ovmf);
 
         assert_eq!(ovmf.get_qemu_args(0), vec![
-            "-boot menu=on,strict=on,reboot-timeout=1000".to_string(),
-            "-smbios type=1".to_string(),
-            "-drive if=pflash,unit=0,format=raw,readonly=on,file=/usr/share/ezkvm/OVMF_CODE_4M.secboot.fd".to_string(),
-            "-drive if=pflash,unit=1,id=drive-efidisk0,format=raw,file=NO_SETTINGS_FILE_PROVIDED,size=540672".to_string()
+            QemuArgument::new("-smbios", "type=1"),
+            QemuArgument::new("-drive", "if=pflash,unit=0,format=raw,readonly=on,file=/usr/share/ezkvm/OVMF_CODE_4M.secboot.fd"),
+            QemuArgument::new("-drive", "if=pflash,unit=1,id=drive-efidisk0,format=raw,file=NO_SETTINGS_FILE_PROVIDED,size=540672")
         ]);
     }
     #[test]
@@ -180,19 +331,213 @@ mod tests {
             arch: Some(OVMFArch::Arch64),
             size: Some(OVMFSize::Size2M),
             secure_boot: Some(false),
+            ms_keys: false,
+            firmware: None,
         };
         assert_eq!(actual, ovmf);
 
         assert_eq!(
             ovmf.get_qemu_args(0),
             vec![
-                "-boot menu=on,strict=on,reboot-timeout=1000".to_string(),
-                "-smbios type=1,uuid=the_uuid".to_string(),
-                "-drive if=pflash,unit=0,format=raw,readonly=on,file=/usr/share/ezkvm/OVMF_CODE.fd"
-                    .to_string(),
-                "-drive if=pflash,unit=1,id=drive-efidisk0,format=raw,file=the_file,size=131072"
-                    .to_string()
+                QemuArgument::new("-smbios", "type=1,uuid=the_uuid"),
+                QemuArgument::new(
+                    "-drive",
+                    "if=pflash,unit=0,format=raw,readonly=on,file=/usr/share/ezkvm/OVMF_CODE.fd"
+                ),
+                QemuArgument::new(
+                    "-drive",
+                    "if=pflash,unit=1,id=drive-efidisk0,format=raw,file=the_file,size=131072"
+                )
             ]
         );
     }
+
+    #[test]
+    #[serial]
+    fn test_boot_rom_is_escaped() {
+        let find_files = Osal::find_files_context();
+        find_files
+            .expect()
+            .returning(|_p: &str, _l: Vec<&str>| vec![PathBuf::from("50-edk2.json")]);
+        let read_file = Osal::read_file_context();
+        read_file.expect().returning(|path: PathBuf| {
+            let descriptor = std::fs::read_to_string(concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/tests/fixtures/firmware/usr/share/qemu/firmware/50-edk2-x86_64-secure.json"
+            ))
+            .unwrap();
+            assert_eq!(path, PathBuf::from("50-edk2.json"));
+            Ok(descriptor.replace("/usr/share/OVMF/", "/opt/a,b/"))
+        });
+        let get_file_size = Osal::get_file_size_context();
+        get_file_size
+            .expect()
+            .returning(|_path: String| Some(3653632));
+        let path_exists = Osal::path_exists_context();
+        path_exists.expect().returning(|_path: String| false);
+
+        let mut ovmf: OVMF = serde_yaml::from_str("file: /vms/a,b/efidisk").unwrap();
+        ovmf.find_firmware();
+        let args = ovmf.get_qemu_args(0);
+        assert_eq!(
+            args[1].get_value(),
+            Some("if=pflash,unit=0,format=raw,readonly=on,file=/opt/a,,b/OVMF_CODE_4M.secboot.fd")
+        );
+        assert_eq!(
+            args[2].get_value(),
+            Some(
+                "if=pflash,unit=1,id=drive-efidisk0,format=raw,file=/vms/a,,b/efidisk,size=540672"
+            )
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn test_find_firmware() {
+        let find_files = Osal::find_files_context();
+        find_files.expect().returning(|_p: &str, _l: Vec<&str>| {
+            vec![PathBuf::from(concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/tests/fixtures/firmware/usr/share/qemu/firmware/50-edk2-x86_64-secure.json"
+            ))]
+        });
+        let read_file = Osal::read_file_context();
+        read_file
+            .expect()
+            .returning(|path: PathBuf| Ok(std::fs::read_to_string(path).unwrap()));
+        let get_file_size = Osal::get_file_size_context();
+        get_file_size
+            .expect()
+            .returning(|_path: String| Some(3653632));
+
+        // the firmware in /usr/share/ezkvm takes precedence
+        let path_exists = Osal::path_exists_context();
+        path_exists.expect().returning(|_path: String| true);
+        let mut ovmf: OVMF = serde_yaml::from_str("file: /dev/vm1/vm-108-efidisk").unwrap();
+        ovmf.find_firmware();
+        assert_eq!(ovmf.boot_rom(), "/usr/share/ezkvm/OVMF_CODE_4M.secboot.fd");
+
+        path_exists.checkpoint();
+        path_exists.expect().returning(|_path: String| false);
+        ovmf.find_firmware();
+        assert_eq!(ovmf.boot_rom(), "/usr/share/OVMF/OVMF_CODE_4M.secboot.fd");
+
+        let mut ovmf: OVMF =
+            serde_yaml::from_str("{ file: /dev/vm1/vm-108-efidisk, secure_boot: false }").unwrap();
+        ovmf.find_firmware();
+        assert_eq!(ovmf.boot_rom(), "/usr/share/ezkvm/OVMF_CODE_4M.fd");
+    }
+
+    #[test]
+    #[serial]
+    fn test_validate() {
+        let path_exists = Osal::path_exists_context();
+        path_exists.expect().returning(|_path: String| true);
+        let get_file_size = Osal::get_file_size_context();
+        get_file_size
+            .expect()
+            .returning(|path: String| match path.as_str() {
+                "/dev/vm1/vm-108-efidisk" => Some(4194304),
+                "/dev/vm1/vm-109-efidisk" => Some(131072),
+                "/usr/share/ezkvm/OVMF_VARS_4M.ms.fd" => Some(540672),
+                _ => None,
+            });
+        let config = Config::default();
+
+        let ovmf: OVMF = serde_yaml::from_str("file: /dev/vm1/vm-108-efidisk").unwrap();
+        assert!(ovmf.validate(&config).is_empty());
+
+        let ovmf: OVMF = serde_yaml::from_str("file: /dev/vm1/vm-109-efidisk").unwrap();
+        assert_eq!(
+            ovmf.validate(&config),
+            vec![Finding::error(
+                "file",
+                "the settings file /dev/vm1/vm-109-efidisk is 131072 bytes, the firmware needs 540672"
+            )]
+        );
+
+        let ovmf: OVMF = serde_yaml::from_str("file: /dev/vm1/vm-110-efidisk").unwrap();
+        assert_eq!(
+            ovmf.validate(&config),
+            vec![Finding::error(
+                "file",
+                "the settings file /dev/vm1/vm-110-efidisk does not exist"
+            )]
+        );
+
+        // a file is created from the template on start, when there is one
+        let ovmf: OVMF = serde_yaml::from_str("file: /vms/win11.efivars").unwrap();
+        assert_eq!(
+            ovmf.validate(&config),
+            vec![Finding::error(
+                "file",
+                "the settings file /vms/win11.efivars does not exist, and there is no vars template of 540672 bytes to create it from"
+            )]
+        );
+        let ovmf: OVMF =
+            serde_yaml::from_str("{ file: /vms/win11.efivars, ms_keys: true }").unwrap();
+        assert!(ovmf.validate(&config).is_empty());
+
+        let ovmf: OVMF =
+            serde_yaml::from_str("{ file: /vms/win11.efivars, ms_keys: true, secure_boot: false }")
+                .unwrap();
+        assert_eq!(
+            ovmf.validate(&config),
+            vec![Finding::error(
+                "ms_keys",
+                "is only used together with secure_boot"
+            )]
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn test_pre_start() {
+        let path_exists = Osal::path_exists_context();
+        path_exists
+            .expect()
+            .returning(|path: String| path != "/vms/win11.efivars");
+        let get_file_size = Osal::get_file_size_context();
+        get_file_size
+            .expect()
+            .returning(|_path: String| Some(540672));
+        let copy_file = Osal::copy_file_context();
+        copy_file
+            .expect()
+            .withf(|from: &String, to: &String| {
+                from == "/usr/share/ezkvm/OVMF_VARS_4M.ms.fd" && to == "/vms/win11.efivars"
+            })
+            .times(1)
+            .returning(|_from: String, _to: String| Ok(()));
+
+        let ovmf: OVMF =
+            serde_yaml::from_str("{ file: /vms/win11.efivars, ms_keys: true }").unwrap();
+        assert_eq!(ovmf.pre_start(&Config::default()), Ok(()));
+        // an existing settings file is left alone
+        let ovmf: OVMF = serde_yaml::from_str("file: /vms/win10.efivars").unwrap();
+        assert_eq!(ovmf.pre_start(&Config::default()), Ok(()));
+
+        // a settings file that can not be copied stops the start
+        copy_file.checkpoint();
+        copy_file
+            .expect()
+            .returning(|_from: String, to: String| Err(OsalError::WriteError(Some(to))));
+        let ovmf: OVMF = serde_yaml::from_str("file: /vms/win11.efivars").unwrap();
+        assert_eq!(
+            ovmf.pre_start(&Config::default()),
+            Err(OsalError::WriteError(Some(
+                "/vms/win11.efivars".to_string()
+            )))
+        );
+
+        // as does a missing vars template
+        get_file_size.checkpoint();
+        get_file_size.expect().returning(|_path: String| None);
+        assert_eq!(
+            ovmf.pre_start(&Config::default()),
+            Err(OsalError::OpenError(Some(
+                "the vars template to create /vms/win11.efivars from".to_string()
+            )))
+        );
+    }
 }
@@ -1,11 +1,12 @@
 use crate::config::system::bios::Bios;
-use crate::config::types::QemuDevice;
+use crate::config::types::{QemuArgument, QemuDevice};
 use derive_getters::Getters;
 use serde::Deserialize;
 
 const BOOT_SPLASH_FILE: &str = "/usr/share/ezkvm/bootsplash.jpg";
 
 #[derive(Deserialize, Debug, Clone, Getters)]
+#[serde(deny_unknown_fields)]
 pub struct SeaBios {
     uuid: String,
 }
@@ -18,19 +19,20 @@ impl SeaBios {
 }
 
 impl QemuDevice for SeaBios {
-    fn get_qemu_args(&self, _index: usize) -> Vec<String> {
-        vec![
-            format!(
-                "-boot menu=on,strict=on,reboot-timeout=1000,splash={}",
-                BOOT_SPLASH_FILE
-            ),
-            format!("-smbios type=1,uuid={}", self.uuid()),
-        ]
+    fn get_qemu_args(&self, _index: usize) -> Vec<QemuArgument> {
+        vec![QemuArgument::new(
+            "-smbios",
+            format!("type=1,uuid={}", self.uuid()),
+        )]
     }
 }
 
 #[typetag::deserialize(name = "seabios")]
-impl Bios for SeaBios {}
+impl Bios for SeaBios {
+    fn get_boot_splash(&self) -> Option<&'static str> {
+        Some(BOOT_SPLASH_FILE)
+    }
+}
 
 #[cfg(test)]
 mod tests {
@@ -41,9 +43,9 @@ mod tests {
         let seabios = SeaBios {
             uuid: "the_uuid".to_string(),
         };
-        assert_eq!(seabios.get_qemu_args(0), vec![
-            "-boot menu=on,strict=on,reboot-timeout=1000,splash=/usr/share/ezkvm/bootsplash.jpg".to_string(),
-            "-smbios type=1,uuid=the_uuid".to_string(),
-        ]);
+        assert_eq!(
+            seabios.get_qemu_args(0),
+            vec![QemuArgument::new("-smbios", "type=1,uuid=the_uuid")]
+        );
     }
 }
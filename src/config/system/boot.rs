@@ -0,0 +1,186 @@
+use crate::config::types::{QemuArgument, QemuDevice};
+use crate::config::{Config, Finding};
+#[mockall_double::double]
+use crate::osal::Osal;
+use derive_getters::Getters;
+use serde::Deserialize;
+
+/// how the vm boots: the firmware boot options, and optionally a kernel that qemu loads
+/// directly, for example to test a self built kernel against an existing disk image
+#[derive(Deserialize, PartialEq, Debug, Clone, Getters)]
+#[serde(default, deny_unknown_fields)]
+pub struct Boot {
+    kernel: Option<String>,
+    initrd: Option<String>,
+    cmdline: Option<String>,
+    dtb: Option<String>,
+    /// the drives to try, in qemu's notation, like `cd` for the first disk, then the cdrom
+    order: Option<String>,
+    menu: bool,
+    splash: Option<String>,
+    /// milliseconds before a failed boot is retried, -1 to not retry at all
+    reboot_timeout: i32,
+}
+
+impl Default for Boot {
+    fn default() -> Self {
+        Self {
+            kernel: None,
+            initrd: None,
+            cmdline: None,
+            dtb: None,
+            order: None,
+            menu: true,
+            splash: None,
+            reboot_timeout: 1000,
+        }
+    }
+}
+
+impl Boot {
+    /// the -boot options of the firmware; the splash of the bios is used unless one is set
+    pub fn get_firmware_args(&self, bios_splash: Option<&str>) -> Vec<QemuArgument> {
+        let mut options = vec![];
+        if let Some(order) = &self.order {
+            options.push(format!("order={}", order));
+        }
+        options.extend(vec![
+            format!("menu={}", if self.menu { "on" } else { "off" }),
+            "strict=on".to_string(),
+            format!("reboot-timeout={}", self.reboot_timeout),
+        ]);
+        if let Some(splash) = self.splash.as_deref().or(bios_splash) {
+            options.push(format!("splash={}", QemuArgument::escape(splash)));
+        }
+        vec![QemuArgument::new("-boot", options.join(","))]
+    }
+
+    /// the arguments to load a kernel directly, if there is one; the command line of the
+    /// chipset is used unless one is set
+    pub fn get_kernel_args(&self, chipset_cmdline: Option<&str>) -> Vec<QemuArgument> {
+        let mut result = vec![];
+        if let Some(kernel) = &self.kernel {
+            result.push(QemuArgument::new("-kernel", kernel));
+            if let Some(initrd) = &self.initrd {
+                result.push(QemuArgument::new("-initrd", initrd));
+            }
+            if let Some(cmdline) = self.cmdline.as_deref().or(chipset_cmdline) {
+                result.push(QemuArgument::new("-append", cmdline));
+            }
+            if let Some(dtb) = &self.dtb {
+                result.push(QemuArgument::new("-dtb", dtb));
+            }
+        }
+        result
+    }
+}
+
+impl QemuDevice for Boot {
+    fn get_qemu_args(&self, _index: usize) -> Vec<QemuArgument> {
+        let mut result = self.get_firmware_args(None);
+        result.extend(self.get_kernel_args(None));
+        result
+    }
+
+    fn validate(&self, _config: &Config) -> Vec<Finding> {
+        let mut result = vec![];
+        let files = [
+            ("kernel", &self.kernel),
+            ("initrd", &self.initrd),
+            ("dtb", &self.dtb),
+            ("splash", &self.splash),
+        ];
+        for (key, file) in files {
+            if let Some(file) = file {
+                if !Osal::path_exists(file.clone()) {
+                    result.push(Finding::error(key, format!("{} does not exist", file)));
+                }
+            }
+        }
+        if self.kernel.is_none() {
+            let options = [
+                ("initrd", &self.initrd),
+                ("cmdline", &self.cmdline),
+                ("dtb", &self.dtb),
+            ];
+            for (key, _) in options.iter().filter(|(_, value)| value.is_some()) {
+                result.push(Finding::error(key, "is only used together with a kernel"));
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serial_test::serial;
+
+    #[test]
+    fn test_defaults() {
+        let boot: Boot = serde_yaml::from_str("{}").unwrap();
+        assert_eq!(boot, Boot::default());
+        assert_eq!(
+            boot.get_qemu_args(0),
+            vec![QemuArgument::new(
+                "-boot",
+                "menu=on,strict=on,reboot-timeout=1000"
+            )]
+        );
+        assert_eq!(
+            boot.get_firmware_args(Some("/usr/share/ezkvm/bootsplash.jpg")),
+            vec![QemuArgument::new(
+                "-boot",
+                "menu=on,strict=on,reboot-timeout=1000,splash=/usr/share/ezkvm/bootsplash.jpg"
+            )]
+        );
+    }
+
+    #[test]
+    fn test_valid() {
+        let boot: Boot = serde_yaml::from_str(
+            r#"
+            kernel: /home/user/linux/arch/x86/boot/bzImage
+            initrd: /home/user/linux/initrd.img
+            cmdline: "root=/dev/sda2 ro quiet"
+            order: cd
+            menu: false
+            splash: /vms/splash.jpg
+            reboot_timeout: -1
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            boot.get_qemu_args(0),
+            vec![
+                QemuArgument::new(
+                    "-boot",
+                    "order=cd,menu=off,strict=on,reboot-timeout=-1,splash=/vms/splash.jpg"
+                ),
+                QemuArgument::new("-kernel", "/home/user/linux/arch/x86/boot/bzImage"),
+                QemuArgument::new("-initrd", "/home/user/linux/initrd.img"),
+                QemuArgument::new("-append", "root=/dev/sda2 ro quiet"),
+            ]
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn test_validate() {
+        let path_exists = Osal::path_exists_context();
+        path_exists
+            .expect()
+            .returning(|path: String| path != "/vms/missing.dtb");
+
+        let boot: Boot =
+            serde_yaml::from_str("{ cmdline: console=ttyS0, dtb: /vms/missing.dtb }").unwrap();
+        assert_eq!(
+            boot.validate(&Config::default()),
+            vec![
+                Finding::error("dtb", "/vms/missing.dtb does not exist"),
+                Finding::error("cmdline", "is only used together with a kernel"),
+                Finding::error("dtb", "is only used together with a kernel"),
+            ]
+        );
+    }
+}
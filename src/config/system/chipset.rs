@@ -1,14 +1,163 @@
+mod i440fx;
+mod microvm;
 mod q35;
 
+#[allow(unused)]
+pub use crate::config::system::chipset::i440fx::I440fx;
+#[allow(unused)]
+pub use crate::config::system::chipset::microvm::Microvm;
 #[allow(unused)]
 pub use crate::config::system::chipset::q35::Q35;
 
+use crate::config::pci_allocator::PciBus;
+use crate::config::system::tpm::TpmModel;
 use crate::config::types::QemuDevice;
+use crate::config::Finding;
+#[mockall_double::double]
+use crate::osal::Osal;
+
+const QEMU: &str = "qemu-system-x86_64";
 
 #[typetag::deserialize(tag = "type")]
-pub trait Chipset: QemuDevice {}
+pub trait Chipset: QemuDevice {
+    /// the buses on which the PciAllocator may place devices
+    fn get_pci_buses(&self) -> Vec<PciBus> {
+        vec![]
+    }
+    /// the bus for devices that are not given a slot of their own, like ivshmem
+    fn get_root_bus(&self) -> &'static str {
+        "pcie.0"
+    }
+    /// the hda controller that the audio of spice and gtk is connected to
+    fn get_audio_device(&self) -> &'static str {
+        "ich9-intel-hda"
+    }
+    /// the number of ide buses, ide-cd drives use the bus that matches their storage index
+    fn get_ide_buses(&self) -> usize {
+        6
+    }
+    /// without a pci bus, virtio devices use their virtio-mmio variant, like virtio-blk-device
+    fn has_pci_bus(&self) -> bool {
+        true
+    }
+    /// whether the guest console is a serial port, that ezkvm makes available on a socket
+    fn has_serial_console(&self) -> bool {
+        false
+    }
+    /// whether the machine boots from firmware, as opposed to booting a kernel directly
+    fn needs_firmware(&self) -> bool {
+        true
+    }
+    /// the kernel command line when system.boot has none, for machines that boot a kernel
+    fn get_default_cmdline(&self) -> Option<&'static str> {
+        None
+    }
+    /// the tpm interfaces the machine has
+    fn get_tpm_models(&self) -> Vec<TpmModel> {
+        vec![TpmModel::Tis, TpmModel::Crb]
+    }
+}
 impl Default for Box<dyn Chipset> {
     fn default() -> Self {
         Q35::boxed_default()
     }
 }
+
+/// the virtio-mmio variant of a virtio pci device, like virtio-net-device for virtio-net-pci;
+/// other devices are returned as they are
+pub fn virtio_mmio_device(device: &str) -> String {
+    match device.strip_suffix("-pci") {
+        Some(name) if name.starts_with("virtio-") => format!("{}-device", name),
+        _ => device.to_string(),
+    }
+}
+
+/// the versions of a machine type, like 8.1 for pc-q35-8.1, that the installed qemu supports
+fn supported_versions(machine: &str) -> Vec<String> {
+    let prefix = format!("{}-", machine);
+    Osal::get_machine_types(QEMU.to_string())
+        .iter()
+        .filter_map(|machine_type| machine_type.strip_prefix(&prefix))
+        .map(str::to_string)
+        .collect()
+}
+
+/// the major and minor version of the installed qemu
+pub fn installed_qemu_version() -> Option<(u64, u64)> {
+    Osal::get_qemu_version(QEMU.to_string())
+}
+
+/// the newest version of a machine type that the installed qemu supports
+fn newest_version(machine: &str) -> Option<String> {
+    let numeric = |version: &String| -> Option<Vec<u32>> {
+        version.split('.').map(|part| part.parse().ok()).collect()
+    };
+    supported_versions(machine)
+        .into_iter()
+        .filter_map(|version| numeric(&version).map(|numeric| (numeric, version)))
+        .max()
+        .map(|(_, version)| version)
+}
+
+/// guests that must keep the machine they were installed on pin the version; without
+/// a pinned version nor a qemu to ask, the alias lets qemu itself pick the newest
+fn machine_type(machine: &str, alias: &str, version: &Option<String>) -> String {
+    match version.clone().or_else(|| newest_version(machine)) {
+        Some(version) => format!("{}-{}", machine, version),
+        None => alias.to_string(),
+    }
+}
+
+/// a warning when the installed qemu does not support the pinned version
+fn validate_version(machine: &str, version: &Option<String>) -> Vec<Finding> {
+    let version = match version {
+        Some(version) => version,
+        None => return vec![],
+    };
+    let supported = supported_versions(machine);
+    if supported.is_empty() || supported.contains(version) {
+        vec![]
+    } else {
+        vec![Finding::warning(
+            "version",
+            format!(
+                "{}-{} is not supported by the installed {}, it supports: {}",
+                machine,
+                version,
+                QEMU,
+                supported.join(", ")
+            ),
+        )]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serial_test::serial;
+
+    #[test]
+    #[serial]
+    fn test_newest_version() {
+        let get_machine_types = Osal::get_machine_types_context();
+        get_machine_types.expect().returning(|_| {
+            vec![
+                "pc-q35-8.1".to_string(),
+                "pc-q35-10.0".to_string(),
+                "pc-q35-9.2".to_string(),
+                "q35".to_string(),
+                "pc-i440fx-9.2".to_string(),
+            ]
+        });
+        assert_eq!(newest_version("pc-q35"), Some("10.0".to_string()));
+        assert_eq!(newest_version("pc-i440fx"), Some("9.2".to_string()));
+        assert_eq!(newest_version("microvm"), None);
+    }
+
+    #[test]
+    fn test_virtio_mmio_device() {
+        assert_eq!(virtio_mmio_device("virtio-net-pci"), "virtio-net-device");
+        assert_eq!(virtio_mmio_device("virtio-blk-pci"), "virtio-blk-device");
+        assert_eq!(virtio_mmio_device("e1000"), "e1000");
+    }
+}
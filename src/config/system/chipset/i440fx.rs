@@ -0,0 +1,152 @@
+use crate::config::pci_allocator::PciBus;
+use crate::config::system::chipset::{machine_type, validate_version, Chipset};
+use crate::config::types::{QemuArgument, QemuDevice};
+use crate::config::{Config, Finding};
+use serde::Deserialize;
+
+const MACHINE: &str = "pc-i440fx";
+
+/// the plain pci chipset, for older guests like windows xp and 7 that do not know about pcie
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct I440fx {
+    /// the machine version, like 8.1; when left out, the newest the installed qemu supports
+    #[serde(default)]
+    version: Option<String>,
+}
+
+impl I440fx {
+    #[cfg(test)]
+    pub fn new() -> Self {
+        Self { version: None }
+    }
+    #[cfg(test)]
+    pub fn with_version(self, version: &str) -> Self {
+        Self {
+            version: Some(version.to_string()),
+        }
+    }
+}
+
+impl I440fx {
+    /// pci.0 is the root bus, with the piix3 isa/ide bridge in slot 1; pci.1 and pci.2 are
+    /// named like the q35 bridges, so devices that prefer them find the same buses
+    fn get_topology_args(&self) -> Vec<QemuArgument> {
+        let mut result = vec![QemuArgument::new(
+            "-device",
+            "piix3-usb-uhci,id=uhci,bus=pci.0,addr=0x1.0x2",
+        )];
+        for (bridge, slot) in [(1, 0x1e), (2, 0x1f), (3, 0x1d)] {
+            result.push(QemuArgument::new(
+                "-device",
+                format!(
+                    "pci-bridge,id=pci.{},chassis_nr={},bus=pci.0,addr=0x{:x}",
+                    bridge, bridge, slot
+                ),
+            ));
+        }
+        result
+    }
+}
+
+impl QemuDevice for I440fx {
+    fn get_qemu_args(&self, _index: usize) -> Vec<QemuArgument> {
+        let mut result = vec![
+            QemuArgument::new(
+                "-machine",
+                format!(
+                    "hpet=off,type={}",
+                    machine_type(MACHINE, "pc", &self.version)
+                ),
+            ),
+            QemuArgument::new("-rtc", "driftfix=slew,base=localtime"),
+            QemuArgument::new("-global", "kvm-pit.lost_tick_policy=discard"),
+        ];
+        result.extend(self.get_topology_args());
+        result.extend(vec![
+            QemuArgument::new(
+                "-device",
+                "qemu-xhci,p2=15,p3=15,id=xhci,bus=pci.1,addr=0x1b",
+            ),
+            QemuArgument::new(
+                "-iscsi",
+                "initiator-name=iqn.1993-08.org.debian:01:39407ad058b",
+            ),
+            QemuArgument::new("-device", "pvscsi,id=scsihw0,bus=pci.0,addr=0x5"),
+        ]);
+        result
+    }
+
+    fn validate(&self, _config: &Config) -> Vec<Finding> {
+        validate_version(MACHINE, &self.version)
+    }
+}
+
+#[typetag::deserialize(name = "i440fx")]
+impl Chipset for I440fx {
+    fn get_pci_buses(&self) -> Vec<PciBus> {
+        vec![
+            // host bridge, piix3, pvscsi and the bridges
+            PciBus::new("pci.0", 0x0..=0x1f)
+                .with_reserved(vec![0x0, 0x1, 0x5, 0x1d, 0x1e, 0x1f])
+                .with_aliases(vec!["pcie.0"]),
+            PciBus::new("pci.1", 0x0..=0x1f).with_reserved(vec![0x1b]), // xhci
+            PciBus::new("pci.2", 0x0..=0x1f),
+            // there are no pcie root ports, passthrough devices go on a bridge of their own
+            PciBus::new("pci.3", 0x0..=0x1f).with_aliases(vec!["ich9-pcie-port-1"]),
+        ]
+    }
+
+    fn get_root_bus(&self) -> &'static str {
+        "pci.0"
+    }
+
+    fn get_audio_device(&self) -> &'static str {
+        "intel-hda"
+    }
+
+    /// the piix3 has a primary and a secondary ide channel
+    fn get_ide_buses(&self) -> usize {
+        2
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unit_test() {
+        let i440fx = I440fx::new().with_version("8.1");
+        assert_eq!(
+            i440fx.get_qemu_args(0),
+            vec![
+                QemuArgument::new("-machine", "hpet=off,type=pc-i440fx-8.1"),
+                QemuArgument::new("-rtc", "driftfix=slew,base=localtime"),
+                QemuArgument::new("-global", "kvm-pit.lost_tick_policy=discard"),
+                QemuArgument::new("-device", "piix3-usb-uhci,id=uhci,bus=pci.0,addr=0x1.0x2"),
+                QemuArgument::new(
+                    "-device",
+                    "pci-bridge,id=pci.1,chassis_nr=1,bus=pci.0,addr=0x1e"
+                ),
+                QemuArgument::new(
+                    "-device",
+                    "pci-bridge,id=pci.2,chassis_nr=2,bus=pci.0,addr=0x1f"
+                ),
+                QemuArgument::new(
+                    "-device",
+                    "pci-bridge,id=pci.3,chassis_nr=3,bus=pci.0,addr=0x1d"
+                ),
+                QemuArgument::new(
+                    "-device",
+                    "qemu-xhci,p2=15,p3=15,id=xhci,bus=pci.1,addr=0x1b"
+                ),
+                QemuArgument::new(
+                    "-iscsi",
+                    "initiator-name=iqn.1993-08.org.debian:01:39407ad058b"
+                ),
+                QemuArgument::new("-device", "pvscsi,id=scsihw0,bus=pci.0,addr=0x5")
+            ]
+        );
+    }
+}
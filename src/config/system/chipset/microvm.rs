@@ -0,0 +1,69 @@
+use crate::config::system::chipset::Chipset;
+use crate::config::types::{QemuArgument, QemuDevice};
+use serde::Deserialize;
+
+/// a minimal machine without pci and firmware, that boots the linux kernel of system.boot
+/// directly; meant for ci runners and other short lived linux guests
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Microvm {}
+
+impl QemuDevice for Microvm {
+    fn get_qemu_args(&self, _index: usize) -> Vec<QemuArgument> {
+        vec![
+            QemuArgument::new(
+                "-machine",
+                "microvm,x-option-roms=off,pit=off,pic=off,isa-serial=on,rtc=on",
+            ),
+            // scsi-hd disks are attached to scsihw0, like the pvscsi controller of q35
+            QemuArgument::new("-device", "virtio-scsi-device,id=scsihw0"),
+        ]
+    }
+}
+
+#[typetag::deserialize(name = "microvm")]
+impl Chipset for Microvm {
+    fn has_pci_bus(&self) -> bool {
+        false
+    }
+
+    fn has_serial_console(&self) -> bool {
+        true
+    }
+
+    fn needs_firmware(&self) -> bool {
+        false
+    }
+
+    /// the kernel talks to the serial console, which is the only console a microvm has
+    fn get_default_cmdline(&self) -> Option<&'static str> {
+        Some("console=ttyS0")
+    }
+
+    fn get_ide_buses(&self) -> usize {
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unit_test() {
+        let microvm: Microvm = serde_yaml::from_str("{}").unwrap();
+        assert_eq!(
+            microvm.get_qemu_args(0),
+            vec![
+                QemuArgument::new(
+                    "-machine",
+                    "microvm,x-option-roms=off,pit=off,pic=off,isa-serial=on,rtc=on"
+                ),
+                QemuArgument::new("-device", "virtio-scsi-device,id=scsihw0"),
+            ]
+        );
+
+        // the kernel is set in system.boot
+        assert!(serde_yaml::from_str::<Microvm>("kernel: /vms/ci/vmlinuz").is_err());
+    }
+}
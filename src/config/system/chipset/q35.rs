@@ -1,57 +1,383 @@
-use crate::config::system::chipset::Chipset;
-use crate::config::types::QemuDevice;
+use crate::config::pci_allocator::PciBus;
+use crate::config::system::chipset::{machine_type, validate_version, Chipset};
+use crate::config::types::{QemuArgument, QemuDevice};
+use crate::config::{Config, Finding};
 use serde::Deserialize;
 
-const PVE_CONFIG_FILE: &str = "/usr/share/ezkvm/pve-q35-4.0.cfg";
+const MACHINE: &str = "pc-q35";
 
-#[derive(Deserialize, Debug, Clone)]
-pub struct Q35 {}
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Q35 {
+    /// the machine version, like 8.1; when left out, the newest the installed qemu supports
+    #[serde(default)]
+    version: Option<String>,
+}
 
 impl Q35 {
     #[cfg(test)]
     pub fn new() -> Self {
-        Self {}
+        Self { version: None }
+    }
+    #[cfg(test)]
+    pub fn with_version(self, version: &str) -> Self {
+        Self {
+            version: Some(version.to_string()),
+        }
     }
     pub fn boxed_default() -> Box<Self> {
-        Box::new(Self {})
+        Box::new(Self::default())
+    }
+}
+
+impl Q35 {
+    /// the pci(e) topology that used to be read from proxmox's pve-q35-4.0.cfg; the bus names
+    /// and addresses must not change, since existing guests have bound drivers to them
+    fn get_topology_args(&self) -> Vec<QemuArgument> {
+        let mut result = vec![];
+        // two usb 2 controllers, each with three usb 1 companions
+        for (ehci, id, slot) in [(1, "ehci", 0x1d), (2, "ehci-2", 0x1a)] {
+            result.push(QemuArgument::new(
+                "-device",
+                format!(
+                    "ich9-usb-ehci{},id={},multifunction=on,bus=pcie.0,addr={:#x}.0x7",
+                    ehci, id, slot
+                ),
+            ));
+            for function in 0..3 {
+                let uhci = (ehci - 1) * 3 + function + 1;
+                result.push(QemuArgument::new("-device", format!(
+                    "ich9-usb-uhci{},id=uhci-{},multifunction=on,bus=pcie.0,addr={:#x}.0x{},masterbus={}.0,firstport={}",
+                    uhci,
+                    uhci,
+                    slot,
+                    function,
+                    id,
+                    function * 2
+                )));
+            }
+        }
+        for port in 1..=4 {
+            result.push(QemuArgument::new("-device", format!(
+                "pcie-root-port,id=ich9-pcie-port-{},multifunction=on,bus=pcie.0,addr=0x1c.0x{},port={},chassis={},x-speed=16,x-width=32",
+                port,
+                port - 1,
+                port,
+                port
+            )));
+        }
+        result.push(QemuArgument::new(
+            "-device",
+            "i82801b11-bridge,id=pcidmi,bus=pcie.0,addr=0x1e",
+        ));
+        for bridge in 0..=2 {
+            result.push(QemuArgument::new(
+                "-device",
+                format!(
+                    "pci-bridge,id=pci.{},chassis_nr={},bus=pcidmi,addr=0x{}",
+                    bridge,
+                    bridge + 1,
+                    bridge + 1
+                ),
+            ));
+        }
+        result
     }
 }
 
 impl QemuDevice for Q35 {
-    fn get_qemu_args(&self, _index: usize) -> Vec<String> {
-        vec![
-            "-machine hpet=off,type=pc-q35-8.1".to_string(),
-            "-rtc driftfix=slew,base=localtime".to_string(),
-            "-global kvm-pit.lost_tick_policy=discard".to_string(),
-            format!("-readconfig {}", PVE_CONFIG_FILE),
-            "-device qemu-xhci,p2=15,p3=15,id=xhci,bus=pci.1,addr=0x1b".to_string(),
-            "-iscsi initiator-name=iqn.1993-08.org.debian:01:39407ad058b".to_string(),
-            "-device pvscsi,id=scsihw0,bus=pci.0,addr=0x5".to_string(),
-        ]
+    fn get_qemu_args(&self, _index: usize) -> Vec<QemuArgument> {
+        let mut result = vec![
+            QemuArgument::new(
+                "-machine",
+                format!(
+                    "hpet=off,type={}",
+                    machine_type(MACHINE, "q35", &self.version)
+                ),
+            ),
+            QemuArgument::new("-rtc", "driftfix=slew,base=localtime"),
+            QemuArgument::new("-global", "kvm-pit.lost_tick_policy=discard"),
+        ];
+        result.extend(self.get_topology_args());
+        result.extend(vec![
+            QemuArgument::new(
+                "-device",
+                "qemu-xhci,p2=15,p3=15,id=xhci,bus=pci.1,addr=0x1b",
+            ),
+            QemuArgument::new(
+                "-iscsi",
+                "initiator-name=iqn.1993-08.org.debian:01:39407ad058b",
+            ),
+            QemuArgument::new("-device", "pvscsi,id=scsihw0,bus=pci.0,addr=0x5"),
+        ]);
+        result
+    }
+
+    fn validate(&self, _config: &Config) -> Vec<Finding> {
+        validate_version(MACHINE, &self.version)
     }
 }
 
 #[typetag::deserialize(name = "q35")]
-impl Chipset for Q35 {}
+impl Chipset for Q35 {
+    fn get_pci_buses(&self) -> Vec<PciBus> {
+        let mut result = vec![
+            PciBus::new("pci.0", 0x0..=0x1f).with_reserved(vec![0x5]), // pvscsi
+            PciBus::new("pci.1", 0x0..=0x1f).with_reserved(vec![0x1b]), // xhci
+            PciBus::new("pci.2", 0x0..=0x1f),
+        ];
+        // a pcie root port has a single slot
+        for port in 1..=4 {
+            result.push(PciBus::new(&format!("ich9-pcie-port-{}", port), 0x0..=0x0));
+        }
+        // the host bridge, the usb controllers, the root ports, the dmi bridge and the lpc
+        result.push(
+            PciBus::new("pcie.0", 0x0..=0x1f)
+                .with_reserved(vec![0x0, 0x1a, 0x1c, 0x1d, 0x1e, 0x1f]),
+        );
+        result
+    }
+}
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    #[mockall_double::double]
+    use crate::osal::Osal;
+    use serial_test::serial;
+    use std::collections::BTreeMap;
 
     #[test]
     fn unit_test() {
-        let q35 = Q35 {};
+        let q35 = Q35::new().with_version("8.1");
         assert_eq!(
             q35.get_qemu_args(0),
             vec![
-                "-machine hpet=off,type=pc-q35-8.1".to_string(),
-                "-rtc driftfix=slew,base=localtime".to_string(),
-                "-global kvm-pit.lost_tick_policy=discard".to_string(),
-                "-readconfig /usr/share/ezkvm/pve-q35-4.0.cfg".to_string(),
-                "-device qemu-xhci,p2=15,p3=15,id=xhci,bus=pci.1,addr=0x1b".to_string(),
-                "-iscsi initiator-name=iqn.1993-08.org.debian:01:39407ad058b".to_string(),
-                "-device pvscsi,id=scsihw0,bus=pci.0,addr=0x5".to_string()
+                QemuArgument::new("-machine", "hpet=off,type=pc-q35-8.1"),
+                QemuArgument::new("-rtc", "driftfix=slew,base=localtime"),
+                QemuArgument::new("-global", "kvm-pit.lost_tick_policy=discard"),
+                QemuArgument::new("-device", "ich9-usb-ehci1,id=ehci,multifunction=on,bus=pcie.0,addr=0x1d.0x7"),
+                QemuArgument::new("-device", "ich9-usb-uhci1,id=uhci-1,multifunction=on,bus=pcie.0,addr=0x1d.0x0,masterbus=ehci.0,firstport=0"),
+                QemuArgument::new("-device", "ich9-usb-uhci2,id=uhci-2,multifunction=on,bus=pcie.0,addr=0x1d.0x1,masterbus=ehci.0,firstport=2"),
+                QemuArgument::new("-device", "ich9-usb-uhci3,id=uhci-3,multifunction=on,bus=pcie.0,addr=0x1d.0x2,masterbus=ehci.0,firstport=4"),
+                QemuArgument::new("-device", "ich9-usb-ehci2,id=ehci-2,multifunction=on,bus=pcie.0,addr=0x1a.0x7"),
+                QemuArgument::new("-device", "ich9-usb-uhci4,id=uhci-4,multifunction=on,bus=pcie.0,addr=0x1a.0x0,masterbus=ehci-2.0,firstport=0"),
+                QemuArgument::new("-device", "ich9-usb-uhci5,id=uhci-5,multifunction=on,bus=pcie.0,addr=0x1a.0x1,masterbus=ehci-2.0,firstport=2"),
+                QemuArgument::new("-device", "ich9-usb-uhci6,id=uhci-6,multifunction=on,bus=pcie.0,addr=0x1a.0x2,masterbus=ehci-2.0,firstport=4"),
+                QemuArgument::new("-device", "pcie-root-port,id=ich9-pcie-port-1,multifunction=on,bus=pcie.0,addr=0x1c.0x0,port=1,chassis=1,x-speed=16,x-width=32"),
+                QemuArgument::new("-device", "pcie-root-port,id=ich9-pcie-port-2,multifunction=on,bus=pcie.0,addr=0x1c.0x1,port=2,chassis=2,x-speed=16,x-width=32"),
+                QemuArgument::new("-device", "pcie-root-port,id=ich9-pcie-port-3,multifunction=on,bus=pcie.0,addr=0x1c.0x2,port=3,chassis=3,x-speed=16,x-width=32"),
+                QemuArgument::new("-device", "pcie-root-port,id=ich9-pcie-port-4,multifunction=on,bus=pcie.0,addr=0x1c.0x3,port=4,chassis=4,x-speed=16,x-width=32"),
+                QemuArgument::new("-device", "i82801b11-bridge,id=pcidmi,bus=pcie.0,addr=0x1e"),
+                QemuArgument::new("-device", "pci-bridge,id=pci.0,chassis_nr=1,bus=pcidmi,addr=0x1"),
+                QemuArgument::new("-device", "pci-bridge,id=pci.1,chassis_nr=2,bus=pcidmi,addr=0x2"),
+                QemuArgument::new("-device", "pci-bridge,id=pci.2,chassis_nr=3,bus=pcidmi,addr=0x3"),
+                QemuArgument::new("-device", "qemu-xhci,p2=15,p3=15,id=xhci,bus=pci.1,addr=0x1b"),
+                QemuArgument::new("-iscsi", "initiator-name=iqn.1993-08.org.debian:01:39407ad058b"),
+                QemuArgument::new("-device", "pvscsi,id=scsihw0,bus=pci.0,addr=0x5")
+            ]
+        );
+    }
+
+    /// the devices of proxmox's pve-q35-4.0.cfg, which ezkvm used to pass with -readconfig
+    const PVE_Q35_CFG: &str = r#"
+[device "ehci"]
+  driver = "ich9-usb-ehci1"
+  multifunction = "on"
+  bus = "pcie.0"
+  addr = "1d.7"
+
+[device "uhci-1"]
+  driver = "ich9-usb-uhci1"
+  multifunction = "on"
+  bus = "pcie.0"
+  addr = "1d.0"
+  masterbus = "ehci.0"
+  firstport = "0"
+
+[device "uhci-2"]
+  driver = "ich9-usb-uhci2"
+  multifunction = "on"
+  bus = "pcie.0"
+  addr = "1d.1"
+  masterbus = "ehci.0"
+  firstport = "2"
+
+[device "uhci-3"]
+  driver = "ich9-usb-uhci3"
+  multifunction = "on"
+  bus = "pcie.0"
+  addr = "1d.2"
+  masterbus = "ehci.0"
+  firstport = "4"
+
+[device "ehci-2"]
+  driver = "ich9-usb-ehci2"
+  multifunction = "on"
+  bus = "pcie.0"
+  addr = "1a.7"
+
+[device "uhci-4"]
+  driver = "ich9-usb-uhci4"
+  multifunction = "on"
+  bus = "pcie.0"
+  addr = "1a.0"
+  masterbus = "ehci-2.0"
+  firstport = "0"
+
+[device "uhci-5"]
+  driver = "ich9-usb-uhci5"
+  multifunction = "on"
+  bus = "pcie.0"
+  addr = "1a.1"
+  masterbus = "ehci-2.0"
+  firstport = "2"
+
+[device "uhci-6"]
+  driver = "ich9-usb-uhci6"
+  multifunction = "on"
+  bus = "pcie.0"
+  addr = "1a.2"
+  masterbus = "ehci-2.0"
+  firstport = "4"
+
+[device "ich9-pcie-port-1"]
+  driver = "pcie-root-port"
+  x-speed = "16"
+  x-width = "32"
+  multifunction = "on"
+  bus = "pcie.0"
+  addr = "1c.0"
+  port = "1"
+  chassis = "1"
+
+[device "ich9-pcie-port-2"]
+  driver = "pcie-root-port"
+  x-speed = "16"
+  x-width = "32"
+  multifunction = "on"
+  bus = "pcie.0"
+  addr = "1c.1"
+  port = "2"
+  chassis = "2"
+
+[device "ich9-pcie-port-3"]
+  driver = "pcie-root-port"
+  x-speed = "16"
+  x-width = "32"
+  multifunction = "on"
+  bus = "pcie.0"
+  addr = "1c.2"
+  port = "3"
+  chassis = "3"
+
+[device "ich9-pcie-port-4"]
+  driver = "pcie-root-port"
+  x-speed = "16"
+  x-width = "32"
+  multifunction = "on"
+  bus = "pcie.0"
+  addr = "1c.3"
+  port = "4"
+  chassis = "4"
+
+[device "pcidmi"]
+  driver = "i82801b11-bridge"
+  bus = "pcie.0"
+  addr = "1e.0"
+
+[device "pci.0"]
+  driver = "pci-bridge"
+  bus = "pcidmi"
+  addr = "1.0"
+  chassis_nr = "1"
+
+[device "pci.1"]
+  driver = "pci-bridge"
+  bus = "pcidmi"
+  addr = "2.0"
+  chassis_nr = "2"
+
+[device "pci.2"]
+  driver = "pci-bridge"
+  bus = "pcidmi"
+  addr = "3.0"
+  chassis_nr = "3"
+"#;
+
+    /// the devices in a -readconfig file, as (id, properties) in the order they are listed
+    fn read_config_devices(cfg: &str) -> Vec<(String, BTreeMap<String, String>)> {
+        let mut result: Vec<(String, BTreeMap<String, String>)> = vec![];
+        for line in cfg.lines().map(str::trim) {
+            if let Some(id) = line.strip_prefix("[device \"") {
+                result.push((id.trim_end_matches("\"]").to_string(), BTreeMap::new()));
+            } else if let Some((key, value)) = line.split_once(" = ") {
+                let properties = &mut result.last_mut().unwrap().1;
+                properties.insert(key.to_string(), value.trim_matches('"').to_string());
+            }
+        }
+        result
+    }
+
+    /// the same, for -device arguments; "0x1d.0x7" is written as "1d.7" in a config file
+    fn device_args(args: Vec<QemuArgument>) -> Vec<(String, BTreeMap<String, String>)> {
+        let mut result = vec![];
+        for arg in args.iter().filter(|arg| arg.get_flag() == "-device") {
+            let mut options = arg.get_value().unwrap().split(',');
+            let mut properties =
+                BTreeMap::from([("driver".to_string(), options.next().unwrap().to_string())]);
+            for option in options {
+                let (key, value) = option.split_once('=').unwrap();
+                properties.insert(key.to_string(), value.to_string());
+            }
+            if let Some(addr) = properties.get_mut("addr") {
+                let (slot, function) = addr.split_once('.').unwrap_or((addr, "0"));
+                *addr = format!(
+                    "{}.{}",
+                    slot.trim_start_matches("0x"),
+                    function.trim_start_matches("0x")
+                );
+            }
+            result.push((properties.remove("id").unwrap(), properties));
+        }
+        result
+    }
+
+    #[test]
+    fn test_topology_matches_pve_q35_cfg() {
+        assert_eq!(
+            device_args(Q35::new().get_topology_args()),
+            read_config_devices(PVE_Q35_CFG)
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn test_version() {
+        let get_machine_types = Osal::get_machine_types_context();
+        get_machine_types.expect().returning(|_| {
+            vec![
+                "pc-q35-8.1".to_string(),
+                "pc-q35-9.2".to_string(),
+                "q35".to_string(),
             ]
+        });
+
+        let machine = |q35: &Q35| q35.get_qemu_args(0)[0].clone();
+        assert_eq!(
+            machine(&Q35::new()),
+            QemuArgument::new("-machine", "hpet=off,type=pc-q35-9.2")
         );
+        assert_eq!(
+            machine(&Q35::new().with_version("7.2")),
+            QemuArgument::new("-machine", "hpet=off,type=pc-q35-7.2")
+        );
+
+        let config = Config::default();
+        assert!(Q35::new().validate(&config).is_empty());
+        assert!(Q35::new().with_version("8.1").validate(&config).is_empty());
+        let findings = Q35::new().with_version("7.2").validate(&config);
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].get_path(), "version");
     }
 }
@@ -1,25 +1,94 @@
-use crate::config::types::QemuDevice;
+use crate::config::types::{QemuArgument, QemuDevice};
+use crate::config::{Config, Finding};
+#[mockall_double::double]
+use crate::osal::Osal;
 use serde::Deserialize;
 
+/// a named set of cpu flags, for a kind of guest
+#[derive(Deserialize, PartialEq, Debug, Copy, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub enum CpuPreset {
+    /// the hyper-v enlightenments that windows guests use
+    WindowsHyperv,
+    /// hides kvm from the guest, for gpu drivers that refuse to run in a vm
+    GpuPassthrough,
+    /// what macOS expects of an intel mac
+    Macos,
+    /// vmx or svm, depending on the host, for running vms in the guest
+    NestedVirt,
+}
+
+impl CpuPreset {
+    fn get_flags(&self) -> Vec<String> {
+        let flags: &[&str] = match self {
+            CpuPreset::WindowsHyperv => &[
+                "hv-relaxed",
+                "hv-vapic",
+                "hv-spinlocks=0x1fff",
+                "hv-vpindex",
+                "hv-runtime",
+                "hv-synic",
+                "hv-stimer",
+                "hv-reset",
+                "hv-time",
+                "hv-frequencies",
+                "hv-tlbflush",
+                "hv-ipi",
+            ],
+            CpuPreset::GpuPassthrough => &["kvm=off", "hv-vendor-id=ezkvm"],
+            CpuPreset::Macos => &[
+                "vendor=GenuineIntel",
+                "+invtsc",
+                "+hypervisor",
+                "+pcid",
+                "+invpcid",
+                "+erms",
+                "kvm=on",
+                "vmware-cpuid-freq=on",
+            ],
+            CpuPreset::NestedVirt => match Osal::get_cpu_vendor().as_deref() {
+                Some("AuthenticAMD") => &["+svm"],
+                _ => &["+vmx"],
+            },
+        };
+        flags.iter().map(|flag| flag.to_string()).collect()
+    }
+}
+
 #[derive(Deserialize, PartialEq, Debug, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct Cpu {
     #[serde(default = "default_cpu_model")]
     model: String,
+    /// only for the host model, which qemu makes migratable unless told otherwise
+    #[serde(default)]
+    migratable: Option<bool>,
     #[serde(default = "default_cpu_sockets")]
     sockets: u32,
+    #[serde(default = "default_cpu_count")]
+    dies: u32,
     #[serde(default = "default_cpu_cores")]
     cores: u32,
-    #[serde(default = "default_cpu_flags")]
-    flags: String,
+    #[serde(default = "default_cpu_count")]
+    threads: u32,
+    #[serde(default)]
+    presets: Vec<CpuPreset>,
+    /// when left out, only the default model gets the default flags
+    #[serde(default)]
+    flags: Option<String>,
 }
 
 impl Default for Cpu {
     fn default() -> Self {
         Self {
             model: default_cpu_model(),
+            migratable: None,
             sockets: default_cpu_sockets(),
+            dies: default_cpu_count(),
             cores: default_cpu_cores(),
-            flags: default_cpu_flags(),
+            threads: default_cpu_count(),
+            presets: vec![],
+            flags: None,
         }
     }
 }
@@ -36,44 +105,151 @@ fn default_cpu_cores() -> u32 {
     4
 }
 
+fn default_cpu_count() -> u32 {
+    1
+}
+
 fn default_cpu_flags() -> String {
     "+aes,+pni,+popcnt,+sse4.1,+sse4.2,+ssse3,enforce".to_string()
 }
 
+/// the feature a flag is about, so "+hv_time", "-hv-time" and "hv-time=on" all are "hv-time"
+fn flag_name(flag: &str) -> String {
+    let flag = flag.trim_start_matches(['+', '-', '!']);
+    let name = flag.split_once('=').map_or(flag, |(name, _value)| name);
+    name.replace('_', "-")
+}
+
 impl Cpu {
     pub fn new(model: String, sockets: u32, cores: u32, flags: String) -> Self {
         Self {
             model,
             sockets,
             cores,
-            flags,
+            flags: Some(flags),
+            ..Default::default()
+        }
+    }
+
+    /// the flags of the presets, followed by the flags of the user; a user flag replaces the
+    /// one about the same feature, and "!feature" drops it
+    fn get_flags(&self) -> Vec<String> {
+        let mut result: Vec<String> = vec![];
+        // the default flags are qemu64 flags, they would limit a chosen model or a preset
+        let flags = match &self.flags {
+            Some(flags) => flags.clone(),
+            None if self.model == default_cpu_model() && self.presets.is_empty() => {
+                default_cpu_flags()
+            }
+            None => String::new(),
+        };
+        let flags = flags.split(',').map(str::trim).map(str::to_string);
+        for flag in self
+            .presets
+            .iter()
+            .flat_map(CpuPreset::get_flags)
+            .chain(flags)
+        {
+            if flag.is_empty() {
+                continue;
+            }
+            let name = flag_name(&flag);
+            result.retain(|known| flag_name(known) != name);
+            if !flag.starts_with('!') {
+                result.push(flag);
+            }
+        }
+        result
+    }
+
+    /// the number of vcpus, or None when it does not fit in a u32
+    fn get_total(&self) -> Option<u32> {
+        self.sockets
+            .checked_mul(self.dies)?
+            .checked_mul(self.cores)?
+            .checked_mul(self.threads)
+    }
+
+    fn get_model(&self) -> String {
+        match self.migratable {
+            Some(true) => format!("{},migratable=on", self.model),
+            Some(false) => format!("{},migratable=off", self.model),
+            None => self.model.clone(),
         }
     }
 }
 
 impl QemuDevice for Cpu {
-    fn get_qemu_args(&self, _index: usize) -> Vec<String> {
-        let total = self.sockets * self.cores;
+    fn get_qemu_args(&self, _index: usize) -> Vec<QemuArgument> {
+        // validate() reports a topology that overflows
+        let total = self.get_total().unwrap_or(u32::MAX);
+        let mut topology = format!("{},sockets={}", total, self.sockets);
+        // leave out what qemu defaults to, so it works for machines without dies as well
+        if self.dies != 1 {
+            topology.push_str(&format!(",dies={}", self.dies));
+        }
+        topology.push_str(&format!(",cores={}", self.cores));
+        if self.threads != 1 {
+            topology.push_str(&format!(",threads={}", self.threads));
+        }
+        topology.push_str(&format!(",maxcpus={}", total));
+
+        let mut cpu = vec![self.get_model()];
+        cpu.extend(self.get_flags());
         vec![
-            format!(
-                "-smp {},sockets={},cores={},maxcpus={}",
-                total, self.sockets, self.cores, total
-            ),
-            format!("-cpu {},{}", self.model, self.flags),
+            QemuArgument::new("-smp", topology),
+            QemuArgument::new("-cpu", cpu.join(",")),
         ]
     }
+
+    fn validate(&self, _config: &Config) -> Vec<Finding> {
+        let mut result = vec![];
+        for (path, count) in [
+            ("sockets", self.sockets),
+            ("dies", self.dies),
+            ("cores", self.cores),
+            ("threads", self.threads),
+        ] {
+            if count == 0 {
+                result.push(Finding::error(path, "has to be at least 1"));
+            }
+        }
+        if self.get_total().is_none() {
+            result.push(Finding::error(
+                "",
+                format!(
+                    "{} sockets, {} dies, {} cores and {} threads are too many vcpus",
+                    self.sockets, self.dies, self.cores, self.threads
+                ),
+            ));
+        }
+        if self.migratable.is_some() && self.model != "host" && self.model != "max" {
+            result.push(Finding::error(
+                "migratable",
+                format!(
+                    "only the host and max models are migratable, not {}",
+                    self.model
+                ),
+            ));
+        }
+        result
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use serial_test::serial;
 
     #[test]
     fn test_empty_input() {
         let cpu: Cpu = serde_yaml::from_str("").unwrap();
-        let expected: Vec<String> = vec![
-            "-smp 4,sockets=1,cores=4,maxcpus=4".to_string(),
-            "-cpu qemu64,+aes,+pni,+popcnt,+sse4.1,+sse4.2,+ssse3,enforce".to_string(),
+        let expected: Vec<QemuArgument> = vec![
+            QemuArgument::new("-smp", "4,sockets=1,cores=4,maxcpus=4"),
+            QemuArgument::new(
+                "-cpu",
+                "qemu64,+aes,+pni,+popcnt,+sse4.1,+sse4.2,+ssse3,enforce",
+            ),
         ];
         assert_eq!(cpu.get_qemu_args(0), expected);
     }
@@ -88,25 +264,154 @@ mod tests {
         "#;
 
         let cpu: Cpu = serde_yaml::from_str(input).unwrap();
-        let expected: Vec<String> = vec![
-            "-smp 8,sockets=1,cores=8,maxcpus=8".to_string(),
-            "-cpu qemu64,+aes,+pni,+popcnt,+sse4.1,+sse4.2,+ssse3,enforce".to_string(),
+        let expected: Vec<QemuArgument> = vec![
+            QemuArgument::new("-smp", "8,sockets=1,cores=8,maxcpus=8"),
+            QemuArgument::new(
+                "-cpu",
+                "qemu64,+aes,+pni,+popcnt,+sse4.1,+sse4.2,+ssse3,enforce",
+            ),
         ];
         assert_eq!(cpu.get_qemu_args(0), expected);
     }
 
     #[test]
     fn test_get_qemu_args() {
-        let cpu = Cpu {
-            model: "my_model".to_string(),
-            sockets: 2,
-            cores: 6,
-            flags: "my_flags".to_string(),
-        };
-        let expected: Vec<String> = vec![
-            "-smp 12,sockets=2,cores=6,maxcpus=12".to_string(),
-            "-cpu my_model,my_flags".to_string(),
+        let cpu = Cpu::new("my_model".to_string(), 2, 6, "my_flags".to_string());
+        let expected: Vec<QemuArgument> = vec![
+            QemuArgument::new("-smp", "12,sockets=2,cores=6,maxcpus=12"),
+            QemuArgument::new("-cpu", "my_model,my_flags"),
+        ];
+        assert_eq!(cpu.get_qemu_args(0), expected);
+    }
+
+    #[test]
+    fn test_host() {
+        let input =
+            r#"{ model: "host", migratable: false, dies: 2, cores: 4, threads: 2, flags: "" }"#;
+        let cpu: Cpu = serde_yaml::from_str(input).unwrap();
+        let expected: Vec<QemuArgument> = vec![
+            QemuArgument::new("-smp", "16,sockets=1,dies=2,cores=4,threads=2,maxcpus=16"),
+            QemuArgument::new("-cpu", "host,migratable=off"),
         ];
         assert_eq!(cpu.get_qemu_args(0), expected);
+        assert!(cpu.validate(&Config::default()).is_empty());
+    }
+
+    #[test]
+    fn test_default_flags_only_for_the_default_model() {
+        let default_flags = |input: &str| {
+            let cpu: Cpu = serde_yaml::from_str(input).unwrap();
+            cpu.get_qemu_args(0)[1].clone()
+        };
+        assert_eq!(
+            default_flags("{ cores: 2 }"),
+            QemuArgument::new(
+                "-cpu",
+                "qemu64,+aes,+pni,+popcnt,+sse4.1,+sse4.2,+ssse3,enforce"
+            )
+        );
+        assert_eq!(
+            default_flags("{ model: host }"),
+            QemuArgument::new("-cpu", "host")
+        );
+        assert_eq!(
+            default_flags("{ model: max }"),
+            QemuArgument::new("-cpu", "max")
+        );
+        assert_eq!(
+            default_flags("{ model: host, presets: [ windows-hyperv ] }"),
+            QemuArgument::new(
+                "-cpu",
+                "host,hv-relaxed,hv-vapic,hv-spinlocks=0x1fff,hv-vpindex,hv-runtime,hv-synic,\
+                hv-stimer,hv-reset,hv-time,hv-frequencies,hv-tlbflush,hv-ipi"
+            )
+        );
+    }
+
+    #[test]
+    fn test_presets() {
+        let input = r#"
+            model: "host"
+            presets: [ "windows-hyperv", "gpu-passthrough" ]
+            flags: "-hv_ipi,!hv-reset,hv-vendor-id=1234567890ab,+topoext"
+        "#;
+        let cpu: Cpu = serde_yaml::from_str(input).unwrap();
+        assert_eq!(
+            cpu.get_qemu_args(0)[1],
+            QemuArgument::new(
+                "-cpu",
+                "host,hv-relaxed,hv-vapic,hv-spinlocks=0x1fff,hv-vpindex,hv-runtime,hv-synic,\
+                hv-stimer,hv-time,hv-frequencies,hv-tlbflush,kvm=off,-hv_ipi,\
+                hv-vendor-id=1234567890ab,+topoext"
+            )
+        );
+
+        let input = r#"{ model: "Haswell-noTSX", presets: [ "macos" ], flags: "" }"#;
+        let cpu: Cpu = serde_yaml::from_str(input).unwrap();
+        assert_eq!(
+            cpu.get_qemu_args(0)[1],
+            QemuArgument::new(
+                "-cpu",
+                "Haswell-noTSX,vendor=GenuineIntel,+invtsc,+hypervisor,+pcid,+invpcid,+erms,\
+                kvm=on,vmware-cpuid-freq=on"
+            )
+        );
+
+        assert!(serde_yaml::from_str::<Cpu>("{ presets: [ windows ] }").is_err());
+    }
+
+    #[test]
+    #[serial]
+    fn test_nested_virt() {
+        let get_cpu_vendor = Osal::get_cpu_vendor_context();
+        get_cpu_vendor
+            .expect()
+            .returning(|| Some("AuthenticAMD".to_string()));
+
+        let cpu: Cpu =
+            serde_yaml::from_str("{ presets: [ nested-virt ], flags: enforce }").unwrap();
+        assert_eq!(
+            cpu.get_qemu_args(0)[1],
+            QemuArgument::new("-cpu", "qemu64,+svm,enforce")
+        );
+
+        get_cpu_vendor.checkpoint();
+        get_cpu_vendor
+            .expect()
+            .returning(|| Some("GenuineIntel".to_string()));
+        assert_eq!(
+            cpu.get_qemu_args(0)[1],
+            QemuArgument::new("-cpu", "qemu64,+vmx,enforce")
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        let config = Config::default();
+        let cpu: Cpu = serde_yaml::from_str("{ migratable: true, threads: 0 }").unwrap();
+        assert_eq!(
+            cpu.validate(&config),
+            vec![
+                Finding::error("threads", "has to be at least 1"),
+                Finding::error(
+                    "migratable",
+                    "only the host and max models are migratable, not qemu64"
+                ),
+            ]
+        );
+
+        let cpu: Cpu =
+            serde_yaml::from_str("{ sockets: 65536, cores: 65536, threads: 2 }").unwrap();
+        assert_eq!(
+            cpu.validate(&config),
+            vec![Finding::error(
+                "",
+                "65536 sockets, 1 dies, 65536 cores and 2 threads are too many vcpus"
+            )]
+        );
+        assert_eq!(
+            cpu.get_qemu_args(0)[0].get_value(),
+            Some("4294967295,sockets=65536,cores=65536,threads=2,maxcpus=4294967295")
+        );
     }
 }
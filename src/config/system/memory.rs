@@ -1,7 +1,8 @@
-use crate::config::types::QemuDevice;
+use crate::config::types::{QemuArgument, QemuDevice};
 use serde::Deserialize;
 
 #[derive(Deserialize, PartialEq, Debug, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct Memory {
     max: u32,
     balloon: Option<bool>,
@@ -23,8 +24,8 @@ impl Default for Memory {
 }
 
 impl QemuDevice for Memory {
-    fn get_qemu_args(&self, _index: usize) -> Vec<String> {
-        vec![format!("-m {}", self.max)]
+    fn get_qemu_args(&self, _index: usize) -> Vec<QemuArgument> {
+        vec![QemuArgument::new("-m", self.max.to_string())]
     }
 }
 
@@ -38,7 +39,7 @@ mod tests {
             max: 16384,
             balloon: None,
         };
-        let expected: Vec<String> = vec!["-m 16384".to_string()];
+        let expected: Vec<QemuArgument> = vec![QemuArgument::new("-m", "16384")];
         assert_eq!(memory.get_qemu_args(0), expected);
     }
 }
@@ -6,13 +6,50 @@ pub use crate::config::system::tpm::pass_through_tpm::PassThroughTpm;
 pub use crate::config::system::tpm::swtpm::SwTpm;
 
 use crate::config::types::QemuDevice;
+use serde::Deserialize;
 
 mod no_tpm;
 mod pass_through_tpm;
 mod swtpm;
 
+/// the tpm interface that the guest sees
+#[derive(Deserialize, Default, Debug, Copy, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TpmModel {
+    #[default]
+    Tis,
+    Crb,
+    /// the tpm of ppc pseries machines
+    Spapr,
+}
+
+impl TpmModel {
+    pub fn device(&self) -> &'static str {
+        match self {
+            TpmModel::Tis => "tpm-tis",
+            TpmModel::Crb => "tpm-crb",
+            TpmModel::Spapr => "tpm-spapr",
+        }
+    }
+}
+
 #[typetag::deserialize(tag = "type")]
-pub trait Tpm: QemuDevice {}
+pub trait Tpm: QemuDevice {
+    /// the pid of the process that emulates the tpm, once it is started
+    fn get_pid(&self) -> Option<u32> {
+        None
+    }
+    /// the host device the tpm takes, which is recorded in the lock of the vm
+    fn get_host_resource(&self) -> Option<String> {
+        None
+    }
+    /// the interface the guest sees, if there is a tpm at all
+    fn get_model(&self) -> Option<TpmModel> {
+        None
+    }
+    /// lets the tpm keep its files in the runtime directory of the vm
+    fn use_runtime_dir(&mut self, _runtime_dir: &str) {}
+}
 impl Default for Box<dyn Tpm> {
     fn default() -> Self {
         NoTpm::boxed_default()
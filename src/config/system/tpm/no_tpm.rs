@@ -1,8 +1,9 @@
 use crate::config::system::tpm::Tpm;
-use crate::config::types::QemuDevice;
+use crate::config::types::{QemuArgument, QemuDevice};
 use serde::Deserialize;
 
 #[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct NoTpm {}
 impl NoTpm {
     pub fn boxed_default() -> Box<Self> {
@@ -11,7 +12,7 @@ impl NoTpm {
 }
 
 impl QemuDevice for NoTpm {
-    fn get_qemu_args(&self, _index: usize) -> Vec<String> {
+    fn get_qemu_args(&self, _index: usize) -> Vec<QemuArgument> {
         vec![]
     }
 }
@@ -26,7 +27,7 @@ mod tests {
     #[test]
     fn unit_test() {
         let no_tpm = NoTpm {};
-        let expected: Vec<String> = vec![];
+        let expected: Vec<QemuArgument> = vec![];
         assert_eq!(no_tpm.get_qemu_args(0), expected);
     }
 }
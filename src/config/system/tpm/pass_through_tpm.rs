@@ -1,14 +1,133 @@
-use crate::config::system::tpm::Tpm;
-use crate::config::types::QemuDevice;
+use crate::config::system::tpm::{Tpm, TpmModel};
+use crate::config::types::{QemuArgument, QemuDevice};
+use crate::config::{Config, Finding};
+#[mockall_double::double]
+use crate::osal::Osal;
 use serde::Deserialize;
 
+/// the tpm of the host, which only one vm at a time can use
 #[derive(Deserialize, Debug, Clone)]
-pub struct PassThroughTpm {}
+#[serde(deny_unknown_fields)]
+pub struct PassThroughTpm {
+    /// /dev/tpm0, or the resource manager at /dev/tpmrm0
+    #[serde(default = "PassThroughTpm::path_default")]
+    path: String,
+    /// the sysfs file to cancel a tpm command with; qemu looks it up when left out
+    #[serde(default)]
+    cancel_path: Option<String>,
+    #[serde(default)]
+    model: TpmModel,
+}
+
+impl PassThroughTpm {
+    fn path_default() -> String {
+        "/dev/tpm0".to_string()
+    }
+}
+
 impl QemuDevice for PassThroughTpm {
-    fn get_qemu_args(&self, _index: usize) -> Vec<String> {
-        todo!()
+    fn get_qemu_args(&self, index: usize) -> Vec<QemuArgument> {
+        let mut tpmdev = format!(
+            "passthrough,id=tpm{},path={}",
+            index,
+            QemuArgument::escape(&self.path)
+        );
+        if let Some(cancel_path) = &self.cancel_path {
+            tpmdev.push_str(&format!(
+                ",cancel-path={}",
+                QemuArgument::escape(cancel_path)
+            ));
+        }
+        vec![
+            QemuArgument::new("-tpmdev", tpmdev),
+            QemuArgument::new(
+                "-device",
+                format!("{},tpmdev=tpm{}", self.model.device(), index),
+            ),
+        ]
+    }
+
+    fn validate(&self, _config: &Config) -> Vec<Finding> {
+        let mut result = vec![];
+        if !Osal::path_exists(self.path.clone()) {
+            result.push(Finding::error(
+                "path",
+                format!("{} does not exist", self.path),
+            ));
+        }
+        if let Some(cancel_path) = &self.cancel_path {
+            if !Osal::path_exists(cancel_path.clone()) {
+                result.push(Finding::error(
+                    "cancel_path",
+                    format!("{} does not exist", cancel_path),
+                ));
+            }
+        }
+        result
     }
 }
 
 #[typetag::deserialize(name = "passthrough")]
-impl Tpm for PassThroughTpm {}
+impl Tpm for PassThroughTpm {
+    /// /dev/tpm0 and /dev/tpmrm0 are the same tpm, so the claim does not name the path
+    fn get_host_resource(&self) -> Option<String> {
+        Some("tpm:host".to_string())
+    }
+
+    fn get_model(&self) -> Option<TpmModel> {
+        Some(self.model)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serial_test::serial;
+
+    #[test]
+    fn unit_test() {
+        let tpm: PassThroughTpm = serde_yaml::from_str("{}").unwrap();
+        assert_eq!(
+            tpm.get_qemu_args(0),
+            vec![
+                QemuArgument::new("-tpmdev", "passthrough,id=tpm0,path=/dev/tpm0"),
+                QemuArgument::new("-device", "tpm-tis,tpmdev=tpm0"),
+            ]
+        );
+
+        let tpm: PassThroughTpm = serde_yaml::from_str(
+            "{ path: /dev/tpmrm0, cancel_path: /sys/class/tpm/tpm0/device/cancel, model: crb }",
+        )
+        .unwrap();
+        assert_eq!(
+            tpm.get_qemu_args(0),
+            vec![
+                QemuArgument::new(
+                    "-tpmdev",
+                    "passthrough,id=tpm0,path=/dev/tpmrm0,cancel-path=/sys/class/tpm/tpm0/device/cancel"
+                ),
+                QemuArgument::new("-device", "tpm-crb,tpmdev=tpm0"),
+            ]
+        );
+        assert_eq!(tpm.get_host_resource(), Some("tpm:host".to_string()));
+    }
+
+    #[test]
+    #[serial]
+    fn test_validate() {
+        let path_exists = Osal::path_exists_context();
+        path_exists
+            .expect()
+            .returning(|path: String| path == "/dev/tpm0");
+
+        let tpm: PassThroughTpm =
+            serde_yaml::from_str("{ cancel_path: /sys/class/tpm/tpm0/device/cancel }").unwrap();
+        assert_eq!(
+            tpm.validate(&Config::default()),
+            vec![Finding::error(
+                "cancel_path",
+                "/sys/class/tpm/tpm0/device/cancel does not exist"
+            )]
+        );
+    }
+}
@@ -1,27 +1,84 @@
-use crate::config::system::tpm::Tpm;
-use crate::config::types::QemuDevice;
-use crate::config::Config;
+use crate::config::system::tpm::{Tpm, TpmModel};
+use crate::config::types::{QemuArgument, QemuDevice};
+use crate::config::{Config, General};
 //use crate::{get_swtpm_uid_and_gid};
-use crate::osal::{Osal, OsalError};
+#[mockall_double::double]
+use crate::osal::Osal;
+use crate::osal::OsalError;
 use log::{debug, warn};
 use serde::Deserialize;
 use std::os::unix::process::CommandExt;
 use std::process::{Child, Command};
+use std::sync::OnceLock;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const SOCKET_TIMEOUT: Duration = Duration::from_secs(5);
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+#[derive(Deserialize, Default, Debug, Copy, Clone, PartialEq)]
+pub enum SwTpmVersion {
+    #[serde(rename = "1.2")]
+    Tpm12,
+    #[default]
+    #[serde(rename = "2.0")]
+    Tpm20,
+}
 
 #[allow(dead_code)]
 #[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct SwTpm {
     disk: String,
-    socket: String,
+    /// the control socket; when left out, ezkvm keeps it in the runtime directory of the vm
+    #[serde(default)]
+    socket: Option<String>,
+    #[serde(default)]
+    version: SwTpmVersion,
+    #[serde(default)]
+    model: TpmModel,
+    #[serde(skip, default = "SwTpm::runtime_dir_default")]
+    runtime_dir: String,
+    /// the pid of swtpm, once pre_start() has started it
+    #[serde(skip)]
+    pid: OnceLock<u32>,
 }
 
 #[typetag::deserialize(name = "swtpm")]
-impl Tpm for SwTpm {}
+impl Tpm for SwTpm {
+    fn get_pid(&self) -> Option<u32> {
+        self.pid.get().copied()
+    }
+
+    fn get_model(&self) -> Option<TpmModel> {
+        Some(self.model)
+    }
+
+    fn use_runtime_dir(&mut self, runtime_dir: &str) {
+        self.runtime_dir = runtime_dir.to_string();
+    }
+}
 
 impl SwTpm {
     #[cfg(test)]
     pub fn new(disk: String, socket: String) -> Self {
-        Self { disk, socket }
+        Self {
+            disk,
+            socket: Some(socket),
+            version: SwTpmVersion::default(),
+            model: TpmModel::default(),
+            runtime_dir: SwTpm::runtime_dir_default(),
+            pid: OnceLock::new(),
+        }
+    }
+    fn runtime_dir_default() -> String {
+        General::default().runtime_dir()
+    }
+    fn socket(&self) -> String {
+        match &self.socket {
+            Some(socket) => socket.clone(),
+            None => format!("{}/swtpm.socket", self.runtime_dir),
+        }
     }
     fn spawn(&self, uid: u32, gid: u32, name: String) -> Result<Child, OsalError> {
         debug!(
@@ -40,55 +97,146 @@ impl SwTpm {
         )
     }
 
+    /// creates the tpm state, with an endorsement key and the certificates that swtpm_localca
+    /// signs, as configured in swtpm_setup.conf
+    fn setup(&self, uid: u32, gid: u32) -> Result<(), OsalError> {
+        debug!("SwTpm::setup() disk: {}", self.disk);
+
+        let mut child = Osal::execute_command(
+            Command::new("/usr/bin/env")
+                .args(self.get_setup_args())
+                .uid(uid)
+                .gid(gid),
+            Some("swtpm_setup".to_string()),
+        )?;
+        match child.wait() {
+            Ok(status) if status.success() => Ok(()),
+            _ => Err(OsalError::ExecError(Some(format!(
+                "swtpm_setup for {}",
+                self.disk
+            )))),
+        }
+    }
+
+    /// qemu fails when it connects before swtpm listens on its socket
+    fn wait_for_socket(&self, child: &mut Child) -> Result<(), OsalError> {
+        let deadline = Instant::now() + SOCKET_TIMEOUT;
+        while !Osal::path_exists(self.socket()) {
+            // swtpm that exits never creates the socket, no need to wait for the timeout
+            if let Ok(Some(status)) = child.try_wait() {
+                return Err(OsalError::ExecError(Some(format!(
+                    "swtpm exited with {} before it listened on {}",
+                    status,
+                    self.socket()
+                ))));
+            }
+            if Instant::now() > deadline {
+                return Err(OsalError::Timeout(Some(self.socket())));
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+        Ok(())
+    }
+
+    fn get_version_args(&self) -> Vec<String> {
+        match self.version {
+            SwTpmVersion::Tpm12 => vec![],
+            SwTpmVersion::Tpm20 => vec!["--tpm2".to_string()],
+        }
+    }
+
+    fn get_setup_args(&self) -> Vec<String> {
+        let mut result = vec!["swtpm_setup".to_string()];
+        result.extend(self.get_version_args());
+        result.extend(vec![
+            "--tpmstate".to_string(),
+            format!("file://{}", self.disk),
+            "--createek".to_string(),
+            "--create-ek-cert".to_string(),
+            "--create-platform-cert".to_string(),
+            "--lock-nvram".to_string(),
+            "--not-overwrite".to_string(),
+        ]);
+        result
+    }
+
     fn get_args(&self) -> Vec<String> {
-        vec![
+        let mut result = vec![
             "swtpm".to_string(),
             "socket".to_string(),
             "--tpmstate".to_string(),
             format!("backend-uri=file://{}", self.disk),
             "--ctrl".to_string(),
-            format!("type=unixio,path={},mode=0600", self.socket),
-            "--tpm2".to_string(),
-        ]
+            format!("type=unixio,path={},mode=0600", self.socket()),
+            // exit with qemu, instead of lingering after it is gone
+            "--terminate".to_string(),
+        ];
+        result.extend(self.get_version_args());
+        result
     }
 }
 
 impl QemuDevice for SwTpm {
-    fn get_qemu_args(&self, index: usize) -> Vec<String> {
+    fn get_qemu_args(&self, index: usize) -> Vec<QemuArgument> {
         vec![
-            format!(
-                "-chardev socket,id=chrtpm{},path={}",
-                index,
-                self.socket.clone()
+            QemuArgument::new(
+                "-chardev",
+                format!(
+                    "socket,id=chrtpm{},path={}",
+                    index,
+                    QemuArgument::escape(&self.socket())
+                ),
+            ),
+            QemuArgument::new(
+                "-tpmdev",
+                format!("emulator,id=tpm{},chardev=chrtpm{}", index, index),
+            ),
+            QemuArgument::new(
+                "-device",
+                format!("{},tpmdev=tpm{}", self.model.device(), index),
             ),
-            format!("-tpmdev emulator,id=tpm{},chardev=chrtpm{}", index, index),
-            format!("-device tpm-tis,tpmdev=tpm{}", index),
         ]
     }
 
-    fn pre_start(&self, config: &Config) {
-        debug!("SwTpm::start()");
+    fn get_helper_commands(&self, _config: &Config) -> Vec<Vec<String>> {
+        vec![self.get_args()]
+    }
+
+    /// a failure of swtpm_setup or swtpm keeps the vm from starting, it would start without
+    /// its tpm, or qemu would fail to connect to it
+    fn pre_start(&self, config: &Config) -> Result<(), OsalError> {
+        debug!("SwTpm::pre_start()");
 
         //let (uid, gid) = get_swtpm_uid_and_gid(config);
         let (uid, gid) = config.get_escalated_uid_and_gid();
-        let name = config.general().name().clone();
-        match self.spawn(uid, gid, name) {
-            Ok(_child) => debug!("SwTpm::pre_start() succeeded"),
-            Err(_error) => warn!("SwTpm::pre_start() failed"),
+        if Osal::is_blank(self.disk.clone()) {
+            self.setup(uid, gid)?;
+        }
+
+        if self.socket.is_none() {
+            if let Err(error) = Osal::create_directory(self.runtime_dir.clone()) {
+                warn!("SwTpm::pre_start() no runtime directory: {:?}", error);
+            }
         }
+        // a socket that is left behind would be mistaken for the new one
+        let _ = Osal::delete_file(self.socket());
+        let name = config.general().name().clone();
+        let mut child = self.spawn(uid, gid, name)?;
+        let _ = self.pid.set(child.id());
+        self.wait_for_socket(&mut child)?;
+        debug!("SwTpm::pre_start() succeeded");
+        Ok(())
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use serial_test::serial;
 
     #[test]
     fn unit_test() {
-        let tpm = SwTpm {
-            disk: "the_disk".to_string(),
-            socket: "the_socket".to_string(),
-        };
+        let tpm = SwTpm::new("the_disk".to_string(), "the_socket".to_string());
         assert_eq!(
             tpm.get_args(),
             vec![
@@ -98,16 +246,181 @@ mod tests {
                 "backend-uri=file://the_disk".to_string(),
                 "--ctrl".to_string(),
                 "type=unixio,path=the_socket,mode=0600".to_string(),
+                "--terminate".to_string(),
                 "--tpm2".to_string()
             ]
         );
         assert_eq!(
             tpm.get_qemu_args(0),
             vec![
-                "-chardev socket,id=chrtpm0,path=the_socket".to_string(),
-                "-tpmdev emulator,id=tpm0,chardev=chrtpm0".to_string(),
-                "-device tpm-tis,tpmdev=tpm0".to_string()
+                QemuArgument::new("-chardev", "socket,id=chrtpm0,path=the_socket"),
+                QemuArgument::new("-tpmdev", "emulator,id=tpm0,chardev=chrtpm0"),
+                QemuArgument::new("-device", "tpm-tis,tpmdev=tpm0")
             ]
         );
     }
+
+    #[test]
+    fn test_version() {
+        let tpm: SwTpm =
+            serde_yaml::from_str(r#"{ disk: the_disk, socket: the_socket, version: "1.2" }"#)
+                .unwrap();
+        assert!(!tpm.get_args().contains(&"--tpm2".to_string()));
+        assert_eq!(
+            tpm.get_setup_args(),
+            vec![
+                "swtpm_setup",
+                "--tpmstate",
+                "file://the_disk",
+                "--createek",
+                "--create-ek-cert",
+                "--create-platform-cert",
+                "--lock-nvram",
+                "--not-overwrite",
+            ]
+        );
+
+        let tpm: SwTpm = serde_yaml::from_str("{ disk: the_disk, socket: the_socket }").unwrap();
+        assert_eq!(tpm.version, SwTpmVersion::Tpm20);
+        assert_eq!(tpm.get_setup_args()[1], "--tpm2");
+    }
+
+    #[test]
+    fn test_runtime_dir() {
+        let mut tpm: SwTpm =
+            serde_yaml::from_str("{ disk: /dev/vm1/vm-108-tpmstate, model: crb }").unwrap();
+        tpm.use_runtime_dir("/var/ezkvm/run/wakiza");
+        assert_eq!(
+            tpm.get_qemu_args(0),
+            vec![
+                QemuArgument::new(
+                    "-chardev",
+                    "socket,id=chrtpm0,path=/var/ezkvm/run/wakiza/swtpm.socket"
+                ),
+                QemuArgument::new("-tpmdev", "emulator,id=tpm0,chardev=chrtpm0"),
+                QemuArgument::new("-device", "tpm-crb,tpmdev=tpm0")
+            ]
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn test_pre_start() {
+        let get_euid_and_egid = Osal::get_euid_and_egid_context();
+        get_euid_and_egid.expect().returning(|| (0, 0));
+        let get_uid_and_gid = Osal::get_uid_and_gid_context();
+        get_uid_and_gid.expect().returning(|| (1000, 1000));
+        let is_blank = Osal::is_blank_context();
+        is_blank
+            .expect()
+            .withf(|path: &String| path == "/dev/vm1/vm-108-tpmstate")
+            .returning(|_path: String| true);
+        let delete_file = Osal::delete_file_context();
+        delete_file
+            .expect()
+            .withf(|path: &String| path == "/var/ezkvm/wakiza-tpm.socket")
+            .times(1)
+            .returning(|_path: String| Ok(()));
+        let path_exists = Osal::path_exists_context();
+        path_exists.expect().returning(|_path: String| true);
+
+        // swtpm_setup runs first, and has to finish before swtpm is started
+        let execute_command = Osal::execute_command_context();
+        let mut sequence = mockall::Sequence::new();
+        for program in ["swtpm_setup", "swtpm"] {
+            execute_command
+                .expect::<String>()
+                .withf(move |_command: &Command, log_path: &Option<String>| {
+                    log_path.as_deref() == Some(program)
+                })
+                .times(1)
+                .in_sequence(&mut sequence)
+                .returning(|_command: &mut Command, _log_path: Option<String>| {
+                    Command::new("true")
+                        .spawn()
+                        .map_err(|_| OsalError::ExecError(None))
+                });
+        }
+
+        let tpm = SwTpm::new(
+            "/dev/vm1/vm-108-tpmstate".to_string(),
+            "/var/ezkvm/wakiza-tpm.socket".to_string(),
+        );
+        assert_eq!(tpm.get_pid(), None);
+        assert_eq!(tpm.pre_start(&Config::default()), Ok(()));
+        assert!(tpm.get_pid().is_some());
+    }
+
+    #[test]
+    #[serial]
+    fn test_pre_start_failures() {
+        let get_euid_and_egid = Osal::get_euid_and_egid_context();
+        get_euid_and_egid.expect().returning(|| (0, 0));
+        let get_uid_and_gid = Osal::get_uid_and_gid_context();
+        get_uid_and_gid.expect().returning(|| (1000, 1000));
+        let is_blank = Osal::is_blank_context();
+        is_blank.expect().returning(|_path: String| true);
+        let delete_file = Osal::delete_file_context();
+        delete_file.expect().returning(|_path: String| Ok(()));
+        let tpm = SwTpm::new(
+            "/dev/vm1/vm-108-tpmstate".to_string(),
+            "/var/ezkvm/wakiza-tpm.socket".to_string(),
+        );
+
+        // a failed swtpm_setup keeps swtpm from being started
+        let execute_command = Osal::execute_command_context();
+        execute_command
+            .expect::<String>()
+            .withf(|_command: &Command, log_path: &Option<String>| {
+                log_path.as_deref() == Some("swtpm_setup")
+            })
+            .times(1)
+            .returning(|_command: &mut Command, _log_path: Option<String>| {
+                Command::new("false")
+                    .spawn()
+                    .map_err(|_| OsalError::ExecError(None))
+            });
+        assert_eq!(
+            tpm.pre_start(&Config::default()),
+            Err(OsalError::ExecError(Some(
+                "swtpm_setup for /dev/vm1/vm-108-tpmstate".to_string()
+            )))
+        );
+        assert_eq!(tpm.get_pid(), None);
+
+        // as does swtpm that can not be spawned
+        is_blank.checkpoint();
+        is_blank.expect().returning(|_path: String| false);
+        execute_command.checkpoint();
+        execute_command.expect::<String>().times(1).returning(
+            |_command: &mut Command, _log_path: Option<String>| {
+                Err(OsalError::ExecError(Some("swtpm".to_string())))
+            },
+        );
+        assert_eq!(
+            tpm.pre_start(&Config::default()),
+            Err(OsalError::ExecError(Some("swtpm".to_string())))
+        );
+        assert_eq!(tpm.get_pid(), None);
+
+        // and swtpm that exits before its socket shows up
+        let path_exists = Osal::path_exists_context();
+        path_exists.expect().returning(|_path: String| false);
+        execute_command.checkpoint();
+        execute_command.expect::<String>().times(1).returning(
+            |_command: &mut Command, _log_path: Option<String>| {
+                Command::new("false")
+                    .spawn()
+                    .map_err(|_| OsalError::ExecError(None))
+            },
+        );
+        assert_eq!(
+            tpm.pre_start(&Config::default()),
+            Err(OsalError::ExecError(Some(
+                "swtpm exited with exit status: 1 before it listened on \
+                 /var/ezkvm/wakiza-tpm.socket"
+                    .to_string()
+            )))
+        );
+    }
 }
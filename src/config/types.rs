@@ -1,7 +1,11 @@
 mod pci;
+mod pci_address;
+mod qemu_argument;
 mod qemu_device;
 mod usb;
 
 pub use pci::Pci;
+pub use pci_address::{PciAddress, PciSlot};
+pub use qemu_argument::QemuArgument;
 pub use qemu_device::QemuDevice;
 pub use usb::Usb;
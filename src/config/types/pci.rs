@@ -1,17 +1,34 @@
 use crate::config::default_when_missing;
-use crate::config::QemuDevice;
+use crate::config::types::{PciAddress, PciSlot, QemuArgument};
+use crate::config::{Config, Finding, QemuDevice};
+#[mockall_double::double]
+use crate::osal::Osal;
 use serde::Deserialize;
 
+const PCI_BUS: &str = "ich9-pcie-port-1";
+
 #[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Pci {
     vm_id: String,
     host_id: String,
     #[serde(default, deserialize_with = "default_when_missing")]
     multi_function: Option<bool>,
+    #[serde(skip)]
+    pci_address: Option<PciAddress>,
 }
 
 impl QemuDevice for Pci {
-    fn get_qemu_args(&self, _index: usize) -> Vec<String> {
+    fn get_pci_slots(&mut self) -> Vec<PciSlot<'_>> {
+        // the vm_id pins the device to a slot.function on the first pcie port
+        self.pci_address = format!("{}:{}", PCI_BUS, self.vm_id).parse().ok();
+        match self.pci_address {
+            Some(_) => vec![PciSlot::new(PCI_BUS, &mut self.pci_address)],
+            None => vec![],
+        }
+    }
+
+    fn get_qemu_args(&self, _index: usize) -> Vec<QemuArgument> {
         let multi_function = match self.multi_function {
             None => "".to_string(),
             Some(multi_function) => match multi_function {
@@ -19,9 +36,40 @@ impl QemuDevice for Pci {
                 false => String::from(",multifunction=off"),
             },
         };
-        vec![format!(
-            "-device vfio-pci,host={},id=hostpci{},bus=ich9-pcie-port-1,addr=0x{}{}",
-            self.host_id, self.vm_id, self.vm_id, multi_function
+        // the chipset may have placed the port on a bus of another name
+        let bus = match &self.pci_address {
+            Some(pci_address) => pci_address.bus(),
+            None => PCI_BUS,
+        };
+        vec![QemuArgument::new(
+            "-device",
+            format!(
+                "vfio-pci,host={},id=hostpci{},bus={},addr=0x{}{}",
+                self.host_id, self.vm_id, bus, self.vm_id, multi_function
+            ),
         )]
     }
+
+    fn validate(&self, config: &Config) -> Vec<Finding> {
+        if !config.system().chipset().has_pci_bus() {
+            return vec![Finding::error(
+                "",
+                format!(
+                    "the chipset has no pci bus to pass {} through",
+                    self.host_id
+                ),
+            )];
+        }
+        match Osal::get_pci_driver(self.host_id.clone()).as_deref() {
+            Some("vfio-pci") => vec![],
+            Some(driver) => vec![Finding::error(
+                "host_id",
+                format!("{} is bound to {}, not to vfio-pci", self.host_id, driver),
+            )],
+            None => vec![Finding::error(
+                "host_id",
+                format!("{} is not bound to vfio-pci", self.host_id),
+            )],
+        }
+    }
 }
@@ -0,0 +1,156 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// a location on a pci bus, written as `[bus:]slot[.function]` with a hexadecimal slot,
+/// for example `pci.1:0x12` or `ich9-pcie-port-2:0.1`; the bus defaults to `pcie.0`
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct PciAddress {
+    bus: String,
+    slot: u8,
+    function: u8,
+}
+
+impl PciAddress {
+    pub fn new(bus: &str, slot: u8, function: u8) -> Self {
+        Self {
+            bus: bus.to_string(),
+            slot,
+            function,
+        }
+    }
+
+    pub fn bus(&self) -> &str {
+        &self.bus
+    }
+
+    pub fn slot(&self) -> u8 {
+        self.slot
+    }
+
+    pub fn function(&self) -> u8 {
+        self.function
+    }
+
+    /// the same slot with another function, for devices that span several functions
+    pub fn with_function(&self, function: u8) -> Self {
+        Self {
+            bus: self.bus.clone(),
+            slot: self.slot,
+            function,
+        }
+    }
+
+    /// the bus and addr options for a qemu -device argument
+    pub fn get_device_options(&self) -> String {
+        match self.function {
+            0 => format!("bus={},addr=0x{:x}", self.bus, self.slot),
+            function => format!("bus={},addr=0x{:x}.{}", self.bus, self.slot, function),
+        }
+    }
+}
+
+impl FromStr for PciAddress {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let error = || format!("invalid pci address '{}'", value);
+
+        let (bus, location) = match value.rsplit_once(':') {
+            Some((bus, location)) if !bus.is_empty() => (bus, location),
+            Some(_) => return Err(error()),
+            None => ("pcie.0", value),
+        };
+        let (slot, function) = match location.split_once('.') {
+            Some((slot, function)) => (slot, function),
+            None => (location, "0"),
+        };
+        let slot = u8::from_str_radix(slot.trim_start_matches("0x"), 16).map_err(|_| error())?;
+        let function = function.parse::<u8>().map_err(|_| error())?;
+        if slot > 0x1f || function > 7 {
+            return Err(error());
+        }
+
+        Ok(Self::new(bus, slot, function))
+    }
+}
+
+impl TryFrom<String> for PciAddress {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<PciAddress> for String {
+    fn from(address: PciAddress) -> Self {
+        address.to_string()
+    }
+}
+
+impl fmt::Display for PciAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:0x{:x}.{}", self.bus, self.slot, self.function)
+    }
+}
+
+/// a pci address a device needs, together with the bus it would prefer to be placed on
+#[derive(Debug)]
+pub struct PciSlot<'a> {
+    preferred_bus: &'static str,
+    address: &'a mut Option<PciAddress>,
+}
+
+impl<'a> PciSlot<'a> {
+    pub fn new(preferred_bus: &'static str, address: &'a mut Option<PciAddress>) -> Self {
+        Self {
+            preferred_bus,
+            address,
+        }
+    }
+
+    pub fn preferred_bus(&self) -> &'static str {
+        self.preferred_bus
+    }
+
+    pub fn address(&self) -> &Option<PciAddress> {
+        self.address
+    }
+
+    pub fn assign(&mut self, address: PciAddress) {
+        *self.address = Some(address);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!("0x2".parse(), Ok(PciAddress::new("pcie.0", 2, 0)));
+        assert_eq!("pci.1:12".parse(), Ok(PciAddress::new("pci.1", 0x12, 0)));
+        assert_eq!(
+            "ich9-pcie-port-2:0x0.1".parse(),
+            Ok(PciAddress::new("ich9-pcie-port-2", 0, 1))
+        );
+
+        assert!("pci.1:".parse::<PciAddress>().is_err());
+        assert!(":0x2".parse::<PciAddress>().is_err());
+        assert!("pci.1:0x20".parse::<PciAddress>().is_err());
+        assert!("pci.1:0x2.8".parse::<PciAddress>().is_err());
+    }
+
+    #[test]
+    fn test_device_options() {
+        let address: PciAddress = serde_yaml::from_str("pci.2:0xc").unwrap();
+        assert_eq!(address.get_device_options(), "bus=pci.2,addr=0xc");
+        assert_eq!(
+            address.with_function(1).get_device_options(),
+            "bus=pci.2,addr=0xc.1"
+        );
+        assert_eq!(address.to_string(), "pci.2:0xc.0");
+    }
+}
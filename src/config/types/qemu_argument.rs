@@ -0,0 +1,101 @@
+use std::fmt;
+
+/// a single qemu command line argument: a flag like `-device`, optionally followed by
+/// its value; flag and value are kept apart, so they reach qemu as separate argv entries
+/// no matter what characters (spaces included) the value contains
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct QemuArgument {
+    flag: String,
+    value: Option<String>,
+}
+
+impl QemuArgument {
+    pub fn new(flag: &str, value: impl Into<String>) -> Self {
+        Self {
+            flag: flag.to_string(),
+            value: Some(value.into()),
+        }
+    }
+
+    /// an argument without a value, like `-nodefaults`
+    pub fn flag(flag: &str) -> Self {
+        Self {
+            flag: flag.to_string(),
+            value: None,
+        }
+    }
+
+    pub fn get_flag(&self) -> &str {
+        &self.flag
+    }
+
+    pub fn get_value(&self) -> Option<&str> {
+        self.value.as_deref()
+    }
+
+    /// escapes a value that is embedded in a qemu option list, where ',' separates options
+    pub fn escape(value: &str) -> String {
+        value.replace(',', ",,")
+    }
+
+    /// the argv entries for this argument
+    pub fn to_args(&self) -> Vec<String> {
+        let mut args = vec![self.flag.clone()];
+        args.extend(self.value.clone());
+        args
+    }
+
+    /// the argv entries for a list of arguments
+    pub fn flatten(arguments: &[QemuArgument]) -> Vec<String> {
+        arguments.iter().flat_map(QemuArgument::to_args).collect()
+    }
+}
+
+impl fmt::Display for QemuArgument {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.value {
+            None => write!(f, "{}", self.flag),
+            Some(value) => write!(f, "{} {}", self.flag, value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_args() {
+        assert_eq!(
+            QemuArgument::flag("-nodefaults").to_args(),
+            vec!["-nodefaults"]
+        );
+        assert_eq!(
+            QemuArgument::new("-name", "my vm").to_args(),
+            vec!["-name", "my vm"]
+        );
+        assert_eq!(
+            QemuArgument::flatten(&[
+                QemuArgument::new("-m", "1024"),
+                QemuArgument::flag("-no-hpet"),
+            ]),
+            vec!["-m", "1024", "-no-hpet"]
+        );
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(
+            QemuArgument::escape("/vms/disk one.img"),
+            "/vms/disk one.img"
+        );
+        assert_eq!(
+            QemuArgument::escape("/vms/a,b,,c.img"),
+            "/vms/a,,b,,,,c.img"
+        );
+        assert_eq!(
+            QemuArgument::new("-device", "virtio-mouse").to_string(),
+            "-device virtio-mouse"
+        );
+    }
+}
@@ -1,9 +1,29 @@
-use crate::config::Config;
+use crate::config::system::Chipset;
+use crate::config::types::{PciSlot, QemuArgument};
+use crate::config::{Config, Finding};
+use crate::osal::OsalError;
 use std::fmt::Debug;
 
 pub trait QemuDevice: Debug {
-    fn get_qemu_args(&self, index: usize) -> Vec<String>;
-    fn pre_start(&self, _config: &Config) {}
+    fn get_qemu_args(&self, index: usize) -> Vec<QemuArgument>;
+    /// lets a device adapt to the chipset of the vm, before its pci slots are assigned
+    fn use_chipset(&mut self, _chipset: &dyn Chipset) {}
+    /// the pci addresses this device occupies, unassigned ones are filled in by the PciAllocator
+    fn get_pci_slots(&mut self) -> Vec<PciSlot<'_>> {
+        vec![]
+    }
+    /// the command lines of the helper processes this device spawns, program first
+    fn get_helper_commands(&self, _config: &Config) -> Vec<Vec<String>> {
+        vec![]
+    }
+    /// problems that keep qemu from starting, or from working as intended
+    fn validate(&self, _config: &Config) -> Vec<Finding> {
+        vec![]
+    }
+    /// prepares the host for the vm; an error keeps qemu from being started
+    fn pre_start(&self, _config: &Config) -> Result<(), OsalError> {
+        Ok(())
+    }
     fn post_start(&self, _config: &Config) {}
     fn pre_stop(&self, _config: &Config) {}
     fn post_stop(&self, _config: &Config) {}
@@ -1,7 +1,8 @@
-use crate::config::QemuDevice;
+use crate::config::{Config, Finding, QemuArgument, QemuDevice};
 use serde::Deserialize;
 
 #[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Usb {
     vm_port: String,
     host_port: String,
@@ -9,10 +10,23 @@ pub struct Usb {
 }
 
 impl QemuDevice for Usb {
-    fn get_qemu_args(&self, index: usize) -> Vec<String> {
-        vec![format!(
-            "-device usb-host,bus=xhci.0,port={},hostbus={},hostport={},id=usb{}",
-            self.vm_port, self.host_bus, self.host_port, index
+    fn get_qemu_args(&self, index: usize) -> Vec<QemuArgument> {
+        vec![QemuArgument::new(
+            "-device",
+            format!(
+                "usb-host,bus=xhci.0,port={},hostbus={},hostport={},id=usb{}",
+                self.vm_port, self.host_bus, self.host_port, index
+            ),
         )]
     }
+
+    fn validate(&self, config: &Config) -> Vec<Finding> {
+        match config.system().chipset().has_pci_bus() {
+            true => vec![],
+            false => vec![Finding::error(
+                "",
+                "the chipset has no pci bus for the xhci controller of usb-host",
+            )],
+        }
+    }
 }
@@ -0,0 +1,164 @@
+use colored::Colorize;
+use std::fmt;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Severity {
+    /// qemu will not start, or not work as intended
+    Error,
+    /// qemu will start, but probably not the way it was meant to
+    Warning,
+}
+
+/// a single problem found by validating a config
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    severity: Severity,
+    path: String,
+    message: String,
+}
+
+impl Finding {
+    pub fn error(path: &str, message: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Error,
+            path: path.to_string(),
+            message: message.into(),
+        }
+    }
+
+    pub fn warning(path: &str, message: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Warning,
+            path: path.to_string(),
+            message: message.into(),
+        }
+    }
+
+    /// prefixes the key path with the key of the parent item, like `system` for `bios.file`
+    pub fn within(self, parent: &str) -> Self {
+        let path = match self.path.as_str() {
+            "" => parent.to_string(),
+            path if path.starts_with('[') => format!("{}{}", parent, path),
+            path => format!("{}.{}", parent, path),
+        };
+        Self { path, ..self }
+    }
+
+    pub fn get_severity(&self) -> Severity {
+        self.severity
+    }
+
+    /// the dotted key path of the offending item, like `storage[1].file`
+    pub fn get_path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn get_message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error".red().bold(),
+            Severity::Warning => "warning".yellow().bold(),
+        };
+        write!(f, "{}: {}: {}", severity, self.path.bold(), self.message)
+    }
+}
+
+/// prefixes the key paths of all findings with the key of their parent item
+pub fn within(parent: &str, findings: Vec<Finding>) -> Vec<Finding> {
+    findings
+        .into_iter()
+        .map(|finding| finding.within(parent))
+        .collect()
+}
+
+/// all problems found in a config, so they can be reported at once
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Validation {
+    findings: Vec<Finding>,
+}
+
+impl Validation {
+    pub fn new(findings: Vec<Finding>) -> Self {
+        Self { findings }
+    }
+
+    pub fn get_findings(&self) -> &Vec<Finding> {
+        &self.findings
+    }
+
+    pub fn errors(&self) -> Vec<&Finding> {
+        self.with_severity(Severity::Error)
+    }
+
+    pub fn warnings(&self) -> Vec<&Finding> {
+        self.with_severity(Severity::Warning)
+    }
+
+    pub fn has_errors(&self) -> bool {
+        !self.errors().is_empty()
+    }
+
+    fn with_severity(&self, severity: Severity) -> Vec<&Finding> {
+        self.findings
+            .iter()
+            .filter(|finding| finding.severity == severity)
+            .collect()
+    }
+}
+
+impl fmt::Display for Validation {
+    /// errors first, then warnings
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for finding in self.errors().into_iter().chain(self.warnings()) {
+            writeln!(f, "{}", finding)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serial_test::serial;
+
+    #[test]
+    fn test_within() {
+        let finding = Finding::error("file", "does not exist").within("bios");
+        assert_eq!(finding.get_path(), "bios.file");
+
+        let finding = finding.within("system");
+        assert_eq!(finding.get_path(), "system.bios.file");
+
+        let finding = Finding::warning("[1]", "is unused").within("storage");
+        assert_eq!(finding.get_path(), "storage[1]");
+
+        let finding = Finding::warning("", "is unused").within("spice");
+        assert_eq!(finding.get_path(), "spice");
+    }
+
+    #[test]
+    #[serial]
+    fn test_display() {
+        let validation = Validation::new(vec![
+            Finding::warning("spice", "nothing connects to it"),
+            Finding::error("storage[0].file", "/dev/vm1/boot does not exist"),
+        ]);
+        assert!(validation.has_errors());
+        assert_eq!(validation.errors().len(), 1);
+        assert_eq!(validation.warnings().len(), 1);
+
+        colored::control::set_override(false);
+        let text = validation.to_string();
+        colored::control::unset_override();
+
+        assert_eq!(
+            text,
+            "error: storage[0].file: /dev/vm1/boot does not exist\nwarning: spice: nothing connects to it\n"
+        );
+    }
+}
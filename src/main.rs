@@ -1,66 +1,304 @@
 extern crate colored;
 mod args;
+mod cmdline;
 mod config;
 mod osal;
+mod qmp;
 mod resource;
+mod status;
 
 use crate::args::{EzkvmArguments, EzkvmCommand};
+use crate::cmdline::{format_commands, CmdlineFormat};
 use std::env;
 use std::fs::File;
 use std::io::Read;
-use std::process::Command;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitCode};
+use std::thread;
+use std::time::{Duration, Instant};
 
 use crate::colored::Colorize;
-use crate::config::{Config, QemuDevice};
+use crate::config::{installed_qemu_version, Config, QemuArgument, QemuDevice, Validation};
 use crate::osal::{Osal, OsalError};
+use crate::qmp::{check_file_migration, QmpClient};
 use crate::resource::data_manager::DataManager;
 use crate::resource::lock::Lock;
 use crate::resource::resource_pool::ResourcePool;
+use crate::status::{OutputFormat, VmStatus};
 use chrono::Local;
 use env_logger::Builder;
-use log::{debug, info, Level, LevelFilter};
+use log::{debug, error, info, warn, Level, LevelFilter};
 use std::io::Write;
 use std::os::unix::prelude::CommandExt;
 
-fn main() {
-    let args = EzkvmArguments::new(env::args().collect());
+const QUIT_TIMEOUT: Duration = Duration::from_secs(10);
+const HIBERNATE_TIMEOUT: Duration = Duration::from_secs(600);
+const RESUME_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// exit codes, see the README
+const EXIT_FAILURE: u8 = 1;
+const EXIT_USAGE: u8 = 2;
+const EXIT_CONFIG: u8 = 3;
+const EXIT_STATE: u8 = 4;
+
+fn main() -> ExitCode {
+    let args = match EzkvmArguments::new(env::args().collect()) {
+        Ok(args) => args,
+        Err(error) => {
+            eprintln!("ezkvm: {}\n", error);
+            eprint!("{}", EzkvmArguments::usage("ezkvm"));
+            return ExitCode::from(EXIT_USAGE);
+        }
+    };
     init_logger(args.log_level);
     debug!("main( {:?} )", args.command);
 
     let _resource_manager = DataManager::instance();
 
-    match args.command {
-        EzkvmCommand::Start { name } => handle_start_command(name),
-        EzkvmCommand::Stop { .. } => todo!(),
-        EzkvmCommand::Hibernate { .. } => todo!(),
-        _ => args.print_usage(),
+    let config = args.config.clone();
+    let result = match args.command {
+        EzkvmCommand::Start { name } => handle_start_command(name, config),
+        EzkvmCommand::Stop { name, timeout } => handle_stop_command(name, config, timeout),
+        EzkvmCommand::Hibernate { name } => handle_hibernate_command(name, config),
+        EzkvmCommand::Status { name, format } => handle_status_command(name, config, format),
+        EzkvmCommand::List { format } => handle_list_command(format),
+        EzkvmCommand::Validate { name } => handle_validate_command(name, config),
+        EzkvmCommand::ShowCmdline { name, format } => {
+            handle_show_cmdline_command(name, config, format)
+        }
+        EzkvmCommand::Console { name } => handle_console_command(name, config),
+        EzkvmCommand::Help => {
+            args.print_usage();
+            Ok(())
+        }
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            // also reported with --quiet, the logger is off then
+            match &error {
+                OsalError::Config(error) => eprint!("{}", error),
+                _ => eprintln!("ezkvm: {}", describe(&error)),
+            }
+            ExitCode::from(exit_code(&error))
+        }
+    }
+}
+
+fn exit_code(error: &OsalError) -> u8 {
+    match error {
+        OsalError::OpenError(_)
+        | OsalError::ReadError(_)
+        | OsalError::ParseError(_)
+        | OsalError::Config(_)
+        | OsalError::Invalid(_) => EXIT_CONFIG,
+        OsalError::Busy(_) => EXIT_STATE,
+        _ => EXIT_FAILURE,
     }
 }
 
-fn handle_start_command(name: String) {
-    let config = load_vm(format!("/etc/ezkvm/{}.yaml", name).as_str());
+fn describe(error: &OsalError) -> String {
+    match error {
+        OsalError::OpenError(Some(detail)) => format!("unable to open {}", detail),
+        OsalError::ReadError(Some(detail)) => format!("unable to read {}", detail),
+        OsalError::ParseError(Some(detail)) => format!("unable to parse {}", detail),
+        OsalError::Busy(Some(detail)) => detail.to_string(),
+        OsalError::Invalid(Some(detail)) => detail.to_string(),
+        other => format!("{:?}", other),
+    }
+}
 
-    config.pre_start(&config);
+/// the config given with --config, or else the one found in the search path
+fn load_vm(name: &str, config: Option<PathBuf>) -> Result<Config, OsalError> {
+    debug!("load_vm({})", name);
+
+    let mut config = match config {
+        Some(file) => Config::read_file(file)?,
+        None => Config::read(name)?,
+    };
+    config.find_firmware();
+    config.use_runtime_dir();
+    Ok(config)
+}
 
-    if let Ok(_lock) = start_vm(&name, &config) {
-        // use lock
-    } else {
-        debug!("Unable to start the vm");
+/// fails with Busy when the vm has no live qemu process
+fn require_running(name: &str) -> Result<Lock, OsalError> {
+    match Lock::read(name) {
+        Ok(lock) if lock.is_alive() => Ok(lock),
+        _ => Err(OsalError::Busy(Some(format!("{} is not running", name)))),
     }
+}
+
+fn handle_start_command(name: String, config: Option<PathBuf>) -> Result<(), OsalError> {
+    let mut config = load_vm(&name, config)?;
+
+    // taken before anything is started, so two starts of a vm can not both get past this
+    let mut lock = Lock::acquire(&name)?;
+    let result = run_vm(&name, &mut config, &mut lock);
 
-    config.post_start(&config);
+    // however far run_vm() got, swtpm must not outlive qemu, nor the lock the vm
+    if let Err(error) = lock.terminate_swtpm() {
+        warn!("Unable to stop swtpm: {:?}", error);
+    }
+    if let Err(error) = lock.delete() {
+        debug!("Lock was already removed: {:?}", error);
+    }
+    result
 }
 
-fn load_vm(file: &str) -> Config {
-    debug!("load_vm({})", file);
+fn run_vm(name: &str, config: &mut Config, lock: &mut Lock) -> Result<(), OsalError> {
+    // refuse to start what qemu is known to refuse, or to run in a broken state
+    let validation = config.validation(name);
+    for finding in validation.warnings() {
+        warn!("{}: {}", finding.get_path(), finding.get_message());
+    }
+    for finding in validation.errors() {
+        error!("{}: {}", finding.get_path(), finding.get_message());
+    }
+    if validation.has_errors() {
+        return Err(invalid(name, &validation));
+    }
 
-    let mut file = File::open(file).expect("Unable to open file");
-    let mut contents = String::new();
+    // a hibernated vm resumes from its saved state instead of booting
+    let resume = Path::new(&config.general().state_file()).exists();
+    if resume {
+        match installed_qemu_version() {
+            Some(version) => check_file_migration(version)?,
+            None => warn!("Unable to tell whether qemu can resume from its state file"),
+        }
+    }
 
-    file.read_to_string(&mut contents)
-        .expect("Unable to read file");
+    // swtpm may be running even when pre_start fails, the lock has to know it to stop it
+    let prepared = config.pre_start(config);
+    lock.set_swtpm_pid(config.system().tpm().get_pid());
+    prepared?;
 
-    serde_yaml::from_str(contents.as_str()).unwrap()
+    let mut child = start_vm(config, lock, resume)?;
+    if resume {
+        finish_resume(config);
+    }
+    config.post_start(config);
+
+    // stay around until qemu exits, so the lock does not outlive the vm
+    if let Err(error) = child.wait() {
+        warn!("Unable to wait for qemu: {:?}", error);
+    }
+    Ok(())
+}
+
+fn handle_stop_command(
+    name: String,
+    config: Option<PathBuf>,
+    timeout: Duration,
+) -> Result<(), OsalError> {
+    let config = load_vm(&name, config)?;
+    require_running(&name)?;
+
+    config.pre_stop(&config);
+    let result = stop_vm(&name, &config, timeout);
+    config.post_stop(&config);
+
+    result
+}
+
+fn handle_hibernate_command(name: String, config: Option<PathBuf>) -> Result<(), OsalError> {
+    let config = load_vm(&name, config)?;
+    require_running(&name)?;
+
+    config.pre_hibernate(&config);
+    let result = hibernate_vm(&config);
+    config.post_hibernate(&config);
+
+    result
+}
+
+fn handle_status_command(
+    name: String,
+    config: Option<PathBuf>,
+    format: OutputFormat,
+) -> Result<(), OsalError> {
+    let path = match config {
+        Some(file) => file,
+        None => Config::find(&name)?,
+    };
+    print!("{}", VmStatus::read(&name, path)?.format(format));
+    Ok(())
+}
+
+fn handle_list_command(format: OutputFormat) -> Result<(), OsalError> {
+    print!("{}", VmStatus::format_list(&VmStatus::list(), format));
+    Ok(())
+}
+
+fn handle_validate_command(name: String, config: Option<PathBuf>) -> Result<(), OsalError> {
+    let mut config = load_vm(&name, config)?;
+
+    // the same order as start, so both report the same problem first
+    let validation = config.validation(&name);
+    print!("{}", validation);
+    if validation.has_errors() {
+        return Err(invalid(&name, &validation));
+    }
+    config.allocate_pci_addresses()?;
+
+    match validation.warnings().len() {
+        0 => println!("{} is valid", name),
+        count => println!("{} is valid, with {} warning(s)", name, count),
+    }
+    Ok(())
+}
+
+fn invalid(name: &str, validation: &Validation) -> OsalError {
+    OsalError::Invalid(Some(format!(
+        "{} has {} error(s), see `ezkvm validate {}`",
+        name,
+        validation.errors().len(),
+        name
+    )))
+}
+
+fn handle_show_cmdline_command(
+    name: String,
+    config: Option<PathBuf>,
+    format: CmdlineFormat,
+) -> Result<(), OsalError> {
+    let mut config = load_vm(&name, config)?;
+
+    // preview the resources instead of claiming them, nothing is started or written
+    config.preview_resources(&name)?;
+    config.allocate_pci_addresses()?;
+
+    let mut commands = vec![config.get_qemu_command()];
+    commands.extend(config.get_helper_commands(&config));
+    print!("{}", format_commands(&commands, format));
+    Ok(())
+}
+
+fn handle_console_command(name: String, config: Option<PathBuf>) -> Result<(), OsalError> {
+    let config = load_vm(&name, config)?;
+    require_running(&name)?;
+
+    let commands = config.display().get_helper_commands(&config);
+    if commands.is_empty() {
+        return Err(OsalError::ExecError(Some(format!(
+            "the display of {} has no console client",
+            name
+        ))));
+    }
+
+    let (uid, gid) = config.get_default_uid_and_gid();
+    for command in commands {
+        info!("{}", command.join(" "));
+        let mut child = Osal::execute_command(
+            Command::new("/usr/bin/env").args(command).uid(uid).gid(gid),
+            None::<String>,
+        )?;
+        child
+            .wait()
+            .map_err(|error| OsalError::ExecError(Some(error.to_string())))?;
+    }
+    Ok(())
 }
 
 #[allow(dead_code)]
@@ -76,28 +314,121 @@ fn load_pool(file: &str) -> ResourcePool {
     serde_yaml::from_str(contents.as_str()).unwrap()
 }
 
-fn start_vm(name: &String, config: &Config) -> Result<Lock, OsalError> {
+fn start_vm(config: &mut Config, lock: &mut Lock, resume: bool) -> Result<Child, OsalError> {
     debug!("start_vm()");
 
     let (uid, gid) = config.get_escalated_uid_and_gid();
 
-    let config_qemu_args = config.get_qemu_args(0);
-    let mut args = "qemu-system-x86_64".to_string();
-    for arg in config_qemu_args {
-        //info!("{}", arg);
-        args = format!("{} {}", args, arg).to_string();
+    // claimed devices are part of the qemu command line, so claim them first
+    for resource in config.allocate_resources()? {
+        lock.add_resource(resource);
     }
-    info!("{}", args);
-    let args: Vec<String> = args.split_whitespace().map(str::to_string).collect();
+    config.allocate_pci_addresses()?;
+    // a host tpm is recorded like a claimed device, so other vms see that it is taken
+    if let Some(resource) = config.system().tpm().get_host_resource() {
+        lock.add_resource(resource);
+    }
+    // other vms only see the claims once they are in the lock, the claims were made against
+    // the locks as they were when ezkvm started, so check them again once they are visible
+    lock.write()?;
+    lock.check_claims()?;
 
-    let resources: Vec<String> = config.allocate_resources()?;
+    // every flag and value is a separate argv entry, so values are passed to qemu verbatim
+    let mut args = config.get_qemu_command();
+    if resume {
+        args.extend(
+            QemuArgument::new(
+                "-incoming",
+                format!("file:{}", config.general().state_file()),
+            )
+            .to_args(),
+        );
+    }
+    info!("{}", args.join(" "));
 
-    match Osal::execute_command(
+    let child = Osal::execute_command(
         Command::new("/usr/bin/env").args(args).uid(uid).gid(gid),
         Some("qemu".to_string()),
-    ) {
-        Ok(child) => Ok(Lock::new(name.clone(), child.id(), resources)),
-        Err(error) => Err(error),
+    )?;
+
+    // the lock still holds while it has the pid of ezkvm, which waits for qemu
+    lock.set_pid(child.id());
+    if let Err(error) = lock.write() {
+        warn!("start_vm(): unable to record the pid of qemu: {:?}", error);
+    }
+    Ok(child)
+}
+
+fn stop_vm(name: &str, config: &Config, timeout: Duration) -> Result<(), OsalError> {
+    debug!("stop_vm()");
+
+    // escalate from a guest shutdown, to terminating qemu, to killing the qemu process
+    match QmpClient::connect(config.general().qmp_socket()) {
+        Ok(mut qmp) => {
+            match qmp.system_powerdown(timeout) {
+                Ok(()) => return Ok(()),
+                Err(error) => warn!("stop_vm(): guest did not power down: {:?}", error),
+            }
+            match qmp.quit(QUIT_TIMEOUT) {
+                Ok(()) => return Ok(()),
+                Err(error) => warn!("stop_vm(): qemu did not quit: {:?}", error),
+            }
+        }
+        Err(error) => warn!("stop_vm(): unable to connect to qmp: {:?}", error),
+    }
+
+    // never kill a pid that has been reused by some other process
+    let lock = Lock::read(name)?;
+    if !lock.is_alive() {
+        return lock.delete();
+    }
+    Osal::kill_process(*lock.pid())?;
+    if let Err(error) = lock.terminate_swtpm() {
+        warn!("stop_vm(): unable to stop swtpm: {:?}", error);
+    }
+    lock.delete()
+}
+
+fn hibernate_vm(config: &Config) -> Result<(), OsalError> {
+    debug!("hibernate_vm()");
+
+    let state_file = config.general().state_file();
+    let mut qmp = QmpClient::connect(config.general().qmp_socket())?;
+    if let Err(error) = qmp.save_state(&state_file, HIBERNATE_TIMEOUT) {
+        // keep the vm running, and make sure a partial state is never restored
+        let _ = qmp.execute("migrate_cancel", None);
+        let _ = qmp.execute("cont", None);
+        let _ = Osal::delete_file(state_file);
+        return Err(error);
+    }
+
+    qmp.quit(QUIT_TIMEOUT)
+}
+
+fn finish_resume(config: &Config) {
+    debug!("finish_resume()");
+
+    // the qmp socket only appears once qemu is up
+    let deadline = Instant::now() + RESUME_TIMEOUT;
+    let mut qmp = loop {
+        match QmpClient::connect(config.general().qmp_socket()) {
+            Ok(qmp) => break qmp,
+            Err(error) if Instant::now() >= deadline => {
+                error!("finish_resume(): unable to connect to qmp: {:?}", error);
+                return;
+            }
+            Err(_) => thread::sleep(Duration::from_millis(250)),
+        }
+    };
+
+    // only drop the saved state once it has been restored, it must never be restored twice
+    match qmp.restore_state(deadline.saturating_duration_since(Instant::now())) {
+        Ok(()) => {
+            if let Err(error) = Osal::delete_file(config.general().state_file()) {
+                warn!("finish_resume(): unable to delete state file: {:?}", error);
+            }
+        }
+        Err(error) => error!("finish_resume(): vm did not resume: {:?}", error),
     }
 }
 
@@ -124,9 +455,7 @@ fn init_logger(log_level: LevelFilter) {
                 Level::Info => {
                     format!("{}", line.bold())
                 }
-                Level::Debug => {
-                    format!("{}", line)
-                }
+                Level::Debug => line.to_string(),
                 Level::Trace => {
                     format!("{}", line.dimmed())
                 }
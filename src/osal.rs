@@ -1,8 +1,13 @@
+use crate::config::ConfigError;
 use log::{debug, error};
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::{sysconf, Pid, SysconfVar};
 use std::fmt::Display;
-use std::fs::File;
+use std::fs::{File, OpenOptions};
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
 use std::process::{Child, Command};
+use std::time::Duration;
 use std::{fs, process};
 use serde::Deserialize;
 
@@ -15,6 +20,11 @@ pub enum OsalError {
     DeleteError(Option<String>),
     ParseError(Option<String>),
     Busy(Option<String>),
+    Timeout(Option<String>),
+    /// a config file that does not parse, see ConfigError for the details
+    Config(Box<ConfigError>),
+    /// a config that parses, but does not pass validation
+    Invalid(Option<String>),
 }
 
 pub struct Osal {}
@@ -39,12 +49,38 @@ impl Osal {
             T: 'static + AsRef<str>
     {
         let mut result = vec![];
+        let mut found = vec![];
 
-        let pattern = pattern.as_ref();
-        if let Ok(matches) = glob::glob(pattern) {
-            for path in matches {
-                if let Ok(path) = path {
-                    result.push(path.to_owned());
+        // search the locations in the order given, so callers can rely on precedence
+        for location in locations {
+            let location = match location.as_ref().strip_prefix("$XDG_CONFIG_HOME") {
+                // an unset or relative XDG_CONFIG_HOME falls back to ~/.config, as per the spec
+                Some(rest) => match std::env::var("XDG_CONFIG_HOME") {
+                    Ok(config_home) if config_home.starts_with('/') => {
+                        format!("{}{}", config_home, rest)
+                    }
+                    _ => format!("~/.config{}", rest),
+                },
+                None => location.as_ref().to_string(),
+            };
+            let location = match location.strip_prefix("~") {
+                Some(rest) => match home::home_dir() {
+                    Some(home) => format!("{}{}", home.display(), rest),
+                    None => continue,
+                },
+                None => location,
+            };
+
+            let pattern = format!("{}/{}", location, pattern.as_ref());
+            if let Ok(matches) = glob::glob(&pattern) {
+                for path in matches.flatten() {
+                    // a file is only returned once, even when two locations are the same
+                    // directory, like the current directory and /etc/ezkvm
+                    let canonical = fs::canonicalize(&path).unwrap_or(path.clone());
+                    if !found.contains(&canonical) {
+                        found.push(canonical);
+                        result.push(path);
+                    }
                 }
             }
         }
@@ -54,26 +90,153 @@ impl Osal {
     pub fn read_file<P: 'static + AsRef<Path>>(path: P) -> Result<String, OsalError> {
         let file = format!("{:?}", path.as_ref());
         let content = fs::read(path).map_err(|_| OsalError::ReadError(Some(file.clone())))?;
-        Ok(String::from_utf8(content).map_err(|_| OsalError::ParseError(Some(file)))?)
+        String::from_utf8(content).map_err(|_| OsalError::ParseError(Some(file)))
     }
     pub fn read_yaml_file<P,T>(path: P) -> Result<T, OsalError>
         where
             P: 'static + AsRef<Path>,
             T: 'static + for<'a> Deserialize<'a>
     {
+        let file = format!("{}", path.as_ref().display());
         let content = Self::read_file(path)?;
-        serde_yaml::from_str(content.as_str()).map_err(|_| OsalError::ParseError(None))
+        serde_yaml::from_str(content.as_str())
+            .map_err(|error| OsalError::ParseError(Some(format!("{}: {}", file, error))))
     }
     pub fn write_file<P: 'static + AsRef<Path>, S: 'static + AsRef<str>>(
         path: P,
         content: S,
     ) -> Result<(), OsalError> {
         let file = format!("{:?}", path.as_ref());
-        Ok(fs::write(path, content.as_ref()).map_err(|_| OsalError::WriteError(Some(file)))?)
+        fs::write(path, content.as_ref()).map_err(|_| OsalError::WriteError(Some(file)))
+    }
+    /// creates a file that does not exist yet; when several try at once, only one succeeds
+    /// and the others get Busy
+    pub fn create_file<P: 'static + AsRef<Path>, S: 'static + AsRef<str>>(
+        path: P,
+        content: S,
+    ) -> Result<(), OsalError> {
+        let file = format!("{:?}", path.as_ref());
+        let mut handle = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(path)
+            .map_err(|error| match error.kind() {
+                ErrorKind::AlreadyExists => OsalError::Busy(Some(file.clone())),
+                _ => OsalError::WriteError(Some(file.clone())),
+            })?;
+        handle
+            .write_all(content.as_ref().as_bytes())
+            .map_err(|_| OsalError::WriteError(Some(file)))
+    }
+    pub fn copy_file(from: String, to: String) -> Result<(), OsalError> {
+        fs::copy(&from, &to)
+            .map(|_| ())
+            .map_err(|_| OsalError::WriteError(Some(to)))
+    }
+    pub fn create_directory<P: 'static + AsRef<Path>>(path: P) -> Result<(), OsalError> {
+        let directory = format!("{:?}", path.as_ref());
+        fs::create_dir_all(path).map_err(|_| OsalError::WriteError(Some(directory)))
     }
     pub fn delete_file<P: 'static + AsRef<Path>>(path: P) -> Result<(), OsalError> {
         let file = format!("{:?}", path.as_ref());
-        Ok(fs::remove_file(path).map_err(|_| OsalError::DeleteError(Some(file)))?)
+        fs::remove_file(path).map_err(|_| OsalError::DeleteError(Some(file)))
+    }
+    pub fn path_exists(path: String) -> bool {
+        Path::new(&path).exists()
+    }
+    /// whether a file or block device is missing, empty, or starts with 4k of zeroes, like
+    /// a freshly created volume
+    pub fn is_blank(path: String) -> bool {
+        let mut head = vec![];
+        match File::open(&path) {
+            Ok(file) => match file.take(4096).read_to_end(&mut head) {
+                Ok(_) => head.iter().all(|byte| *byte == 0),
+                Err(_) => false,
+            },
+            Err(_) => !Path::new(&path).exists(),
+        }
+    }
+    /// the size in bytes of a file, or of a block device
+    pub fn get_file_size(path: String) -> Option<u64> {
+        File::open(path).ok()?.seek(SeekFrom::End(0)).ok()
+    }
+    /// the full path of a program, searched for in $PATH like a shell would
+    pub fn find_executable(name: String) -> Option<PathBuf> {
+        let path = std::env::var_os("PATH")?;
+        std::env::split_paths(&path)
+            .map(|directory| directory.join(&name))
+            .find(|candidate| candidate.is_file())
+    }
+    /// the kernel driver a host pci device (like 0000:03:00.0) is bound to
+    pub fn get_pci_driver(host_id: String) -> Option<String> {
+        let driver = fs::read_link(format!("/sys/bus/pci/devices/{}/driver", host_id)).ok()?;
+        Some(driver.file_name()?.to_string_lossy().to_string())
+    }
+    /// the machine types a qemu binary supports, as listed by `-machine help`
+    pub fn get_machine_types(qemu: String) -> Vec<String> {
+        let output = match Command::new(&qemu).args(["-machine", "help"]).output() {
+            Ok(output) if output.status.success() => output,
+            _ => return vec![],
+        };
+        String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter(|line| !line.ends_with(':'))
+            .filter_map(|line| line.split_whitespace().next())
+            .map(str::to_string)
+            .collect()
+    }
+    /// the major and minor version of a qemu binary, from `--version`
+    pub fn get_qemu_version(qemu: String) -> Option<(u64, u64)> {
+        let output = Command::new(&qemu).arg("--version").output().ok()?;
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let version = stdout
+            .lines()
+            .next()?
+            .strip_prefix("QEMU emulator version ")?
+            .split_whitespace()
+            .next()?;
+        let mut parts = version.split('.').map(|part| part.parse().ok());
+        Some((parts.next()??, parts.next()??))
+    }
+    /// the vendor_id of the host cpu, like GenuineIntel or AuthenticAMD
+    pub fn get_cpu_vendor() -> Option<String> {
+        let cpuinfo = fs::read_to_string("/proc/cpuinfo").ok()?;
+        cpuinfo
+            .lines()
+            .find_map(|line| line.strip_prefix("vendor_id"))
+            .and_then(|rest| rest.split_once(':'))
+            .map(|(_, vendor)| vendor.trim().to_string())
+    }
+    pub fn get_process_name(pid: u32) -> Option<String> {
+        let comm = fs::read_to_string(format!("/proc/{}/comm", pid)).ok()?;
+        Some(comm.trim_end().to_string())
+    }
+    pub fn get_process_uptime(pid: u32) -> Option<Duration> {
+        let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+
+        // the process name may contain spaces, so only split what follows it;
+        // starttime is field 22 of the stat line, which is the 20th field after the name
+        let fields: Vec<&str> = stat.rsplit_once(')')?.1.split_whitespace().collect();
+        let start_ticks: f64 = fields.get(19)?.parse().ok()?;
+        let ticks_per_second = sysconf(SysconfVar::CLK_TCK).ok()?? as f64;
+
+        let uptime = fs::read_to_string("/proc/uptime").ok()?;
+        let uptime: f64 = uptime.split_whitespace().next()?.parse().ok()?;
+
+        Some(Duration::from_secs_f64(
+            (uptime - start_ticks / ticks_per_second).max(0.0),
+        ))
+    }
+    /// asks a process to exit, unlike kill_process it may clean up first
+    pub fn terminate_process(pid: u32) -> Result<(), OsalError> {
+        debug!("Osal::terminate_process({})", pid);
+        kill(Pid::from_raw(pid as i32), Signal::SIGTERM)
+            .map_err(|_| OsalError::ExecError(Some(format!("kill -TERM {}", pid))))
+    }
+    pub fn kill_process(pid: u32) -> Result<(), OsalError> {
+        debug!("Osal::kill_process({})", pid);
+        kill(Pid::from_raw(pid as i32), Signal::SIGKILL)
+            .map_err(|_| OsalError::ExecError(Some(format!("kill {}", pid))))
     }
     pub fn execute_command<P: 'static + Display + AsRef<Path>>(
         command: &mut Command,
@@ -114,3 +277,30 @@ impl Osal {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_files_returns_a_file_once() {
+        let qemu = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/fixtures/firmware/usr/share/qemu"
+        );
+        let found = Osal::find_files(
+            "50-*.json",
+            vec![
+                format!("{}/firmware", qemu),
+                format!("{}/../qemu/firmware", qemu),
+            ],
+        );
+        assert_eq!(
+            found,
+            vec![PathBuf::from(format!(
+                "{}/firmware/50-edk2-x86_64-secure.json",
+                qemu
+            ))]
+        );
+    }
+}
@@ -0,0 +1,547 @@
+use crate::osal::OsalError;
+use log::{debug, warn};
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+// qemu throttles migrations to 128MiB/s by default, which is far too slow when saving to a local file
+const MIGRATE_MAX_BANDWIDTH: u64 = 10 * 1024 * 1024 * 1024;
+/// `migrate file:` and `-incoming file:` are only supported since qemu 8.2
+const FILE_MIGRATION_VERSION: (u64, u64) = (8, 2);
+
+/// fails when a qemu of the given version can not save or restore a state file
+pub fn check_file_migration(version: (u64, u64)) -> Result<(), OsalError> {
+    if version < FILE_MIGRATION_VERSION {
+        return Err(OsalError::ExecError(Some(format!(
+            "hibernate and resume need qemu {}.{} or later, qemu is {}.{}",
+            FILE_MIGRATION_VERSION.0, FILE_MIGRATION_VERSION.1, version.0, version.1
+        ))));
+    }
+    Ok(())
+}
+
+/// Minimal client for the qemu machine protocol (QMP) socket that
+/// General::get_qemu_args() sets up for every vm.
+pub struct QmpClient {
+    reader: BufReader<UnixStream>,
+    writer: UnixStream,
+    pending: String,
+    events: Vec<Value>,
+    /// the major and minor version of qemu, from its greeting
+    version: (u64, u64),
+}
+
+impl QmpClient {
+    pub fn connect<P: AsRef<Path>>(path: P) -> Result<Self, OsalError> {
+        let socket = format!("{:?}", path.as_ref());
+        debug!("QmpClient::connect({})", socket);
+
+        let stream = UnixStream::connect(path).map_err(|_| OsalError::OpenError(Some(socket)))?;
+        Self::from_stream(stream)
+    }
+
+    fn from_stream(stream: UnixStream) -> Result<Self, OsalError> {
+        let writer = stream
+            .try_clone()
+            .map_err(|_| OsalError::OpenError(Some("qmp".to_string())))?;
+
+        let mut client = Self {
+            reader: BufReader::new(stream),
+            writer,
+            pending: String::new(),
+            events: vec![],
+            version: (0, 0),
+        };
+        client.handshake()?;
+        Ok(client)
+    }
+
+    /// qemu greets with a banner, and only accepts commands after capabilities negotiation
+    fn handshake(&mut self) -> Result<(), OsalError> {
+        let greeting = self.read_message(REPLY_TIMEOUT)?;
+        if greeting.get("QMP").is_none() {
+            return Err(OsalError::ParseError(Some(format!(
+                "unexpected qmp greeting: {}",
+                greeting
+            ))));
+        }
+        let version = &greeting["QMP"]["version"]["qemu"];
+        self.version = (
+            version["major"].as_u64().unwrap_or_default(),
+            version["minor"].as_u64().unwrap_or_default(),
+        );
+
+        self.execute("qmp_capabilities", None)?;
+        Ok(())
+    }
+
+    pub fn execute(&mut self, command: &str, arguments: Option<Value>) -> Result<Value, OsalError> {
+        debug!("QmpClient::execute({}, {:?})", command, arguments);
+
+        let mut request = json!({ "execute": command });
+        if let Some(arguments) = arguments {
+            request["arguments"] = arguments;
+        }
+
+        writeln!(self.writer, "{}", request)
+            .map_err(|_| OsalError::WriteError(Some(command.to_string())))?;
+
+        loop {
+            let message = self.read_message(REPLY_TIMEOUT)?;
+            if let Some(result) = message.get("return") {
+                return Ok(result.clone());
+            }
+            if let Some(error) = message.get("error") {
+                return Err(OsalError::ExecError(Some(format!(
+                    "{}: {}",
+                    command,
+                    error["desc"].as_str().unwrap_or("unknown error")
+                ))));
+            }
+            if message.get("event").is_some() {
+                // keep events that arrive before the reply, someone may be waiting for them
+                self.events.push(message);
+            }
+        }
+    }
+
+    pub fn wait_for_event(&mut self, event: &str, timeout: Duration) -> Result<Value, OsalError> {
+        debug!("QmpClient::wait_for_event({}, {:?})", event, timeout);
+
+        if let Some(position) = self.events.iter().position(|e| e["event"] == event) {
+            return Ok(self.events.remove(position));
+        }
+
+        let deadline = Instant::now() + timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(OsalError::Timeout(Some(event.to_string())));
+            }
+
+            match self.read_message(remaining) {
+                Ok(message) if message["event"] == event => return Ok(message),
+                Ok(_) => continue,
+                Err(OsalError::Timeout(_)) => {
+                    return Err(OsalError::Timeout(Some(event.to_string())))
+                }
+                Err(error) => return Err(error),
+            }
+        }
+    }
+
+    /// ask the guest os to shut down, and wait for it to do so
+    pub fn system_powerdown(&mut self, timeout: Duration) -> Result<(), OsalError> {
+        self.execute("system_powerdown", None)?;
+        self.wait_for_event("SHUTDOWN", timeout)?;
+        Ok(())
+    }
+
+    /// terminate qemu without involving the guest os
+    pub fn quit(&mut self, timeout: Duration) -> Result<(), OsalError> {
+        self.execute("quit", None)?;
+
+        // qemu closes the socket once it is on its way out
+        let deadline = Instant::now() + timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(OsalError::Timeout(Some("quit".to_string())));
+            }
+
+            match self.read_message(remaining) {
+                Ok(_) => continue,
+                Err(OsalError::ReadError(_)) => return Ok(()),
+                Err(OsalError::Timeout(_)) => {
+                    return Err(OsalError::Timeout(Some("quit".to_string())))
+                }
+                Err(error) => return Err(error),
+            }
+        }
+    }
+
+    /// pause the vm and save its state to a file, qemu stays alive (paused) afterwards
+    pub fn save_state(&mut self, path: &str, timeout: Duration) -> Result<(), OsalError> {
+        check_file_migration(self.version)?;
+        self.execute("stop", None)?;
+        self.execute(
+            "migrate-set-parameters",
+            Some(json!({ "max-bandwidth": MIGRATE_MAX_BANDWIDTH })),
+        )?;
+        self.execute("migrate", Some(json!({ "uri": format!("file:{}", path) })))?;
+
+        let deadline = Instant::now() + timeout;
+        loop {
+            let migration = self.execute("query-migrate", None)?;
+            match migration["status"].as_str() {
+                Some("completed") => return Ok(()),
+                Some("failed") | Some("cancelled") => {
+                    return Err(OsalError::ExecError(Some(format!(
+                        "migrate: {}",
+                        migration["error-desc"]
+                            .as_str()
+                            .unwrap_or("migration failed")
+                    ))))
+                }
+                _ => {}
+            }
+
+            if Instant::now() >= deadline {
+                return Err(OsalError::Timeout(Some("migrate".to_string())));
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    /// wait for the incoming migration to restore a saved state, and continue the vm; it was
+    /// saved by save_state(), after stopping it, so it is restored paused
+    pub fn restore_state(&mut self, timeout: Duration) -> Result<(), OsalError> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let migration = self.execute("query-migrate", None)?;
+            match migration["status"].as_str() {
+                Some("completed") => {
+                    self.execute("cont", None)?;
+                    return Ok(());
+                }
+                Some("failed") | Some("cancelled") => {
+                    return Err(OsalError::ExecError(Some(format!(
+                        "incoming migration: {}",
+                        migration["error-desc"]
+                            .as_str()
+                            .unwrap_or("migration failed")
+                    ))))
+                }
+                _ => {}
+            }
+
+            if Instant::now() >= deadline {
+                return Err(OsalError::Timeout(Some("query-migrate".to_string())));
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    fn read_message(&mut self, timeout: Duration) -> Result<Value, OsalError> {
+        self.reader
+            .get_ref()
+            .set_read_timeout(Some(timeout))
+            .map_err(|_| OsalError::ReadError(Some("qmp".to_string())))?;
+
+        // partial lines are kept in self.pending, so a timeout does not lose data
+        match self.reader.read_line(&mut self.pending) {
+            Ok(0) => Err(OsalError::ReadError(Some(
+                "qmp connection closed".to_string(),
+            ))),
+            Ok(_) => {
+                let line = std::mem::take(&mut self.pending);
+                debug!("QmpClient::read_message(): {}", line.trim_end());
+                serde_json::from_str(&line).map_err(|_| {
+                    warn!(
+                        "QmpClient::read_message(): unable to parse '{}'",
+                        line.trim_end()
+                    );
+                    OsalError::ParseError(Some(line))
+                })
+            }
+            Err(error) if matches!(error.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                Err(OsalError::Timeout(Some("qmp".to_string())))
+            }
+            Err(_) => Err(OsalError::ReadError(Some("qmp".to_string()))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use std::thread::JoinHandle;
+
+    const GREETING: &str = r#"{"QMP": {"version": {"qemu": {"micro": 0, "minor": 2, "major": 8}}, "capabilities": []}}"#;
+
+    /// plays the qemu side of the conversation: for every expected command, send the scripted replies
+    /// the connection stays open until the handle is joined, unless close_when_done is set
+    fn fake_qemu(
+        stream: UnixStream,
+        script: Vec<(&'static str, Vec<&'static str>)>,
+        close_when_done: bool,
+    ) -> JoinHandle<Option<UnixStream>> {
+        thread::spawn(move || {
+            let mut writer = stream.try_clone().unwrap();
+            let mut reader = BufReader::new(stream);
+
+            writeln!(writer, "{}", GREETING).unwrap();
+            for (command, replies) in script {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                let request: Value = serde_json::from_str(&line).unwrap();
+                assert_eq!(request["execute"], command);
+
+                for reply in replies {
+                    writeln!(writer, "{}", reply).unwrap();
+                }
+            }
+
+            (!close_when_done).then_some(writer)
+        })
+    }
+
+    #[test]
+    fn test_system_powerdown_succeeds() {
+        let (client, server) = UnixStream::pair().unwrap();
+        let qemu = fake_qemu(
+            server,
+            vec![
+                ("qmp_capabilities", vec![r#"{"return": {}}"#]),
+                (
+                    "system_powerdown",
+                    vec![
+                        r#"{"return": {}}"#,
+                        r#"{"event": "POWERDOWN", "timestamp": {"seconds": 1, "microseconds": 0}}"#,
+                        r#"{"event": "SHUTDOWN", "data": {"guest": true, "reason": "guest-shutdown"}, "timestamp": {"seconds": 2, "microseconds": 0}}"#,
+                    ],
+                ),
+            ],
+            false,
+        );
+
+        let mut qmp = QmpClient::from_stream(client).unwrap();
+        assert_eq!(qmp.system_powerdown(Duration::from_secs(1)), Ok(()));
+        qemu.join().unwrap();
+    }
+
+    #[test]
+    fn test_system_powerdown_times_out() {
+        let (client, server) = UnixStream::pair().unwrap();
+        let qemu = fake_qemu(
+            server,
+            vec![
+                ("qmp_capabilities", vec![r#"{"return": {}}"#]),
+                ("system_powerdown", vec![r#"{"return": {}}"#]),
+            ],
+            false,
+        );
+
+        let mut qmp = QmpClient::from_stream(client).unwrap();
+        assert_eq!(
+            qmp.system_powerdown(Duration::from_millis(100)),
+            Err(OsalError::Timeout(Some("SHUTDOWN".to_string())))
+        );
+        qemu.join().unwrap();
+    }
+
+    #[test]
+    fn test_event_before_reply_is_not_lost() {
+        let (client, server) = UnixStream::pair().unwrap();
+        let qemu = fake_qemu(
+            server,
+            vec![
+                ("qmp_capabilities", vec![r#"{"return": {}}"#]),
+                (
+                    "system_powerdown",
+                    vec![
+                        r#"{"event": "SHUTDOWN", "data": {"guest": true}}"#,
+                        r#"{"return": {}}"#,
+                    ],
+                ),
+            ],
+            false,
+        );
+
+        let mut qmp = QmpClient::from_stream(client).unwrap();
+        assert_eq!(qmp.system_powerdown(Duration::from_millis(100)), Ok(()));
+        qemu.join().unwrap();
+    }
+
+    #[test]
+    fn test_error_reply_is_reported() {
+        let (client, server) = UnixStream::pair().unwrap();
+        let qemu = fake_qemu(
+            server,
+            vec![
+                ("qmp_capabilities", vec![r#"{"return": {}}"#]),
+                (
+                    "no_such_command",
+                    vec![
+                        r#"{"error": {"class": "CommandNotFound", "desc": "The command no_such_command has not been found"}}"#,
+                    ],
+                ),
+            ],
+            false,
+        );
+
+        let mut qmp = QmpClient::from_stream(client).unwrap();
+        assert_eq!(
+            qmp.execute("no_such_command", None),
+            Err(OsalError::ExecError(Some(
+                "no_such_command: The command no_such_command has not been found".to_string()
+            )))
+        );
+        qemu.join().unwrap();
+    }
+
+    #[test]
+    fn test_save_state_polls_until_migration_completed() {
+        let (client, server) = UnixStream::pair().unwrap();
+        let qemu = fake_qemu(
+            server,
+            vec![
+                ("qmp_capabilities", vec![r#"{"return": {}}"#]),
+                ("stop", vec![r#"{"event": "STOP"}"#, r#"{"return": {}}"#]),
+                ("migrate-set-parameters", vec![r#"{"return": {}}"#]),
+                ("migrate", vec![r#"{"return": {}}"#]),
+                ("query-migrate", vec![r#"{"return": {"status": "active"}}"#]),
+                (
+                    "query-migrate",
+                    vec![r#"{"return": {"status": "completed"}}"#],
+                ),
+            ],
+            false,
+        );
+
+        let mut qmp = QmpClient::from_stream(client).unwrap();
+        assert_eq!(
+            qmp.save_state("/var/ezkvm/wakiza.state", Duration::from_secs(5)),
+            Ok(())
+        );
+        qemu.join().unwrap();
+    }
+
+    #[test]
+    fn test_save_state_needs_qemu_8_2() {
+        let (client, server) = UnixStream::pair().unwrap();
+        let qemu = thread::spawn(move || {
+            let mut writer = server.try_clone().unwrap();
+            let mut reader = BufReader::new(server);
+            writeln!(
+                writer,
+                "{}",
+                GREETING.replace(r#""minor": 2"#, r#""minor": 1"#)
+            )
+            .unwrap();
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            writeln!(writer, r#"{{"return": {{}}}}"#).unwrap();
+            // nothing else is sent, the vm is not even stopped
+            line.clear();
+            reader.read_line(&mut line).unwrap();
+            line
+        });
+
+        let mut qmp = QmpClient::from_stream(client).unwrap();
+        assert_eq!(
+            qmp.save_state("/var/ezkvm/wakiza.state", Duration::from_secs(5)),
+            Err(OsalError::ExecError(Some(
+                "hibernate and resume need qemu 8.2 or later, qemu is 8.1".to_string()
+            )))
+        );
+        drop(qmp);
+        assert_eq!(qemu.join().unwrap(), "");
+    }
+
+    #[test]
+    fn test_save_state_reports_failed_migration() {
+        let (client, server) = UnixStream::pair().unwrap();
+        let qemu = fake_qemu(
+            server,
+            vec![
+                ("qmp_capabilities", vec![r#"{"return": {}}"#]),
+                ("stop", vec![r#"{"return": {}}"#]),
+                ("migrate-set-parameters", vec![r#"{"return": {}}"#]),
+                ("migrate", vec![r#"{"return": {}}"#]),
+                (
+                    "query-migrate",
+                    vec![
+                        r#"{"return": {"status": "failed", "error-desc": "No space left on device"}}"#,
+                    ],
+                ),
+            ],
+            false,
+        );
+
+        let mut qmp = QmpClient::from_stream(client).unwrap();
+        assert_eq!(
+            qmp.save_state("/var/ezkvm/wakiza.state", Duration::from_secs(5)),
+            Err(OsalError::ExecError(Some(
+                "migrate: No space left on device".to_string()
+            )))
+        );
+        qemu.join().unwrap();
+    }
+
+    #[test]
+    fn test_restore_state_continues_the_vm() {
+        let (client, server) = UnixStream::pair().unwrap();
+        let qemu = fake_qemu(
+            server,
+            vec![
+                ("qmp_capabilities", vec![r#"{"return": {}}"#]),
+                ("query-migrate", vec![r#"{"return": {}}"#]),
+                ("query-migrate", vec![r#"{"return": {"status": "active"}}"#]),
+                (
+                    "query-migrate",
+                    vec![r#"{"return": {"status": "completed"}}"#],
+                ),
+                // the event comes first, the client may be gone once it has its return
+                ("cont", vec![r#"{"event": "RESUME"}"#, r#"{"return": {}}"#]),
+            ],
+            false,
+        );
+
+        let mut qmp = QmpClient::from_stream(client).unwrap();
+        assert_eq!(qmp.restore_state(Duration::from_secs(5)), Ok(()));
+        // a cont that was never sent fails the fake qemu, once the connection is gone
+        drop(qmp);
+        qemu.join().unwrap();
+    }
+
+    #[test]
+    fn test_restore_state_reports_failed_migration() {
+        let (client, server) = UnixStream::pair().unwrap();
+        let qemu = fake_qemu(
+            server,
+            vec![
+                ("qmp_capabilities", vec![r#"{"return": {}}"#]),
+                (
+                    "query-migrate",
+                    vec![
+                        r#"{"return": {"status": "failed", "error-desc": "load of migration failed"}}"#,
+                    ],
+                ),
+            ],
+            false,
+        );
+
+        let mut qmp = QmpClient::from_stream(client).unwrap();
+        assert_eq!(
+            qmp.restore_state(Duration::from_secs(5)),
+            Err(OsalError::ExecError(Some(
+                "incoming migration: load of migration failed".to_string()
+            )))
+        );
+        qemu.join().unwrap();
+    }
+
+    #[test]
+    fn test_quit_waits_for_connection_to_close() {
+        let (client, server) = UnixStream::pair().unwrap();
+        let qemu = fake_qemu(
+            server,
+            vec![
+                ("qmp_capabilities", vec![r#"{"return": {}}"#]),
+                ("quit", vec![r#"{"return": {}}"#]),
+            ],
+            true,
+        );
+
+        let mut qmp = QmpClient::from_stream(client).unwrap();
+        assert_eq!(qmp.quit(Duration::from_secs(1)), Ok(()));
+        qemu.join().unwrap();
+    }
+}
@@ -1,6 +1,7 @@
 use crate::resource::lock::{Lock};
 use crate::resource::resource::Resource;
 use crate::resource::resource_pool::ResourcePool;
+use crate::resource::tag_expression::TagExpression;
 use log::{debug, info};
 use once_cell::sync::Lazy;
 use std::collections::HashMap;
@@ -12,7 +13,7 @@ static RESOURCE_MANAGER: Lazy<Arc<Mutex<DataManager>>> =
     Lazy::new(|| Arc::new(Mutex::new(DataManager::new("/etc/ezkvm/resource"))));
 
 #[allow(dead_code)]
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct DataManager {
     resources: HashMap<String, ResourcePool>,
     locks: HashMap<String, Lock>,
@@ -29,6 +30,10 @@ impl DataManager {
         let locks = load_machine_locks().unwrap_or_default();
         info!("ResourceManager::new() locks:\n{:?}", locks);
 
+        Self::from(resources, locks)
+    }
+
+    pub fn from(resources: HashMap<String, ResourcePool>, locks: HashMap<String, Lock>) -> Self {
         let locked_resources = find_locked_resources(&resources, &locks);
         info!(
             "ResourceManager::new() locked_resources:\n{:?}",
@@ -47,22 +52,69 @@ impl DataManager {
         RESOURCE_MANAGER.clone()
     }
 
-    pub fn claim_resource(&mut self, pool: String) -> Result<String, OsalError> {
-        debug!("DataManager::claim_resource('{}')", pool);
+    /// claims a free device from the given pool, or from any pool when none is given;
+    /// a free device listed in preferred wins, otherwise pools are searched in alphabetical
+    /// order and devices in the order of their pool file
+    pub fn claim_resource(
+        &mut self,
+        pool: Option<&String>,
+        tags: Option<&TagExpression>,
+        preferred: &[String],
+    ) -> Result<Resource, OsalError> {
+        debug!(
+            "DataManager::claim_resource({:?}, {:?}, {:?})",
+            pool, tags, preferred
+        );
+
+        let mut pools: Vec<(&String, &ResourcePool)> = match pool {
+            Some(pool) => self.resources.get_key_value(pool).into_iter().collect(),
+            None => self.resources.iter().collect(),
+        };
+        pools.sort_by_key(|(name, _)| *name);
+
+        let candidates: Vec<&Resource> = pools
+            .iter()
+            .flat_map(|(_, resource_pool)| resource_pool.find_resources(tags))
+            .filter(|resource| !self.locked_resources.contains_key(&resource.get_id()))
+            .collect();
+        let claimed = preferred
+            .iter()
+            .find_map(|id| candidates.iter().find(|resource| resource.get_id() == *id))
+            .or(candidates.first())
+            .map(|resource| (*resource).clone());
 
-        if let Some(resource_pool) = self.resources.get(&pool) {
-            let id = resource_pool.claim_resource(&self.locked_resources)?;
-            self.current_lock.add_resource(id.clone());
-            self.locked_resources
-                .insert(id.clone(), self.current_lock.name().clone());
-            return Ok(id);
+        match claimed {
+            Some(resource) => {
+                let id = resource.get_id();
+                debug!("DataManager::claim_resource() found: {}", id);
+                self.current_lock.add_resource(id.clone());
+                self.locked_resources
+                    .insert(id, self.current_lock.name().clone());
+                Ok(resource)
+            }
+            None => {
+                let request = match (pool, tags) {
+                    (Some(pool), Some(tags)) => format!("{}: {}", pool, tags),
+                    (Some(pool), None) => pool.clone(),
+                    (None, Some(tags)) => tags.to_string(),
+                    (None, None) => "any".to_string(),
+                };
+                debug!(
+                    "DataManager::claim_resource() Resource '{}' not available.",
+                    request
+                );
+                Err(OsalError::Busy(Some(request)))
+            }
         }
+    }
 
-        debug!(
-            "DataManager::claim_resource() Resource '{}' not available.",
-            pool.clone()
-        );
-        Err(OsalError::Busy(Some(pool)))
+    /// forgets the lock of a vm, so the resources it holds count as free again
+    pub fn release(&mut self, name: &str) {
+        if let Some(lock) = self.locks.remove(name) {
+            for resource in lock.resources() {
+                self.locked_resources.remove(resource);
+            }
+        }
     }
 
     pub fn get_resource(&self, pool: &String, id: &String) -> Option<&Resource> {
@@ -88,14 +140,12 @@ fn load_resource_pools() -> Result<HashMap<String, ResourcePool>, OsalError> {
     let mut resource_pools = HashMap::from([]);
 
     let files = fs::read_dir("/etc/ezkvm/resources/").map_err(|_| OsalError::OpenError(None) )?;
-    for file in files {
-        if let Ok(entry) = file {
-            if let Ok(file_name) = entry.file_name().into_string() {
-                if let Some(base_name) = file_name.strip_suffix(".yaml") {
-                    debug!("load_resource_pools(): {:?}", base_name);
-                    if let Ok(resource_pool) = ResourcePool::read(base_name) {
-                        resource_pools.insert(base_name.to_string(), resource_pool);
-                    }
+    for entry in files.flatten() {
+        if let Ok(file_name) = entry.file_name().into_string() {
+            if let Some(base_name) = file_name.strip_suffix(".yaml") {
+                debug!("load_resource_pools(): {:?}", base_name);
+                if let Ok(resource_pool) = ResourcePool::read(base_name) {
+                    resource_pools.insert(base_name.to_string(), resource_pool);
                 }
             }
         }
@@ -106,23 +156,8 @@ fn load_resource_pools() -> Result<HashMap<String, ResourcePool>, OsalError> {
 
 fn load_machine_locks() -> Result<HashMap<String, Lock>, OsalError> {
     debug!("read_locks()");
-    let mut locks = HashMap::from([]);
-
-    let files = fs::read_dir("/var/ezkvm/lock/").map_err(|_| OsalError::OpenError(None))?;
-    for file in files {
-        if let Ok(entry) = file {
-            if let Ok(file_name) = entry.file_name().into_string() {
-                if let Some(base_name) = file_name.strip_suffix(".yaml") {
-                    debug!("LockList::read(): {:?}", base_name);
-                    if let Ok(lock) = Lock::read(base_name) {
-                        locks.insert(base_name.to_string(), lock);
-                    }
-                }
-            }
-        }
-    }
-
-    Ok(locks)
+    // a vm that is being started holds the pid of ezkvm, and already holds its resources
+    Ok(Lock::list_held().into_iter().collect())
 }
 
 fn find_locked_resources(
@@ -131,7 +166,7 @@ fn find_locked_resources(
 ) -> HashMap<String, String> {
     let mut result = HashMap::from([]);
 
-    for (_, lock) in locks {
+    for lock in locks.values() {
         for locked_resource in lock.resources() {
             result.insert(locked_resource.clone(), lock.name().clone());
         }
@@ -139,3 +174,106 @@ fn find_locked_resources(
 
     result
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data_manager() -> DataManager {
+        let p2200: ResourcePool = serde_yaml::from_str(
+            r#"
+            id: p2200
+            devices:
+              - { id: nvidia12, tags: [ gpu, nvidia ], pci: [ "0000:12:00.0" ] }
+              - { id: nvidia43, tags: [ gpu, nvidia ], pci: [ "0000:43:00.0" ] }
+        "#,
+        )
+        .unwrap();
+        let rx570: ResourcePool = serde_yaml::from_str(
+            r#"
+            id: rx570
+            devices:
+              - { id: amd03, tags: [ gpu, amd ], pci: [ "0000:03:00.0" ] }
+        "#,
+        )
+        .unwrap();
+        let lock = Lock::new("mygeeto".to_string(), 12345, vec!["amd03".to_string()]);
+
+        DataManager::from(
+            HashMap::from([("rx570".to_string(), rx570), ("p2200".to_string(), p2200)]),
+            HashMap::from([("mygeeto".to_string(), lock)]),
+        )
+    }
+
+    fn claim(
+        data_manager: &mut DataManager,
+        pool: Option<&str>,
+        tags: Option<&str>,
+        preferred: &[String],
+    ) -> Result<String, OsalError> {
+        let pool = pool.map(str::to_string);
+        let tags: Option<TagExpression> = tags.map(|tags| tags.parse().unwrap());
+        data_manager
+            .claim_resource(pool.as_ref(), tags.as_ref(), preferred)
+            .map(|resource| resource.get_id())
+    }
+
+    #[test]
+    fn test_claim_across_pools_in_order() {
+        let mut data_manager = data_manager();
+
+        assert_eq!(
+            claim(&mut data_manager, None, Some("gpu"), &[]),
+            Ok("nvidia12".to_string())
+        );
+        assert_eq!(
+            claim(&mut data_manager, None, Some("gpu"), &[]),
+            Ok("nvidia43".to_string())
+        );
+        assert_eq!(
+            claim(&mut data_manager, None, Some("gpu"), &[]),
+            Err(OsalError::Busy(Some("gpu".to_string())))
+        );
+    }
+
+    #[test]
+    fn test_claim_from_pool() {
+        let mut data_manager = data_manager();
+
+        assert_eq!(
+            claim(&mut data_manager, Some("rx570"), None, &[]),
+            Err(OsalError::Busy(Some("rx570".to_string())))
+        );
+        assert_eq!(
+            claim(&mut data_manager, Some("unknown"), Some("gpu"), &[]),
+            Err(OsalError::Busy(Some("unknown: gpu".to_string())))
+        );
+    }
+
+    #[test]
+    fn test_claim_prefers_previous_resource() {
+        let mut data_manager = data_manager();
+        let preferred = vec!["amd03".to_string(), "nvidia43".to_string()];
+
+        // amd03 is in use, so the next preferred resource is claimed
+        assert_eq!(
+            claim(&mut data_manager, None, Some("nvidia"), &preferred),
+            Ok("nvidia43".to_string())
+        );
+        assert_eq!(
+            claim(&mut data_manager, None, Some("nvidia"), &preferred),
+            Ok("nvidia12".to_string())
+        );
+    }
+
+    #[test]
+    fn test_release() {
+        let mut data_manager = data_manager();
+        data_manager.release("mygeeto");
+
+        assert_eq!(
+            claim(&mut data_manager, Some("rx570"), None, &[]),
+            Ok("amd03".to_string())
+        );
+    }
+}
@@ -2,14 +2,20 @@
 use crate::osal::Osal;
 use crate::osal::OsalError;
 use derive_getters::Getters;
-use log::debug;
+use log::{debug, warn};
 use serde::{Deserialize, Serialize};
+use std::process;
+
+const LOCK_DIRECTORY: &str = "/var/ezkvm/lock";
 
 #[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Getters)]
 pub struct Lock {
     name: String,
     pid: u32,
     resources: Vec<String>,
+    /// the swtpm that emulates the tpm of the vm, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    swtpm_pid: Option<u32>,
 }
 
 #[allow(dead_code)]
@@ -19,36 +25,155 @@ impl Lock {
             name,
             pid,
             resources,
+            swtpm_pid: None,
         }
     }
 
+    pub fn with_swtpm_pid(self, swtpm_pid: Option<u32>) -> Self {
+        Self { swtpm_pid, ..self }
+    }
+
     pub fn add_resource(&mut self, id: String) {
         self.resources.push(id);
     }
 
+    pub fn set_pid(&mut self, pid: u32) {
+        self.pid = pid;
+    }
+
+    pub fn set_swtpm_pid(&mut self, swtpm_pid: Option<u32>) {
+        self.swtpm_pid = swtpm_pid;
+    }
+
+    fn filename(name: &str) -> String {
+        format!("{}/{}.yaml", LOCK_DIRECTORY, name)
+    }
+
+    fn content(&self) -> Result<String, OsalError> {
+        serde_yaml::to_string(&self)
+            .map_err(|_| OsalError::ParseError(Some(Lock::filename(&self.name))))
+    }
+
     pub fn read(name: &str) -> Result<Self, OsalError> {
-        let filename = format!("/var/ezkvm/lock/{}.yaml", name);
+        let filename = Lock::filename(name);
         let content = Osal::read_file(filename.clone())?;
         let result = serde_yaml::from_str(content.as_str())
             .map_err(|_| OsalError::ParseError(Some(filename)))?;
         Ok(result)
     }
 
-    #[allow(dead_code)]
+    /// the locks of the vms that are running or being started, with the name of their lock
+    /// file; a lock that is left behind is skipped, not removed, Lock::acquire() replaces it
+    pub fn list_held() -> Vec<(String, Self)> {
+        Osal::find_files("*.yaml", vec![LOCK_DIRECTORY])
+            .iter()
+            .filter_map(|path| path.file_stem()?.to_str().map(str::to_string))
+            .filter_map(|name| Lock::read(&name).ok().map(|lock| (name, lock)))
+            .filter(|(_, lock)| lock.is_held())
+            .collect()
+    }
+
+    /// the lock of another vm, running or being started, that holds the resource; vms are
+    /// told apart by the name of their lock file, which is the name they were started with
+    pub fn find_holder(resource: &str, name: &str) -> Option<Self> {
+        Lock::list_held()
+            .into_iter()
+            .find(|(other, lock)| {
+                other != name && lock.resources.iter().any(|held| held == resource)
+            })
+            .map(|(_, lock)| lock)
+    }
+
+    /// fails when another vm holds one of the resources of this lock; checked after the lock
+    /// is written, so of two vms that claim a resource at once, at least one backs off
+    pub fn check_claims(&self) -> Result<(), OsalError> {
+        for resource in &self.resources {
+            if let Some(holder) = Lock::find_holder(resource, &self.name) {
+                let what = match resource.as_str() {
+                    "tpm:host" => "the tpm of the host".to_string(),
+                    _ => format!("resource {}", resource),
+                };
+                return Err(OsalError::Busy(Some(format!(
+                    "{} is in use by vm {}",
+                    what,
+                    holder.name()
+                ))));
+            }
+        }
+        Ok(())
+    }
+
+    /// creates the lock of a vm before any of it is started, so a second start of the vm fails
+    /// here; it holds the pid of ezkvm until write() replaces it with the one of qemu
+    pub fn acquire(name: &str) -> Result<Self, OsalError> {
+        debug!("Lock[{}].acquire()", name);
+        let lock = Lock::new(name.to_string(), process::id(), vec![]);
+        let content = lock.content()?;
+
+        Osal::create_directory(LOCK_DIRECTORY)?;
+        match Osal::create_file(Lock::filename(name), content.clone()) {
+            Err(OsalError::Busy(_)) => {}
+            result => return result.map(|()| lock),
+        }
+
+        match Lock::read(name) {
+            Ok(existing) if existing.is_held() => Err(OsalError::Busy(Some(format!(
+                "{} is already running with pid {}",
+                name, existing.pid
+            )))),
+            Ok(existing) => {
+                warn!(
+                    "Lock::acquire(): removing stale lock for {} (pid {})",
+                    name, existing.pid
+                );
+                existing.delete()?;
+                Osal::create_file(Lock::filename(name), content)
+                    .map_err(|_| OsalError::Busy(Some(format!("{} is being started", name))))?;
+                Ok(lock)
+            }
+            // created, but not written yet
+            Err(_) => Err(OsalError::Busy(Some(format!("{} is being started", name)))),
+        }
+    }
+
     pub fn write(&self) -> Result<(), OsalError> {
-        let filename = format!("/var/ezkvm/lock/{}.yaml", self.name);
-        let content = serde_yaml::to_string(&self)
-            .map_err(|_| OsalError::ParseError(Some(filename.clone())))?;
+        debug!("Lock[{}].write()", self.name);
+        let content = self.content()?;
 
-        Osal::write_file(filename, content)
+        Osal::create_directory(LOCK_DIRECTORY)?;
+        Osal::write_file(Lock::filename(&self.name), content)
     }
 
-    #[allow(dead_code)]
     pub fn delete(&self) -> Result<(), OsalError> {
         debug!("Lock[{}].delete()", self.name);
-        let filename = format!("/var/ezkvm/lock/{}.yaml", self.name);
+        Osal::delete_file(Lock::filename(&self.name))
+    }
 
-        Osal::delete_file(filename)
+    /// a lock is only valid while its pid is a running qemu process; the pid may
+    /// have been reused by an unrelated process since the lock was written
+    pub fn is_alive(&self) -> bool {
+        match Osal::get_process_name(self.pid) {
+            Some(process_name) => process_name.starts_with("qemu"),
+            None => false,
+        }
+    }
+
+    /// stops the swtpm of the vm, unless its pid has been reused by another process
+    pub fn terminate_swtpm(&self) -> Result<(), OsalError> {
+        match self.swtpm_pid {
+            Some(pid) if Osal::get_process_name(pid).is_some_and(|name| name == "swtpm") => {
+                Osal::terminate_process(pid)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// whether the vm runs, or is being started by an ezkvm that still waits for qemu
+    pub fn is_held(&self) -> bool {
+        match Osal::get_process_name(self.pid) {
+            Some(process_name) => process_name.starts_with("qemu") || process_name == "ezkvm",
+            None => false,
+        }
     }
 }
 
@@ -56,6 +181,7 @@ impl Lock {
 mod test {
     use super::*;
     use serial_test::serial;
+    use std::path::PathBuf;
 
     #[test]
     #[serial]
@@ -88,7 +214,343 @@ mod test {
             name: "wakiza".to_string(),
             pid: 12345,
             resources: vec![],
+            swtpm_pid: None,
         };
         assert_eq!(actual, expectation)
     }
+
+    #[test]
+    #[serial]
+    pub fn write_creates_the_lock_directory() {
+        let create_directory = Osal::create_directory_context();
+        create_directory
+            .expect()
+            .withf(|path: &&str| *path == "/var/ezkvm/lock")
+            .times(1)
+            .returning(|_path: &str| Ok(()));
+        let write_file = Osal::write_file_context();
+        write_file
+            .expect()
+            .times(1)
+            .returning(|path: String, content: String| {
+                assert_eq!(path, "/var/ezkvm/lock/wakiza.yaml");
+                assert_eq!(
+                    content,
+                    "name: wakiza\npid: 12345\nresources:\n- nvidia12\n"
+                );
+                Ok(())
+            });
+
+        let lock = Lock::new("wakiza".to_string(), 12345, vec!["nvidia12".to_string()]);
+        assert_eq!(lock.write(), Ok(()));
+    }
+
+    #[test]
+    #[serial]
+    pub fn lock_is_only_alive_for_a_qemu_process() {
+        let lock = Lock::new("wakiza".to_string(), 12345, vec![]);
+        let get_process_name = Osal::get_process_name_context();
+
+        get_process_name
+            .expect()
+            .returning(|_pid| Some("qemu-system-x86".to_string()));
+        assert!(lock.is_alive());
+
+        get_process_name.checkpoint();
+        get_process_name
+            .expect()
+            .returning(|_pid| Some("bash".to_string()));
+        assert!(!lock.is_alive());
+
+        get_process_name.checkpoint();
+        get_process_name.expect().returning(|_pid| None);
+        assert!(!lock.is_alive());
+    }
+
+    #[test]
+    #[serial]
+    pub fn terminate_swtpm_only_stops_swtpm() {
+        let lock = Lock::new("wakiza".to_string(), 12345, vec![]).with_swtpm_pid(Some(12344));
+        let get_process_name = Osal::get_process_name_context();
+        let terminate_process = Osal::terminate_process_context();
+
+        get_process_name
+            .expect()
+            .returning(|_pid| Some("swtpm".to_string()));
+        terminate_process
+            .expect()
+            .withf(|pid: &u32| *pid == 12344)
+            .times(1)
+            .returning(|_pid| Ok(()));
+        assert_eq!(lock.terminate_swtpm(), Ok(()));
+
+        get_process_name.checkpoint();
+        terminate_process.checkpoint();
+        get_process_name
+            .expect()
+            .returning(|_pid| Some("bash".to_string()));
+        terminate_process.expect().never();
+        assert_eq!(lock.terminate_swtpm(), Ok(()));
+
+        let lock = Lock::new("wakiza".to_string(), 12345, vec![]);
+        assert_eq!(lock.terminate_swtpm(), Ok(()));
+    }
+
+    #[test]
+    #[serial]
+    pub fn find_holder_skips_the_vm_itself_and_stale_locks() {
+        let find_files = Osal::find_files_context();
+        find_files
+            .expect()
+            .returning(|_pattern: &str, _locations: Vec<&str>| {
+                ["wakiza", "stale", "mygeeto"]
+                    .iter()
+                    .map(|name| PathBuf::from(format!("/var/ezkvm/lock/{}.yaml", name)))
+                    .collect()
+            });
+        let read_file = Osal::read_file_context();
+        read_file
+            .expect()
+            .returning(|path: String| match path.as_str() {
+                "/var/ezkvm/lock/wakiza.yaml" => {
+                    Ok("{ name: wakiza, pid: 12345, resources: [ tpm:host ] }".to_string())
+                }
+                "/var/ezkvm/lock/stale.yaml" => {
+                    Ok("{ name: stale, pid: 12346, resources: [ tpm:host ] }".to_string())
+                }
+                _ => Ok("{ name: mygeeto, pid: 12347, resources: [ tpm:host ] }".to_string()),
+            });
+        // mygeeto is still being started, so its lock has the pid of ezkvm
+        let get_process_name = Osal::get_process_name_context();
+        get_process_name.expect().returning(|pid| match pid {
+            12345 => Some("qemu-system-x86".to_string()),
+            12347 => Some("ezkvm".to_string()),
+            _ => None,
+        });
+
+        let holder = |name: &str| Lock::find_holder("tpm:host", name).map(|lock| lock.name);
+        assert_eq!(holder("wakiza"), Some("mygeeto".to_string()));
+        assert_eq!(holder("mygeeto"), Some("wakiza".to_string()));
+        assert_eq!(Lock::find_holder("nvidia12", "wakiza"), None);
+    }
+
+    #[test]
+    #[serial]
+    pub fn find_holder_sees_two_vms_that_start_at_once() {
+        let find_files = Osal::find_files_context();
+        find_files
+            .expect()
+            .returning(|_pattern: &str, _locations: Vec<&str>| {
+                vec![
+                    PathBuf::from("/var/ezkvm/lock/wakiza.yaml"),
+                    PathBuf::from("/var/ezkvm/lock/mygeeto.yaml"),
+                ]
+            });
+        let read_file = Osal::read_file_context();
+        read_file
+            .expect()
+            .returning(|path: String| match path.as_str() {
+                "/var/ezkvm/lock/wakiza.yaml" => {
+                    Ok("{ name: wakiza, pid: 12345, resources: [ tpm:host ] }".to_string())
+                }
+                _ => Ok("{ name: mygeeto, pid: 12346, resources: [ tpm:host ] }".to_string()),
+            });
+        // neither has spawned qemu yet, both locks still have the pid of ezkvm
+        let get_process_name = Osal::get_process_name_context();
+        get_process_name
+            .expect()
+            .returning(|_pid| Some("ezkvm".to_string()));
+
+        let holder = |name: &str| Lock::find_holder("tpm:host", name).map(|lock| lock.name);
+        assert_eq!(holder("wakiza"), Some("mygeeto".to_string()));
+        assert_eq!(holder("mygeeto"), Some("wakiza".to_string()));
+    }
+
+    #[test]
+    #[serial]
+    pub fn check_claims_fails_on_any_resource_held_by_another_vm() {
+        let find_files = Osal::find_files_context();
+        find_files
+            .expect()
+            .returning(|_pattern: &str, _locations: Vec<&str>| {
+                vec![
+                    PathBuf::from("/var/ezkvm/lock/wakiza.yaml"),
+                    PathBuf::from("/var/ezkvm/lock/mygeeto.yaml"),
+                ]
+            });
+        let read_file = Osal::read_file_context();
+        read_file
+            .expect()
+            .returning(|path: String| match path.as_str() {
+                "/var/ezkvm/lock/wakiza.yaml" => {
+                    Ok("{ name: wakiza, pid: 12345, resources: [ nvidia12 ] }".to_string())
+                }
+                _ => Ok("{ name: mygeeto, pid: 12346, resources: [ nvidia12 ] }".to_string()),
+            });
+        let get_process_name = Osal::get_process_name_context();
+        get_process_name
+            .expect()
+            .returning(|_pid| Some("ezkvm".to_string()));
+
+        let mut lock = Lock::new("wakiza".to_string(), 12345, vec![]);
+        lock.add_resource("enp14s0".to_string());
+        assert_eq!(lock.check_claims(), Ok(()));
+        lock.add_resource("nvidia12".to_string());
+        assert_eq!(
+            lock.check_claims(),
+            Err(OsalError::Busy(Some(
+                "resource nvidia12 is in use by vm mygeeto".to_string()
+            )))
+        );
+    }
+
+    #[test]
+    #[serial]
+    pub fn list_held_keeps_starting_vms_and_leaves_stale_locks() {
+        let find_files = Osal::find_files_context();
+        find_files
+            .expect()
+            .returning(|_pattern: &str, _locations: Vec<&str>| {
+                ["wakiza", "stale", "mygeeto"]
+                    .iter()
+                    .map(|name| PathBuf::from(format!("/var/ezkvm/lock/{}.yaml", name)))
+                    .collect()
+            });
+        let read_file = Osal::read_file_context();
+        read_file.expect().returning(|path: String| {
+            let name = path
+                .trim_start_matches("/var/ezkvm/lock/")
+                .trim_end_matches(".yaml");
+            let pid = match name {
+                "wakiza" => 12345,
+                "stale" => 12346,
+                _ => 12347,
+            };
+            Ok(format!("{{ name: {}, pid: {}, resources: [] }}", name, pid))
+        });
+        let get_process_name = Osal::get_process_name_context();
+        get_process_name.expect().returning(|pid| match pid {
+            12345 => Some("qemu-system-x86".to_string()),
+            12347 => Some("ezkvm".to_string()),
+            _ => None,
+        });
+        let delete_file = Osal::delete_file_context();
+        delete_file.expect::<String>().never();
+
+        let names: Vec<String> = Lock::list_held()
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        assert_eq!(names, vec!["wakiza".to_string(), "mygeeto".to_string()]);
+    }
+
+    #[test]
+    #[serial]
+    pub fn acquire_creates_the_lock() {
+        let create_directory = Osal::create_directory_context();
+        create_directory.expect().returning(|_path: &str| Ok(()));
+        let create_file = Osal::create_file_context();
+        create_file
+            .expect()
+            .times(1)
+            .returning(|path: String, content: String| {
+                assert_eq!(path, "/var/ezkvm/lock/wakiza.yaml");
+                assert_eq!(
+                    content,
+                    format!("name: wakiza\npid: {}\nresources: []\n", process::id())
+                );
+                Ok(())
+            });
+
+        let lock = Lock::acquire("wakiza").unwrap();
+        assert_eq!(lock.pid, process::id());
+    }
+
+    #[test]
+    #[serial]
+    pub fn acquire_refuses_a_running_vm() {
+        let create_directory = Osal::create_directory_context();
+        create_directory.expect().returning(|_path: &str| Ok(()));
+        let create_file = Osal::create_file_context();
+        create_file
+            .expect()
+            .returning(|path: String, _content: String| Err(OsalError::Busy(Some(path))));
+        let read_file = Osal::read_file_context();
+        read_file.expect().returning(|_path: String| {
+            Ok("{ name: wakiza, pid: 12345, resources: [] }".to_string())
+        });
+        let delete_file = Osal::delete_file_context();
+        delete_file.expect::<String>().never();
+        let get_process_name = Osal::get_process_name_context();
+
+        // running, and being started by an ezkvm that has not spawned qemu yet
+        for process_name in ["qemu-system-x86", "ezkvm"] {
+            get_process_name.checkpoint();
+            get_process_name
+                .expect()
+                .returning(move |_pid| Some(process_name.to_string()));
+            assert_eq!(
+                Lock::acquire("wakiza"),
+                Err(OsalError::Busy(Some(
+                    "wakiza is already running with pid 12345".to_string()
+                )))
+            );
+        }
+    }
+
+    #[test]
+    #[serial]
+    pub fn acquire_replaces_a_stale_lock() {
+        let create_directory = Osal::create_directory_context();
+        create_directory.expect().returning(|_path: &str| Ok(()));
+        let create_file = Osal::create_file_context();
+        let mut sequence = mockall::Sequence::new();
+        create_file
+            .expect()
+            .times(1)
+            .in_sequence(&mut sequence)
+            .returning(|path: String, _content: String| Err(OsalError::Busy(Some(path))));
+        let read_file = Osal::read_file_context();
+        read_file.expect().returning(|_path: String| {
+            Ok("{ name: wakiza, pid: 12345, resources: [] }".to_string())
+        });
+        let get_process_name = Osal::get_process_name_context();
+        get_process_name.expect().returning(|_pid| None);
+        let delete_file = Osal::delete_file_context();
+        delete_file
+            .expect()
+            .times(1)
+            .in_sequence(&mut sequence)
+            .returning(|path: String| {
+                assert_eq!(path, "/var/ezkvm/lock/wakiza.yaml");
+                Ok(())
+            });
+        create_file
+            .expect()
+            .times(1)
+            .in_sequence(&mut sequence)
+            .returning(|_path: String, _content: String| Ok(()));
+
+        assert!(Lock::acquire("wakiza").is_ok());
+    }
+
+    #[test]
+    #[serial]
+    pub fn acquire_refuses_a_lock_that_is_being_written() {
+        let create_directory = Osal::create_directory_context();
+        create_directory.expect().returning(|_path: &str| Ok(()));
+        let create_file = Osal::create_file_context();
+        create_file
+            .expect()
+            .returning(|path: String, _content: String| Err(OsalError::Busy(Some(path))));
+        let read_file = Osal::read_file_context();
+        read_file
+            .expect()
+            .returning(|_path: String| Ok("".to_string()));
+
+        assert_eq!(
+            Lock::acquire("wakiza"),
+            Err(OsalError::Busy(Some("wakiza is being started".to_string())))
+        );
+    }
 }
@@ -1,4 +1,6 @@
 pub mod data_manager;
 pub mod lock;
+#[allow(clippy::module_inception)]
 pub mod resource;
 pub mod resource_pool;
+pub mod tag_expression;
@@ -1,10 +1,10 @@
 //use crate::yaml::QemuDevice;
-use crate::config::QemuDevice;
+use crate::config::{PciAddress, PciSlot, QemuArgument, QemuDevice};
 use serde::de::{MapAccess, Visitor};
 use serde::{Deserialize, Deserializer};
 use std::fmt;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Resource {
     id: String,
     tags: Vec<String>,
@@ -12,28 +12,57 @@ pub struct Resource {
     parent: Option<String>,
     vf: Option<String>,
     multifunction: Option<bool>,
+    pci_address: Option<PciAddress>,
 }
 
 impl Resource {
     pub fn get_id(&self) -> String {
         self.id.clone()
     }
+
+    /// everything a tag expression can select this resource by
+    pub fn labels(&self) -> Vec<&str> {
+        let mut labels: Vec<&str> = self.tags.iter().map(String::as_str).collect();
+        labels.push(self.id.as_str());
+        if let Some(parent) = &self.parent {
+            labels.push(parent.as_str());
+        }
+        labels
+    }
 }
 
 impl QemuDevice for Resource {
-    fn get_qemu_args(&self, _index: usize) -> Vec<String> {
+    fn get_qemu_args(&self, index: usize) -> Vec<QemuArgument> {
         let mut result = vec![];
-        let offset = 10;
-        for (id, pci) in self.pci.iter().enumerate() {
-            result.push(format!(
-                "-device vfio-pci,host={},id=hostvf{},bus=ich9-pcie-port-1,addr=0x{}",
-                pci,
-                id,
-                id + offset
+        // the pci functions of a resource are kept together in a single slot
+        for (function, pci) in self.pci.iter().enumerate() {
+            let pci_address = match &self.pci_address {
+                Some(pci_address) => format!(
+                    ",{}",
+                    pci_address
+                        .with_function(function as u8)
+                        .get_device_options()
+                ),
+                None => "".to_string(),
+            };
+            let multifunction = match self.multifunction {
+                Some(true) if function == 0 => ",multifunction=on",
+                _ => "",
+            };
+            result.push(QemuArgument::new(
+                "-device",
+                format!(
+                    "vfio-pci,host={},id=resource{}_{}{}{}",
+                    pci, index, function, pci_address, multifunction
+                ),
             ));
         }
         result
     }
+
+    fn get_pci_slots(&mut self) -> Vec<PciSlot<'_>> {
+        vec![PciSlot::new("ich9-pcie-port-1", &mut self.pci_address)]
+    }
 }
 
 impl Default for Resource {
@@ -45,6 +74,7 @@ impl Default for Resource {
             parent: None,
             vf: None,
             multifunction: None,
+            pci_address: None,
         }
     }
 }
@@ -110,3 +140,38 @@ impl<'de> Deserialize<'de> for Resource {
         deserializer.deserialize_struct("Config", FIELDS, PoolDeviceVisitor)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_multifunction_resource() {
+        let resource: Resource = serde_yaml::from_str(
+            r#"{ id: nvidia12, tags: [ gpu, audio ], multifunction: true, pci: [ "0000:12:00.0", "0000:12:00.1" ] }"#,
+        )
+        .unwrap();
+
+        assert_eq!(resource.labels(), vec!["gpu", "audio", "nvidia12"]);
+        assert_eq!(
+            resource.get_qemu_args(1),
+            vec![
+                QemuArgument::new(
+                    "-device",
+                    "vfio-pci,host=0000:12:00.0,id=resource1_0,multifunction=on"
+                ),
+                QemuArgument::new("-device", "vfio-pci,host=0000:12:00.1,id=resource1_1"),
+            ]
+        );
+
+        let mut resource = resource.clone();
+        resource.get_pci_slots()[0].assign(PciAddress::new("ich9-pcie-port-2", 0, 0));
+        assert_eq!(
+            resource.get_qemu_args(1),
+            vec![
+                QemuArgument::new("-device", "vfio-pci,host=0000:12:00.0,id=resource1_0,bus=ich9-pcie-port-2,addr=0x0,multifunction=on"),
+                QemuArgument::new("-device", "vfio-pci,host=0000:12:00.1,id=resource1_1,bus=ich9-pcie-port-2,addr=0x0.1"),
+            ]
+        );
+    }
+}
@@ -1,12 +1,12 @@
 use crate::resource::resource::Resource;
+use crate::resource::tag_expression::TagExpression;
 use log::debug;
 use serde::Deserialize;
-use std::collections::HashMap;
 use std::fs::File;
 use std::io::Read;
 use crate::osal::OsalError;
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct ResourcePool {
     id: String,
     devices: Vec<Resource>,
@@ -33,31 +33,65 @@ impl ResourcePool {
         self.devices.iter().map(|device| device.get_id()).collect()
     }
 
-    pub fn claim_resource(
-        &self,
-        locked_resources: &HashMap<String, String>,
-    ) -> Result<String, OsalError> {
-        debug!("ResourcePool::claim_resource()");
-        for id in self.get_ids() {
-            if !locked_resources.contains_key(&id) {
-                debug!("claim_resource({})  found: {}", self.id, id);
-                return Ok(id);
-            }
-        }
-        debug!(
-            "ResourcePool::claim_resource() Resource {} not available.",
-            self.id.clone()
-        );
-        Err(OsalError::Busy(Some(self.id.clone())))
+    pub fn id(&self) -> &String {
+        &self.id
+    }
+
+    /// the devices that match the tag expression, in the order of the pool file
+    pub fn find_resources(&self, tags: Option<&TagExpression>) -> Vec<&Resource> {
+        debug!("ResourcePool[{}].find_resources({:?})", self.id, tags);
+        self.devices
+            .iter()
+            .filter(|device| {
+                tags.is_none_or(|tags| {
+                    let mut labels = device.labels();
+                    labels.push(self.id.as_str());
+                    tags.matches(&labels)
+                })
+            })
+            .collect()
     }
 
     pub fn get_resource(&self, id: &String) -> Option<&Resource> {
-        for resource in &self.devices {
-            if resource.get_id() == *id {
-                return Some(resource);
-            }
-        }
+        self.devices.iter().find(|resource| resource.get_id() == *id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pool() -> ResourcePool {
+        serde_yaml::from_str(
+            r#"
+            id: mixed
+            devices:
+              - { id: lan0, tags: [ lan ], parent: enp14s0, pci: [ "0000:0e:10.0" ] }
+              - { id: lan8, tags: [ lan ], parent: enp14s1, pci: [ "0000:0e:10.1" ] }
+              - { id: wlan0, tags: [ wlan ], pci: [ "0000:0f:00.0" ] }
+        "#,
+        )
+        .unwrap()
+    }
+
+    fn ids(resources: Vec<&Resource>) -> Vec<String> {
+        resources.iter().map(|resource| resource.get_id()).collect()
+    }
+
+    #[test]
+    fn test_find_all_resources() {
+        assert_eq!(
+            ids(pool().find_resources(None)),
+            vec!["lan0", "lan8", "wlan0"]
+        );
+    }
+
+    #[test]
+    fn test_find_resources_by_tag_expression() {
+        let tags: TagExpression = "lan && !enp14s1".parse().unwrap();
+        assert_eq!(ids(pool().find_resources(Some(&tags))), vec!["lan0"]);
 
-        None
+        let tags: TagExpression = "mixed && !lan".parse().unwrap();
+        assert_eq!(ids(pool().find_resources(Some(&tags))), vec!["wlan0"]);
     }
 }
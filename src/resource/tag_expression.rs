@@ -0,0 +1,113 @@
+use serde::Deserialize;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, PartialEq)]
+struct TagTerm {
+    negated: bool,
+    label: String,
+}
+
+/// selects resources by their labels (tags, id, parent and pool), written either as
+/// a list of terms `[ gpu, nvidia ]` or as an expression `lan && !enp14s1`;
+/// all terms must match, a term starting with '!' must not match
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(try_from = "TagExpressionSource")]
+pub struct TagExpression {
+    terms: Vec<TagTerm>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TagExpressionSource {
+    Expression(String),
+    List(Vec<String>),
+}
+
+impl TryFrom<TagExpressionSource> for TagExpression {
+    type Error = String;
+
+    fn try_from(source: TagExpressionSource) -> Result<Self, Self::Error> {
+        match source {
+            TagExpressionSource::Expression(expression) => expression.parse(),
+            TagExpressionSource::List(list) => list.join(" && ").parse(),
+        }
+    }
+}
+
+impl FromStr for TagExpression {
+    type Err = String;
+
+    fn from_str(expression: &str) -> Result<Self, Self::Err> {
+        let mut terms = vec![];
+        for term in expression.split("&&") {
+            let term = term.trim();
+            let (negated, label) = match term.strip_prefix('!') {
+                Some(label) => (true, label.trim()),
+                None => (false, term),
+            };
+            if label.is_empty() || label.contains(|c: char| c.is_whitespace() || c == '!') {
+                return Err(format!("invalid tag expression '{}'", expression));
+            }
+            terms.push(TagTerm {
+                negated,
+                label: label.to_string(),
+            });
+        }
+
+        Ok(Self { terms })
+    }
+}
+
+impl fmt::Display for TagExpression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let terms: Vec<String> = self
+            .terms
+            .iter()
+            .map(|term| match term.negated {
+                true => format!("!{}", term.label),
+                false => term.label.clone(),
+            })
+            .collect();
+        write!(f, "{}", terms.join(" && "))
+    }
+}
+
+impl TagExpression {
+    pub fn matches(&self, labels: &[&str]) -> bool {
+        self.terms
+            .iter()
+            .all(|term| labels.contains(&term.label.as_str()) != term.negated)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_list_and_expression_are_equivalent() {
+        let list: TagExpression = serde_yaml::from_str("[ lan, '!enp14s1' ]").unwrap();
+        let expression: TagExpression = serde_yaml::from_str("lan && !enp14s1").unwrap();
+
+        assert_eq!(list, expression);
+        assert_eq!(expression.to_string(), "lan && !enp14s1");
+    }
+
+    #[test]
+    fn test_matches() {
+        let expression: TagExpression = "lan && !enp14s1".parse().unwrap();
+
+        assert!(expression.matches(&["lan", "lan3", "enp14s0"]));
+        assert!(!expression.matches(&["lan", "lan11", "enp14s1"]));
+        assert!(!expression.matches(&["gpu", "nvidia12"]));
+    }
+
+    #[test]
+    fn test_invalid_expressions() {
+        assert!("lan && ".parse::<TagExpression>().is_err());
+        assert!("!".parse::<TagExpression>().is_err());
+        assert!("lan enp14s1".parse::<TagExpression>().is_err());
+        assert!(serde_yaml::from_str::<TagExpression>("[ gpu, '' ]").is_err());
+    }
+}
@@ -0,0 +1,336 @@
+use crate::config::Config;
+#[mockall_double::double]
+use crate::osal::Osal;
+use crate::osal::OsalError;
+use crate::resource::lock::Lock;
+use derive_getters::Getters;
+use serde::Serialize;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub enum OutputFormat {
+    #[default]
+    Text,
+    Yaml,
+    Json,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "text" => Ok(OutputFormat::Text),
+            "yaml" => Ok(OutputFormat::Yaml),
+            "json" => Ok(OutputFormat::Json),
+            other => Err(format!("unknown output format '{}'", other)),
+        }
+    }
+}
+
+/// The state of a single vm, as derived from its config file and lock file.
+#[derive(Serialize, Debug, Clone, PartialEq, Getters)]
+pub struct VmStatus {
+    name: String,
+    config: PathBuf,
+    running: bool,
+    pid: Option<u32>,
+    uptime: Option<u64>,
+    resources: Vec<String>,
+    qmp: Option<String>,
+    spice: Option<String>,
+}
+
+impl VmStatus {
+    pub fn new(name: &str, path: PathBuf, config: Option<&Config>) -> Self {
+        let lock = Lock::read(name).ok();
+        let pid = lock.as_ref().map(|lock| *lock.pid());
+        let running = lock.as_ref().is_some_and(|lock| lock.is_alive());
+
+        Self {
+            name: name.to_string(),
+            config: path,
+            running,
+            pid: pid.filter(|_| running),
+            uptime: pid
+                .filter(|_| running)
+                .and_then(Osal::get_process_uptime)
+                .map(|uptime| uptime.as_secs()),
+            resources: match &lock {
+                Some(lock) if running => lock.resources().clone(),
+                _ => vec![],
+            },
+            qmp: config.map(|config| config.general().qmp_socket()),
+            spice: config.and_then(|config| config.spice().as_ref().map(|spice| spice.uri())),
+        }
+    }
+
+    /// the status of a single vm, with its config file at path; a config that does not
+    /// parse only leaves out the details that come from it
+    pub fn read(name: &str, path: PathBuf) -> Result<Self, OsalError> {
+        let config = match Config::read_file(path.clone()) {
+            Ok(config) => Some(config),
+            Err(OsalError::Config(_)) => None,
+            Err(error) => return Err(error),
+        };
+        Ok(Self::new(name, path, config.as_ref()))
+    }
+
+    /// the status of every vm in the config search path
+    pub fn list() -> Vec<Self> {
+        Config::list()
+            .into_iter()
+            .map(|(name, path)| {
+                let config = Config::read_file(path.clone()).ok();
+                Self::new(&name, path, config.as_ref())
+            })
+            .collect()
+    }
+
+    fn state(&self) -> &str {
+        if self.running {
+            "running"
+        } else {
+            "stopped"
+        }
+    }
+
+    fn uptime_text(&self) -> String {
+        match self.uptime {
+            None => "-".to_string(),
+            Some(uptime) => format!(
+                "{}:{:02}:{:02}",
+                uptime / 3600,
+                (uptime / 60) % 60,
+                uptime % 60
+            ),
+        }
+    }
+
+    /// a detailed overview of a single vm
+    pub fn format(&self, format: OutputFormat) -> String {
+        match format {
+            OutputFormat::Yaml => serde_yaml::to_string(self).unwrap_or_default(),
+            OutputFormat::Json => serde_json::to_string_pretty(self).unwrap_or_default() + "\n",
+            OutputFormat::Text => {
+                let optional = |value: &Option<String>| value.clone().unwrap_or("-".to_string());
+                [
+                    format!("name:      {}", self.name),
+                    format!("config:    {}", self.config.display()),
+                    format!("state:     {}", self.state()),
+                    format!(
+                        "pid:       {}",
+                        optional(&self.pid.map(|pid| pid.to_string()))
+                    ),
+                    format!("uptime:    {}", self.uptime_text()),
+                    format!("resources: {}", self.resources.join(", ")),
+                    format!("qmp:       {}", optional(&self.qmp)),
+                    format!("spice:     {}", optional(&self.spice)),
+                ]
+                .join("\n")
+                    + "\n"
+            }
+        }
+    }
+
+    /// a one line per vm overview of several vms
+    pub fn format_list(list: &[Self], format: OutputFormat) -> String {
+        match format {
+            OutputFormat::Yaml => serde_yaml::to_string(list).unwrap_or_default(),
+            OutputFormat::Json => serde_json::to_string_pretty(list).unwrap_or_default() + "\n",
+            OutputFormat::Text => {
+                let mut result = format!(
+                    "{:<32} {:<8} {:>8} {:>10}  {}\n",
+                    "NAME", "STATE", "PID", "UPTIME", "RESOURCES"
+                );
+                for status in list {
+                    result += &format!(
+                        "{:<32} {:<8} {:>8} {:>10}  {}\n",
+                        status.name,
+                        status.state(),
+                        status
+                            .pid
+                            .map(|pid| pid.to_string())
+                            .unwrap_or("-".to_string()),
+                        status.uptime_text(),
+                        status.resources.join(",")
+                    );
+                }
+                result
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Spice;
+    use serial_test::serial;
+    use std::time::Duration;
+
+    fn running_status() -> VmStatus {
+        let read_file = Osal::read_file_context();
+        read_file.expect().returning(|_path: String| {
+            Ok(r#"
+                name: "wakiza"
+                pid: 12345
+                resources: [ "nvidia12", "lan3" ]
+            "#
+            .to_string())
+        });
+        let get_process_name = Osal::get_process_name_context();
+        get_process_name
+            .expect()
+            .returning(|_pid| Some("qemu-system-x86".to_string()));
+        let get_process_uptime = Osal::get_process_uptime_context();
+        get_process_uptime
+            .expect()
+            .returning(|_pid| Some(Duration::from_secs(3723)));
+
+        let config = Config::default().with_spice(Some(Spice::new_with_address_and_port(
+            "127.0.0.1".to_string(),
+            5900,
+        )));
+        VmStatus::new(
+            "wakiza",
+            PathBuf::from("/etc/ezkvm/wakiza.yaml"),
+            Some(&config),
+        )
+    }
+
+    #[test]
+    #[serial]
+    fn test_running_vm() {
+        let status = running_status();
+        assert_eq!(
+            status,
+            VmStatus {
+                name: "wakiza".to_string(),
+                config: PathBuf::from("/etc/ezkvm/wakiza.yaml"),
+                running: true,
+                pid: Some(12345),
+                uptime: Some(3723),
+                resources: vec!["nvidia12".to_string(), "lan3".to_string()],
+                qmp: Some("/var/ezkvm/anonymous.qmp".to_string()),
+                spice: Some("spice://127.0.0.1:5900".to_string()),
+            }
+        );
+
+        assert_eq!(
+            status.format(OutputFormat::Text),
+            r#"name:      wakiza
+config:    /etc/ezkvm/wakiza.yaml
+state:     running
+pid:       12345
+uptime:    1:02:03
+resources: nvidia12, lan3
+qmp:       /var/ezkvm/anonymous.qmp
+spice:     spice://127.0.0.1:5900
+"#
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn test_machine_readable_output() {
+        let status = running_status();
+
+        let yaml: serde_yaml::Value =
+            serde_yaml::from_str(&status.format(OutputFormat::Yaml)).unwrap();
+        assert_eq!(yaml["pid"], serde_yaml::Value::from(12345));
+        assert_eq!(yaml["running"], serde_yaml::Value::from(true));
+
+        let json: serde_json::Value =
+            serde_json::from_str(&VmStatus::format_list(&[status], OutputFormat::Json)).unwrap();
+        assert_eq!(json[0]["name"], "wakiza");
+        assert_eq!(json[0]["uptime"], 3723);
+        assert_eq!(json[0]["resources"][1], "lan3");
+    }
+
+    #[test]
+    #[serial]
+    fn test_stale_lock_is_reported_as_stopped() {
+        let read_file = Osal::read_file_context();
+        read_file.expect().returning(|_path: String| {
+            Ok(r#"
+                name: "wakiza"
+                pid: 12345
+                resources: [ "nvidia12" ]
+            "#
+            .to_string())
+        });
+        let get_process_name = Osal::get_process_name_context();
+        get_process_name.expect().returning(|_pid| None);
+
+        let status = VmStatus::new("wakiza", PathBuf::from("/etc/ezkvm/wakiza.yaml"), None);
+        assert!(!status.running);
+        assert_eq!(status.pid, None);
+        assert_eq!(status.resources, Vec::<String>::new());
+    }
+
+    #[test]
+    #[serial]
+    fn test_vm_without_lock_is_stopped() {
+        let read_file = Osal::read_file_context();
+        read_file
+            .expect()
+            .returning(|path: String| Err(OsalError::ReadError(Some(path))));
+
+        let status = VmStatus::new("wakiza", PathBuf::from("/etc/ezkvm/wakiza.yaml"), None);
+        assert_eq!(
+            VmStatus::format_list(&[status], OutputFormat::Text),
+            format!(
+                "{:<32} {:<8} {:>8} {:>10}  {}\n{:<32} {:<8} {:>8} {:>10}  {}\n",
+                "NAME", "STATE", "PID", "UPTIME", "RESOURCES", "wakiza", "stopped", "-", "-", ""
+            )
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn test_read() {
+        let read_config = Osal::read_file_context();
+        read_config
+            .expect()
+            .returning(|path: PathBuf| match path.to_str() {
+                Some("/home/user/vms/wakiza.yaml") => Ok("general: { name: wakiza }".to_string()),
+                _ => Err(OsalError::ReadError(Some(path.display().to_string()))),
+            });
+        let read_lock = Osal::read_file_context();
+        read_lock
+            .expect()
+            .returning(|path: String| Err(OsalError::ReadError(Some(path))));
+
+        let status = VmStatus::read("wakiza", PathBuf::from("/home/user/vms/wakiza.yaml")).unwrap();
+        assert_eq!(status.config, PathBuf::from("/home/user/vms/wakiza.yaml"));
+        assert_eq!(status.qmp, Some("/var/ezkvm/wakiza.qmp".to_string()));
+        assert!(!status.running);
+
+        // a config that does not parse only leaves out the details that come from it
+        read_config.checkpoint();
+        read_config
+            .expect()
+            .returning(|_path: PathBuf| Ok("gpu: { type: \"voodoo\" }".to_string()));
+        read_lock
+            .expect()
+            .returning(|path: String| Err(OsalError::ReadError(Some(path))));
+        let status = VmStatus::read("wakiza", PathBuf::from("/home/user/vms/wakiza.yaml")).unwrap();
+        assert_eq!(status.qmp, None);
+
+        read_config.checkpoint();
+        read_config
+            .expect()
+            .returning(|path: PathBuf| Err(OsalError::ReadError(Some(path.display().to_string()))));
+        read_lock
+            .expect()
+            .returning(|path: String| Err(OsalError::ReadError(Some(path))));
+        assert_eq!(
+            VmStatus::read("wakiza", PathBuf::from("/home/user/wakiza.yaml")),
+            Err(OsalError::ReadError(Some(
+                "/home/user/wakiza.yaml".to_string()
+            )))
+        );
+    }
+}